@@ -1,4 +1,5 @@
 DROP TABLE IF EXISTS tb_list;
 DROP TABLE IF EXISTS tb_market;
 DROP TABLE IF EXISTS tb_account;
-DROP TABLE IF EXISTS tb_position;
\ No newline at end of file
+DROP TABLE IF EXISTS tb_position;
+DROP TABLE IF EXISTS tb_indexer_progress;
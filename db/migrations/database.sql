@@ -69,6 +69,7 @@ CREATE TABLE IF NOT EXISTS tb_position (
     close_spread bigint NOT NULL DEFAULT 0,
     close_real_price bigint NOT NULL DEFAULT 0,
     profit bigint NOT NULL DEFAULT 0,
+    auto_open_price bigint NOT NULL DEFAULT 0,
     stop_surplus_price bigint NOT NULL DEFAULT 0,
     stop_loss_price bigint NOT NULL DEFAULT 0,
     create_time bigint NOT NULL DEFAULT 0,
@@ -86,4 +87,12 @@ CREATE INDEX idx_position_owner ON tb_position (account_id);
 CREATE INDEX idx_position_market ON tb_position (market_id);
 CREATE INDEX idx_position_status ON tb_position (status);
 CREATE INDEX idx_position_type ON tb_position (position_type);
-CREATE INDEX idx_position_direction ON tb_position (direction);
\ No newline at end of file
+CREATE INDEX idx_position_direction ON tb_position (direction);
+-- the column added after the table was created
+ALTER TABLE tb_position ADD COLUMN IF NOT EXISTS auto_open_price bigint NOT NULL DEFAULT 0;
+
+CREATE TABLE IF NOT EXISTS tb_indexer_progress (
+    name        varchar(64) CONSTRAINT indexer_name PRIMARY KEY,
+    checkpoint  bigint NOT NULL DEFAULT 0 CHECK (checkpoint >= 0),
+    update_time bigint NOT NULL DEFAULT 0
+);
@@ -1,3 +1,4 @@
 #!/bin/bash
-
-coins = sui client gas | awk -F ' ' '{print $1}'
\ No newline at end of file
+# Merge the dust SUI gas coins of the active address
+# and split the SUI into the gas pool size of the scale config.
+scale sui coin rebalance_gas
@@ -40,6 +40,12 @@ impl cfg for Config {
     fn get_price_config(&self) -> config::PriceConfig {
         config::PriceConfig::default()
     }
+    fn get_cron_config(&self) -> config::CronConfig {
+        config::CronConfig::default()
+    }
+    fn get_ws_config(&self) -> config::WsConfig {
+        config::WsConfig::default()
+    }
     fn get(&mut self) {
         println!("scale_config_file: {:?}", self.scale_config_file);
     }
@@ -1,7 +1,9 @@
 use crate::app::App;
 use crate::bot::{
-    influxdb, machine, price,
-    state::MoveCall,
+    influxdb, machine,
+    oracle::PriceOracle,
+    price,
+    state::{self, MoveCall},
     storage::{local, postgres},
     ws::{self, new_shared_dm_symbol_id},
 };
@@ -9,8 +11,8 @@ use crate::com::ClientError;
 use crate::config::{self, Config};
 use crate::http::router::HttpServer;
 use crate::sui::config::{Config as SuiConfig, Context as SuiContext};
-use crate::sui::subscribe;
 use crate::sui::tool::Tool;
+use crate::sui::{indexer::CheckpointIndexer, subscribe};
 use log::*;
 use std::net::ToSocketAddrs;
 use std::path::PathBuf;
@@ -21,9 +23,13 @@ use std::{
         Arc,
     },
 };
-use tokio::{runtime::Builder, runtime::Runtime, signal, sync::mpsc};
+use tokio::{runtime::Builder, runtime::Runtime, sync::mpsc, time::Duration};
 
-use crate::bot::{machine::Watch, ws::WsClient};
+use crate::bot::{
+    machine::{Liquidation, Watch},
+    price::PriceSub,
+    supervisor::{self, Supervisor},
+};
 
 use super::state::Storage;
 
@@ -32,8 +38,13 @@ pub struct Options {
     pub tasks: usize,
     pub socket_addr: Option<SocketAddr>,
     pub full_node: bool,
+    /// Push the pyth prices on chain
+    pub oracle: bool,
+    /// Walk the checkpoints instead of subscribing to the events, only on a full node
+    pub indexer: bool,
     pub gas_budget: u64,
     pub config_file: Option<PathBuf>,
+    pub shutdown_grace: Duration,
 }
 
 pub fn run(
@@ -54,12 +65,19 @@ pub fn run(
         Some(i) => i.to_string(),
         None => "127.0.0.1".to_string(),
     };
+    let shutdown_grace = match args.get_one::<u64>("grace") {
+        Some(g) => Duration::from_secs(*g),
+        None => Duration::from_secs(10),
+    };
     let mut opt = Options {
         tasks,
         socket_addr: None,
         full_node: *args.get_one::<bool>("full_node").unwrap_or(&false),
+        oracle: *args.get_one::<bool>("oracle").unwrap_or(&false),
+        indexer: *args.get_one::<bool>("indexer").unwrap_or(&false),
         gas_budget,
         config_file,
+        shutdown_grace,
     };
     let address = format!("{}:{}", ip, port);
     if port > 0 {
@@ -110,34 +128,66 @@ fn run_sui_app(runtime: Runtime, opt: Options) -> anyhow::Result<()> {
                 return;
             }
         }
-        let run = run_bot(opt, Arc::new(conf.clone()), Arc::new(tool)).await;
-        let (watch, ws_client) = match run {
+        // the liquidations are sent in parallel, each needs a gas coin of its own
+        if let Err(e) = tool.rebalance_gas().await {
+            error!("rebalance gas pool error: {}", e);
+        }
+        let run = run_bot(opt.clone(), Arc::new(conf.clone()), Arc::new(tool)).await;
+        let (watch, liquidation, price_sub, oracle, http_server, pg) = match run {
             Ok(r) => r,
             Err(e) => {
                 error!("run bot error: {}", e);
                 return;
             }
         };
+        let indexer = opt.indexer || conf.indexer_config.enable;
         let ctx = SuiContext::new(conf).await.expect("sui context init error");
-        if let Err(e) = subscribe::sync_all_objects(ctx.clone(), watch.watch_tx.clone()).await {
-            error!("sync all orders error: {}", e);
-        }
+        // the sender is kept until the shutdown, the event subscriber stops receiving on close
         let (sync_tx, sync_rx) = mpsc::unbounded_channel();
-        // // start event task
-        let event_task =
-            subscribe::EventSubscriber::new(ctx.clone(), watch.watch_tx.clone(), sync_rx).await;
+        let event_tasks = match pg {
+            // the objects are loaded from postgres, the indexer continues after its last checkpoint,
+            // or syncs the objects first without progress
+            Some(pg) if indexer => {
+                CheckpointIndexer::new(ctx.clone(), pg, watch.ssm.clone(), watch.watch_tx.clone())
+                    .into_tasks()
+            }
+            _ => {
+                if indexer {
+                    warn!("the checkpoint indexer needs a full node, subscribe to the events");
+                }
+                if let Err(e) =
+                    subscribe::sync_all_objects(ctx.clone(), watch.watch_tx.clone()).await
+                {
+                    error!("sync all orders error: {}", e);
+                }
+                // // start event task
+                subscribe::EventSubscriber::new(ctx.clone(), watch.watch_tx.clone(), sync_rx)
+                    .await
+                    .into_tasks()
+            }
+        };
         info!("bot start success");
-        signal::ctrl_c().await.expect("failed to listen for event");
-        info!("Ctrl-C received, shutting down");
-        // event_task.shutdown().await;
-        // if let Some(http_srv) = http_server {
-        //     http_srv.shutdown().await;
-        // }
-        // ws_client.shutdown().await;
-        // liquidation.shutdown().await;
-        // if let Some(oracle) = oracle {
-        //     oracle.shutdown().await;
-        // }
+        if let Err(e) = supervisor::wait_for_signal().await {
+            error!("listen for shutdown signal error: {}", e);
+        }
+        // stop the producers first, the watch task drains the received messages
+        // and flushes the storage at last.
+        let mut sv = Supervisor::new(opt.shutdown_grace);
+        if let Some(http_server) = http_server {
+            sv.add_stage("http", http_server.into_tasks());
+        }
+        if let Some(oracle) = oracle {
+            sv.add_stage("oracle", oracle.into_tasks());
+        }
+        sv.add_stage("event", event_tasks);
+        sv.add_stage("price", price_sub.into_tasks());
+        sv.add_stage("liquidation", liquidation.into_tasks());
+        sv.add_stage("watch", watch.into_tasks());
+        let not_stopped = sv.shutdown().await;
+        drop(sync_tx);
+        if !not_stopped.is_empty() {
+            error!("bot shutdown with tasks not stopped: {:?}", not_stopped);
+        }
     });
     return Ok(());
 }
@@ -150,7 +200,14 @@ async fn run_bot<C, CF>(
     opt: Options,
     conf: Arc<CF>,
     call: Arc<C>,
-) -> anyhow::Result<(Watch, WsClient)>
+) -> anyhow::Result<(
+    Watch,
+    Liquidation,
+    PriceSub,
+    Option<PriceOracle>,
+    Option<HttpServer>,
+    Option<Arc<postgres::PG>>,
+)>
 where
     C: MoveCall + Send + Sync + 'static,
     CF: Config + Send + Sync + 'static,
@@ -161,16 +218,28 @@ where
     // try load local state data
     let ssm: machine::SharedStateMap = Arc::new(state_mp.clone());
     let (event_ws_tx, event_ws_rx) = ws::new_event_channel(100);
+    let (event_update_tx, event_update_rx) = state::new_event_update_channel();
     let influxdb = influxdb::Influxdb::new(conf.get_influxdb_config());
     let watch: Watch;
-    let ws_client: WsClient;
+    let price_sub: PriceSub;
+    // only the full node keeps the position history
+    let mut pg: Option<Arc<postgres::PG>> = None;
     if opt.full_node {
         let db = Arc::new(postgres::new(conf.get_sql_db_config()).await?);
-        watch = machine::Watch::new(ssm.clone(), db.clone(), event_ws_tx.clone(), true).await;
-        ws_client = price::sub_price(
+        pg = Some(db.clone());
+        watch = machine::Watch::new(
+            ssm.clone(),
+            db.clone(),
+            event_ws_tx.clone(),
+            event_update_tx,
+            opt.socket_addr.is_some(),
+        )
+        .await;
+        price_sub = price::sub_price(
+            ssm.clone(),
             watch.watch_tx.clone(),
-            conf.get_price_config().ws_url.clone(),
-            influxdb,
+            conf.get_price_config(),
+            influxdb.clone(),
             sds.clone(),
             opt.full_node,
         )
@@ -178,16 +247,61 @@ where
         db.load_all(watch.watch_tx.clone()).await?;
     } else {
         let db = Arc::new(local::Local::new(conf.get_storage_path())?);
-        watch = machine::Watch::new(ssm.clone(), db.clone(), event_ws_tx.clone(), false).await;
-        ws_client = price::sub_price(
+        watch = machine::Watch::new(
+            ssm.clone(),
+            db.clone(),
+            event_ws_tx.clone(),
+            event_update_tx,
+            opt.socket_addr.is_some(),
+        )
+        .await;
+        price_sub = price::sub_price(
+            ssm.clone(),
             watch.watch_tx.clone(),
-            conf.get_price_config().ws_url.clone(),
-            influxdb,
+            conf.get_price_config(),
+            influxdb.clone(),
             sds.clone(),
             opt.full_node,
         )
         .await?;
         db.load_all(watch.watch_tx.clone()).await?;
     }
-    Ok((watch, ws_client))
+    let oracle = if opt.oracle {
+        Some(PriceOracle::new(
+            ssm.clone(),
+            conf.get_price_config(),
+            call.clone(),
+        ))
+    } else {
+        None
+    };
+    let liquidation = machine::Liquidation::new(
+        ssm.clone(),
+        opt.tasks,
+        event_ws_tx,
+        event_update_rx,
+        opt.socket_addr.is_some(),
+        conf.get_cron_config(),
+        conf.get_ws_config(),
+        call,
+    )
+    .await?;
+    let http_server = match opt.socket_addr {
+        Some(addr) => Some(
+            HttpServer::new(
+                &addr,
+                ssm.clone(),
+                Arc::new(influxdb),
+                event_ws_rx,
+                pg.clone(),
+                liquidation.get_job_registry(),
+            )
+            .await,
+        ),
+        None => {
+            info!("web server is disabled");
+            None
+        }
+    };
+    Ok((watch, liquidation, price_sub, oracle, http_server, pg))
 }
@@ -1,14 +1,65 @@
+use crate::com::Task;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
 use log::*;
+use serde::Serialize;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use tokio::sync::mpsc;
 use tokio_cron_scheduler::{Job, JobScheduler};
+
+/// Run state of a scheduled job.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct JobStatus {
+    pub name: String,
+    pub cron: String,
+    pub running: bool,
+    pub last_run_time: Option<DateTime<Utc>>,
+    pub last_finish_time: Option<DateTime<Utc>>,
+    pub next_run_time: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub success_count: u64,
+    pub failure_count: u64,
+    /// The times the job was skipped because the previous run was still executing
+    pub skip_count: u64,
+}
+// key is job name
+pub type DmJobStatus = DashMap<String, JobStatus>;
+
+// Clears the running state of a job when its run ends, a run that panicked is a failure.
+struct RunGuard {
+    running: Arc<AtomicBool>,
+    jobs: Arc<DmJobStatus>,
+    name: String,
+}
+impl Drop for RunGuard {
+    fn drop(&mut self) {
+        if let Some(mut s) = self.jobs.get_mut(&self.name) {
+            if s.running {
+                error!("Cron job {} panicked", self.name);
+                s.running = false;
+                s.last_finish_time = Some(Utc::now());
+                s.failure_count += 1;
+                s.last_error = Some("job panicked".to_string());
+            }
+        }
+        self.running.store(false, Ordering::Release);
+    }
+}
+
 pub struct Cron {
     scheduler: JobScheduler,
+    jobs: Arc<DmJobStatus>,
 }
 impl Cron {
     pub async fn new() -> anyhow::Result<Self> {
         let sched = JobScheduler::new().await?;
         sched.start().await?;
-        Ok(Self { scheduler: sched })
+        Ok(Self {
+            scheduler: sched,
+            jobs: Arc::new(DashMap::new()),
+        })
     }
 
     pub async fn shutdown(mut self) -> anyhow::Result<()> {
@@ -31,4 +82,116 @@ impl Cron {
         self.scheduler.add(job).await?;
         Ok(rx)
     }
+
+    /// Register a named job, the run state is tracked in the job registry.
+    /// A tick is skipped if the previous run of the job is still executing.
+    pub async fn add_named_job<F, Fut>(
+        &self,
+        name: &str,
+        cron_str: &str,
+        f: F,
+    ) -> anyhow::Result<()>
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let f = Arc::new(f);
+        let running = Arc::new(AtomicBool::new(false));
+        let jobs = self.jobs.clone();
+        let job_name = name.to_string();
+        let job = Job::new_async(cron_str, move |uuid, mut l| {
+            let f = f.clone();
+            let running = running.clone();
+            let jobs = jobs.clone();
+            let name = job_name.clone();
+            Box::pin(async move {
+                let next_run_time = l.next_tick_for_job(uuid).await.unwrap_or(None);
+                if running
+                    .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+                    .is_err()
+                {
+                    warn!("Cron job {} is still running, skip this tick", name);
+                    if let Some(mut s) = jobs.get_mut(&name) {
+                        s.skip_count += 1;
+                        s.next_run_time = next_run_time;
+                    }
+                    return;
+                }
+                let _guard = RunGuard {
+                    running: running.clone(),
+                    jobs: jobs.clone(),
+                    name: name.clone(),
+                };
+                info!("Running cron job: {}", name);
+                if let Some(mut s) = jobs.get_mut(&name) {
+                    s.running = true;
+                    s.last_run_time = Some(Utc::now());
+                    s.next_run_time = next_run_time;
+                }
+                let rs = f().await;
+                if let Some(mut s) = jobs.get_mut(&name) {
+                    s.running = false;
+                    s.last_finish_time = Some(Utc::now());
+                    match rs {
+                        Ok(_) => {
+                            s.success_count += 1;
+                            s.last_error = None;
+                        }
+                        Err(e) => {
+                            error!("Cron job {} error: {}", name, e);
+                            s.failure_count += 1;
+                            s.last_error = Some(e.to_string());
+                        }
+                    }
+                }
+            })
+        })?;
+        self.jobs.insert(
+            name.to_string(),
+            JobStatus {
+                name: name.to_string(),
+                cron: cron_str.to_string(),
+                ..Default::default()
+            },
+        );
+        let mut scheduler = self.scheduler.clone();
+        let uuid = scheduler.add(job).await?;
+        let next_run_time = scheduler.next_tick_for_job(uuid).await.unwrap_or(None);
+        if let Some(mut s) = self.jobs.get_mut(name) {
+            s.next_run_time = next_run_time;
+        }
+        info!(
+            "Add cron job: {}, cron: {}, next run time: {:?}",
+            name, cron_str, next_run_time
+        );
+        Ok(())
+    }
+
+    /// Turn the scheduler into a task, the scheduler is shut down on the stop signal.
+    pub fn into_task(self) -> Task {
+        let (shutdown_tx, shutdown_rx) = Task::new_shutdown_channel();
+        Task::new(
+            "cron",
+            shutdown_tx,
+            tokio::spawn(async move {
+                let _ = shutdown_rx.await;
+                self.shutdown().await
+            }),
+        )
+    }
+
+    pub fn get_jobs(&self) -> Vec<JobStatus> {
+        list_jobs(&self.jobs)
+    }
+
+    /// The run states of the jobs, shared with the readers.
+    pub fn get_registry(&self) -> Arc<DmJobStatus> {
+        self.jobs.clone()
+    }
+}
+
+pub fn list_jobs(jobs: &DmJobStatus) -> Vec<JobStatus> {
+    let mut rs: Vec<JobStatus> = jobs.iter().map(|v| v.value().clone()).collect();
+    rs.sort_by(|a, b| a.name.cmp(&b.name));
+    rs
 }
@@ -0,0 +1,266 @@
+// Sanity checks of the price ticks before they reach the state map.
+// A symbol whose tick is rejected is unhealthy until a tick of it passes the checks,
+// the liquidations of its market are paused meanwhile. A symbol without a tick for
+// the max age is unhealthy too, found by the periodic sweep.
+use crate::bot::state::OrgPrice;
+use crate::config::{PriceConfig, PriceGuardConfig};
+use chrono::Utc;
+use dashmap::DashMap;
+use log::*;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PriceRejection {
+    /// The age of the price in seconds
+    Stale(i64),
+    /// The confidence interval rate of the price
+    Confidence(f64),
+    /// The deviation rate from the ema price
+    Deviation(f64),
+    NonPositive,
+}
+
+impl fmt::Display for PriceRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stale(age) => write!(f, "price is {}s old", age),
+            Self::Confidence(rate) => write!(f, "confidence interval is {} of the price", rate),
+            Self::Deviation(rate) => write!(f, "price is {} away from the ema price", rate),
+            Self::NonPositive => write!(f, "price is not positive"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PriceHealth {
+    pub reason: PriceRejection,
+    /// The time the symbol became unhealthy
+    pub since: i64,
+    /// The rejected ticks since then
+    pub rejected: u64,
+}
+
+// key is symbol, only the unhealthy symbols are kept
+pub type DmPriceHealth = DashMap<String, PriceHealth>;
+
+pub struct PriceGuard {
+    config: PriceGuardConfig,
+    // key is symbol, value is the max confidence rate of the symbol
+    max_conf_rate: HashMap<String, f64>,
+    // key is symbol, value is the update time of its last accepted live tick
+    last_update: HashMap<String, i64>,
+}
+
+impl PriceGuard {
+    pub fn new(price_config: &PriceConfig) -> Self {
+        let mut max_conf_rate = HashMap::new();
+        for s in price_config.pyth_symbol.iter() {
+            if let Some(rate) = s.max_conf_rate {
+                max_conf_rate.insert(s.symbol.clone(), rate);
+            }
+        }
+        Self {
+            config: price_config.guard.clone(),
+            max_conf_rate,
+            last_update: HashMap::new(),
+        }
+    }
+
+    /// Check the tick, the age is not checked for the replayed ticks.
+    pub fn check(&self, price: &OrgPrice, now: i64, check_age: bool) -> Result<(), PriceRejection> {
+        let (value, conf, ema_price) = (price.price.raw(), price.conf.raw(), price.ema_price.raw());
+        if value <= 0 {
+            return Err(PriceRejection::NonPositive);
+        }
+        let age = now - price.update_time;
+        if check_age && self.config.max_age_secs > 0 && age > self.config.max_age_secs as i64 {
+            return Err(PriceRejection::Stale(age));
+        }
+        let max_conf_rate = self
+            .max_conf_rate
+            .get(&price.symbol)
+            .copied()
+            .unwrap_or(self.config.max_conf_rate);
+        let conf_rate = conf as f64 / value as f64;
+        if max_conf_rate > 0.0 && conf_rate > max_conf_rate {
+            return Err(PriceRejection::Confidence(conf_rate));
+        }
+        if self.config.max_deviation_rate > 0.0 && ema_price > 0 {
+            let deviation = (value - ema_price).abs() as f64 / ema_price as f64;
+            if deviation > self.config.max_deviation_rate {
+                return Err(PriceRejection::Deviation(deviation));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the tick and update the health of its symbol, returns whether the tick is accepted.
+    pub fn guard(&mut self, health: &DmPriceHealth, price: &OrgPrice, check_age: bool) -> bool {
+        let now = Utc::now().timestamp();
+        match self.check(price, now, check_age) {
+            Ok(_) => {
+                // a replayed symbol is not swept
+                if check_age {
+                    self.last_update
+                        .insert(price.symbol.clone(), price.update_time);
+                } else {
+                    self.last_update.remove(&price.symbol);
+                }
+                if let Some((_, h)) = health.remove(&price.symbol) {
+                    info!(
+                        "price of {} is healthy again, {} ticks rejected since {}",
+                        price.symbol, h.rejected, h.since
+                    );
+                }
+                true
+            }
+            Err(reason) => {
+                match health.get_mut(&price.symbol) {
+                    Some(mut h) => {
+                        h.rejected += 1;
+                        h.reason = reason;
+                    }
+                    None => {
+                        warn!(
+                            "price of {} is unhealthy: {}, pause its liquidations",
+                            price.symbol, reason
+                        );
+                        health.insert(
+                            price.symbol.clone(),
+                            PriceHealth {
+                                reason,
+                                since: now,
+                                rejected: 1,
+                            },
+                        );
+                    }
+                }
+                false
+            }
+        }
+    }
+    /// Mark the symbols without a live tick for the max age unhealthy,
+    /// a symbol is healthy again with its next accepted tick.
+    pub fn sweep(&self, health: &DmPriceHealth, now: i64) {
+        if self.config.max_age_secs == 0 {
+            return;
+        }
+        for (symbol, update_time) in self.last_update.iter() {
+            let age = now - update_time;
+            if age <= self.config.max_age_secs as i64 || health.contains_key(symbol) {
+                continue;
+            }
+            warn!(
+                "price of {} has no tick for {}s, pause its liquidations",
+                symbol, age
+            );
+            health.insert(
+                symbol.clone(),
+                PriceHealth {
+                    reason: PriceRejection::Stale(age),
+                    since: now,
+                    rejected: 0,
+                },
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::state::FixedPrice;
+
+    fn tick(price: i64, conf: i64, ema_price: i64, update_time: i64) -> OrgPrice {
+        OrgPrice {
+            price: FixedPrice::from_raw(price),
+            update_time,
+            symbol: "BTC/USD".to_string(),
+            conf: FixedPrice::from_raw(conf),
+            ema_price: FixedPrice::from_raw(ema_price),
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let guard = PriceGuard::new(&PriceConfig::default());
+        let now = 10_000;
+        // price, conf, ema price, age, checked age, result
+        let cases = [
+            (1000, 10, 1000, 0, true, Ok(())),
+            (0, 0, 0, 0, true, Err(PriceRejection::NonPositive)),
+            (-5, 0, 0, 0, true, Err(PriceRejection::NonPositive)),
+            (1000, 10, 1000, 60, true, Ok(())),
+            (1000, 10, 1000, 61, true, Err(PriceRejection::Stale(61))),
+            // the replayed prices are old
+            (1000, 10, 1000, 3600, false, Ok(())),
+            (1000, 20, 1000, 0, true, Ok(())),
+            (
+                1000,
+                30,
+                1000,
+                0,
+                true,
+                Err(PriceRejection::Confidence(0.03)),
+            ),
+            // no ema price from the source
+            (1000, 10, 0, 0, true, Ok(())),
+            (1100, 10, 1000, 0, true, Ok(())),
+            (1200, 10, 1000, 0, true, Err(PriceRejection::Deviation(0.2))),
+            (800, 10, 1000, 0, true, Err(PriceRejection::Deviation(0.2))),
+        ];
+        for (price, conf, ema_price, age, check_age, expected) in cases {
+            let p = tick(price, conf, ema_price, now - age);
+            assert_eq!(
+                guard.check(&p, now, check_age),
+                expected,
+                "price {} conf {} ema {} age {}",
+                price,
+                conf,
+                ema_price,
+                age
+            );
+        }
+    }
+
+    #[test]
+    fn test_guard_recovery() {
+        let mut guard = PriceGuard::new(&PriceConfig::default());
+        let health = DmPriceHealth::new();
+        let now = Utc::now().timestamp();
+        assert!(guard.guard(&health, &tick(1000, 10, 1000, now), true));
+        assert!(health.is_empty());
+        assert!(!guard.guard(&health, &tick(1000, 50, 1000, now), true));
+        assert!(!guard.guard(&health, &tick(1000, 10, 1000, now - 3600), true));
+        {
+            let h = health.get("BTC/USD").unwrap();
+            assert_eq!(h.rejected, 2);
+            assert!(matches!(h.reason, PriceRejection::Stale(_)));
+        }
+        assert!(guard.guard(&health, &tick(1000, 10, 1000, now), true));
+        assert!(health.is_empty());
+    }
+
+    #[test]
+    fn test_sweep_stale_symbol() {
+        let mut guard = PriceGuard::new(&PriceConfig::default());
+        guard.last_update.insert("BTC/USD".to_string(), 1000);
+        guard.last_update.insert("ETH/USD".to_string(), 1050);
+        let health = DmPriceHealth::new();
+        guard.sweep(&health, 1060);
+        assert!(health.is_empty());
+        guard.sweep(&health, 1070);
+        assert_eq!(
+            health.get("BTC/USD").map(|h| h.reason.clone()),
+            Some(PriceRejection::Stale(70))
+        );
+        assert!(!health.contains_key("ETH/USD"));
+        // the first sweep is kept
+        guard.sweep(&health, 1200);
+        assert_eq!(health.get("BTC/USD").unwrap().since, 1070);
+        assert_eq!(health.len(), 2);
+    }
+}
@@ -0,0 +1,137 @@
+// Live klines (candlesticks) aggregated in memory from the price ticks,
+// so the charts get the forming kline without querying the price history.
+use crate::bot::state::OrgPrice;
+use crate::com::ClientError;
+use chrono::{DateTime, Datelike, Months, NaiveDate, TimeZone, Utc};
+use log::*;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// The kline ranges, the same as the ranges of the price history.
+pub const KLINE_RANGES: [&str; 8] = ["1m", "5m", "15m", "1H", "1D", "1W", "1M", "1Y"];
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Kline {
+    pub symbol: String,
+    pub range: String,
+    pub start_time: DateTime<Utc>,
+    pub stop_time: DateTime<Utc>,
+    pub open: i64,
+    pub close: i64,
+    pub low: i64,
+    pub high: i64,
+    /// The window of the kline is over, the kline will not change anymore
+    pub closed: bool,
+}
+
+impl Kline {
+    fn new(
+        price: &OrgPrice,
+        range: &str,
+        start_time: DateTime<Utc>,
+        stop_time: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            symbol: price.symbol.clone(),
+            range: range.to_string(),
+            start_time,
+            stop_time,
+            open: price.price.raw(),
+            close: price.price.raw(),
+            low: price.price.raw(),
+            high: price.price.raw(),
+            closed: false,
+        }
+    }
+
+    fn update(&mut self, price: i64) {
+        self.close = price;
+        self.low = self.low.min(price);
+        self.high = self.high.max(price);
+    }
+}
+
+fn to_date_time(ts: i64) -> anyhow::Result<DateTime<Utc>> {
+    Utc.timestamp_opt(ts, 0)
+        .single()
+        .ok_or_else(|| ClientError::InvalidRange.into())
+}
+
+/// Get the window of the range the timestamp (in seconds) falls in.
+/// The fixed length windows are aligned to the unix epoch, as the influxdb windows are.
+pub fn get_kline_window(range: &str, ts: i64) -> anyhow::Result<(DateTime<Utc>, DateTime<Utc>)> {
+    let secs = match range {
+        "1m" => 60,
+        "5m" => 300,
+        "15m" => 900,
+        "1H" => 3600,
+        "1D" => 86400,
+        "1W" => 604800,
+        "1M" | "1Y" => {
+            let t = to_date_time(ts)?;
+            let (start, months) = if range == "1M" {
+                (NaiveDate::from_ymd_opt(t.year(), t.month(), 1), 1)
+            } else {
+                (NaiveDate::from_ymd_opt(t.year(), 1, 1), 12)
+            };
+            let start = start
+                .and_then(|d| d.and_hms_opt(0, 0, 0))
+                .ok_or(ClientError::InvalidRange)?;
+            let stop = start
+                .checked_add_months(Months::new(months))
+                .ok_or(ClientError::InvalidRange)?;
+            return Ok((Utc.from_utc_datetime(&start), Utc.from_utc_datetime(&stop)));
+        }
+        _ => return Err(ClientError::InvalidRange.into()),
+    };
+    let start = ts - ts.rem_euclid(secs);
+    Ok((to_date_time(start)?, to_date_time(start + secs)?))
+}
+
+#[derive(Debug, Default)]
+pub struct KlineAggregator {
+    // key is (symbol, range), value is the forming kline
+    klines: HashMap<(String, &'static str), Kline>,
+}
+
+impl KlineAggregator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a price tick to the klines of all the ranges of its symbol.
+    /// Returns the changed klines, a kline closed by the tick comes before the new one of its range.
+    pub fn update(&mut self, price: &OrgPrice) -> Vec<Kline> {
+        let mut rs = Vec::with_capacity(KLINE_RANGES.len());
+        for range in KLINE_RANGES {
+            let (start_time, stop_time) = match get_kline_window(range, price.update_time) {
+                Ok(w) => w,
+                Err(e) => {
+                    error!("get kline window error: {}, price: {:?}", e, price);
+                    continue;
+                }
+            };
+            let key = (price.symbol.clone(), range);
+            match self.klines.get_mut(&key) {
+                Some(k) if k.start_time == start_time => {
+                    k.update(price.price.raw());
+                    rs.push(k.clone());
+                    continue;
+                }
+                Some(k) if k.start_time > start_time => {
+                    // a late tick of a closed window
+                    continue;
+                }
+                Some(k) => {
+                    k.closed = true;
+                    rs.push(k.clone());
+                }
+                None => {}
+            }
+            let k = Kline::new(price, range, start_time, stop_time);
+            rs.push(k.clone());
+            self.klines.insert(key, k);
+        }
+        rs
+    }
+}
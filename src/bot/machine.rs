@@ -1,24 +1,28 @@
-use crate::bot::cron::Cron;
+use crate::bot::cron::{Cron, DmJobStatus, JobStatus};
+use crate::bot::guard::DmPriceHealth;
+use crate::bot::pending::{self, DsLimitOpening, PendingBook};
 use crate::bot::state::{
     Account, Address, Direction, Event, EventUpdate, EventUpdateRx, EventUpdateTx, List, Market,
-    Message, MessageReceiver, MessageSender, MoveCall, Position, PositionStatus, PositionType,
-    Price, State, Storage, BURST_RATE,
+    Message, MessageReceiver, MessageSender, MoveCall, OrgPrice, Position, PositionParams,
+    PositionStatus, PositionType, Price, State, Storage, BURST_RATE,
 };
 use crate::bot::storage::local::{self, Local};
+use crate::bot::trigger::{self, DsAutoClosing, TriggerIndex};
 use crate::bot::ws::{
     AccountDynamicData, PositionDynamicData, SpreadData, SupportedSymbol, WsServerState,
     WsSrvMessage, WsWatchTx,
 };
 use crate::com::{self, Task, TaskStopRx};
+use crate::config::{CronConfig, WsConfig};
 use chrono::Utc;
-use dashmap::{DashMap, DashSet};
+use dashmap::{mapref::entry::Entry, DashMap, DashSet};
 use log::*;
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::{Arc, RwLock};
 use tokio::{
     sync::{mpsc, oneshot},
-    time::{self as tokio_time, Duration as TokioDuration},
+    time::{self as tokio_time, Duration as TokioDuration, Instant},
 };
 
 use super::state;
@@ -46,6 +50,7 @@ pub struct StateMap {
     pub ws_state: WsServerState,
     pub account_dynamic_data: DmAccountDynamicData,
     pub position_dynamic_data: DmPositionDynamicData,
+    pub price_health: DmPriceHealth,
 }
 impl StateMap {
     pub fn new(supported_symbol: SupportedSymbol) -> anyhow::Result<Self> {
@@ -63,12 +68,31 @@ impl StateMap {
             ws_state: WsServerState::new(supported_symbol),
             account_dynamic_data: DashMap::new(),
             position_dynamic_data: DashMap::new(),
+            price_health: DashMap::new(),
         })
     }
+
+    /// The last known state of the list, market, account or position of the id.
+    pub fn get_state(&self, id: &Address) -> Option<State> {
+        if let Some(list) = self.list.get(id) {
+            return Some(State::List(list.clone()));
+        }
+        if let Some(account) = self.account.get(id) {
+            return Some(State::Account(account.clone()));
+        }
+        if let Some(market) = self.market.iter().find(|m| m.id == *id) {
+            return Some(State::Market(market.clone()));
+        }
+        self.position
+            .iter()
+            .find_map(|p| p.get(id).map(|p| State::Position(p.clone())))
+    }
 }
 pub type SharedStateMap = Arc<StateMap>;
 pub struct Watch {
     pub watch_tx: MessageSender,
+    /// The states kept by the watch task
+    pub ssm: SharedStateMap,
     task: Task,
 }
 impl Watch {
@@ -76,6 +100,7 @@ impl Watch {
         ssm: SharedStateMap,
         storage: Arc<S>,
         event_ws_tx: WsWatchTx,
+        event_update_tx: EventUpdateTx,
         is_write_ws_event: bool,
     ) -> Self
     where
@@ -85,6 +110,7 @@ impl Watch {
         let (shutdown_tx, shutdown_rx) = Task::new_shutdown_channel();
         Self {
             watch_tx,
+            ssm: ssm.clone(),
             task: Task::new(
                 "watch",
                 shutdown_tx,
@@ -94,6 +120,7 @@ impl Watch {
                     watch_rx,
                     shutdown_rx,
                     event_ws_tx,
+                    event_update_tx,
                     is_write_ws_event,
                 )),
             ),
@@ -102,6 +129,10 @@ impl Watch {
     pub async fn shutdown(self) {
         self.task.shutdown().await;
     }
+
+    pub fn into_tasks(self) -> Vec<Task> {
+        vec![self.task]
+    }
 }
 
 async fn watch_message<S>(
@@ -110,6 +141,7 @@ async fn watch_message<S>(
     mut watch_rx: MessageReceiver,
     mut shutdown_rx: TaskStopRx,
     event_ws_tx: WsWatchTx,
+    event_update_tx: EventUpdateTx,
     is_write_spread: bool,
 ) -> anyhow::Result<()>
 where
@@ -127,13 +159,20 @@ where
                         error!("shutdown channel error: {}", e);
                     }
                 }
+                // handle the messages already received, so their states are saved before exit
+                while let Ok(msg) = watch_rx.try_recv() {
+                    handle_message(ssm.clone(),storage.clone(), msg,event_ws_tx.clone(),&event_update_tx,is_write_spread).await;
+                }
+                if let Err(e) = storage.flush().await {
+                    error!("flush storage error: {}", e);
+                }
                 break;
             }
             r = watch_rx.recv()=> {
                 match r {
                     Some(msg)=>{
                         // debug!("data channel got data : {:?}",msg);
-                        handle_message(ssm.clone(),storage.clone(), msg,event_ws_tx.clone(),is_write_spread).await;
+                        handle_message(ssm.clone(),storage.clone(), msg,event_ws_tx.clone(),&event_update_tx,is_write_spread).await;
                     }
                     None=>{
                         debug!("data channel got none : {:?}",r);
@@ -150,6 +189,7 @@ async fn handle_message<S>(
     storage: Arc<S>,
     msg: Message,
     event_ws_tx: WsWatchTx,
+    event_update_tx: &EventUpdateTx,
     is_write_ws_event: bool,
 ) where
     S: Storage + Send + Sync + 'static,
@@ -175,17 +215,35 @@ async fn handle_message<S>(
         State::Account(account) => {
             if msg.event == Event::Deleted {
                 ssm.account.remove(&account.id);
+                ssm.account_dynamic_data.remove(&account.id);
+                send_event_update(
+                    event_update_tx,
+                    EventUpdate::AccountDeleted(account.id.copy()),
+                );
             } else {
                 ssm.account.insert(account.id.copy(), account.clone());
+                send_event_update(event_update_tx, EventUpdate::AccountUpdate(account.clone()));
             }
             if let Err(e) = storage.save_one(State::Account(account)).await {
                 error!("save account error: {}", e);
             }
         }
         State::Position(position) => {
+            let was_normal = match ssm.position.get(&position.account_id) {
+                Some(p) => p
+                    .get(&position.id)
+                    .map_or(false, |v| v.status == PositionStatus::Normal),
+                None => false,
+            };
+            let is_normal =
+                msg.event != Event::Deleted && position.status == PositionStatus::Normal;
+            // the states loaded from the storage are not opened or closed right now
+            if is_write_ws_event && msg.event != Event::None && was_normal != is_normal {
+                send_position_status_event(&ssm, &event_ws_tx, &position, is_normal);
+            }
             if msg.event == Event::Deleted
-                || position.status != PositionStatus::Normal
-                || position.status != PositionStatus::Pending
+                || (position.status != PositionStatus::Normal
+                    && position.status != PositionStatus::Pending)
             {
                 match ssm.position.get(&position.account_id) {
                     Some(p) => {
@@ -207,6 +265,10 @@ async fn handle_message<S>(
                     }
                 };
             }
+            send_event_update(
+                event_update_tx,
+                EventUpdate::PositionUpdate(position.clone()),
+            );
             if let Err(e) = storage.save_one(State::Position(position)).await {
                 error!("save position error: {}", e);
             }
@@ -214,8 +276,15 @@ async fn handle_message<S>(
         State::Price(org_price) => {
             match ssm.market.get(&org_price.symbol) {
                 Some(m) => {
-                    let price = m.get_price(org_price.price as u64);
+                    let price = match m.get_price(org_price.price) {
+                        Ok(p) => p,
+                        Err(e) => {
+                            error!("get price of {} error: {}", org_price.symbol, e);
+                            return;
+                        }
+                    };
                     ssm.price.insert(org_price.symbol.clone(), price);
+                    send_event_update(event_update_tx, EventUpdate::Price(org_price.clone()));
                     if is_write_ws_event {
                         let spread_data = SpreadData {
                             symbol: org_price.symbol.clone(),
@@ -242,10 +311,160 @@ async fn handle_message<S>(
         }
     }
 }
+// Tell the ws clients a position has been opened or closed.
+fn send_position_status_event(
+    ssm: &StateMap,
+    event_ws_tx: &WsWatchTx,
+    position: &Position,
+    is_open: bool,
+) {
+    let msg = if is_open {
+        WsSrvMessage::PositionOpen(PositionDynamicData {
+            id: position.id.copy(),
+            account_id: position.account_id.copy(),
+            ..Default::default()
+        })
+    } else {
+        ssm.position_dynamic_data.remove(&position.id);
+        WsSrvMessage::PositionClose(PositionDynamicData {
+            id: position.id.copy(),
+            account_id: position.account_id.copy(),
+            profit_rate: get_rate(position.profit, position.margin),
+            profit: position.profit,
+        })
+    };
+    if let Err(e) = event_ws_tx.0.send(msg) {
+        error!("send position status event error: {}", e);
+    }
+}
+fn send_event_update(event_update_tx: &EventUpdateTx, event: EventUpdate) {
+    if let Err(e) = event_update_tx.send(event) {
+        error!("send event update error: {}", e);
+    }
+}
+// key is account address, the account is waiting in the liquidation queue
+type DsQueuedAccount = DashSet<Address>;
+// key is position address, the force liquidation transaction is in flight
+type DsLiquidating = DashSet<Address>;
+// key is account address, value is the last time its dynamic data was pushed
+type DmPushTime = DashMap<Address, Instant>;
+
+// key is account address, a trailing push of the account is scheduled
+type DsDeferredPush = DashSet<Address>;
+
+/// Computes the account and position dynamic data and sends them to the ws clients,
+/// the data of one account is pushed at most once per interval. An update inside the
+/// interval schedules a trailing push, so the last state of a burst still reaches the clients.
+#[derive(Clone)]
+struct DynamicDataPush {
+    event_ws_tx: WsWatchTx,
+    interval: TokioDuration,
+    last_push: Arc<DmPushTime>,
+    deferred: Arc<DsDeferredPush>,
+}
+impl DynamicDataPush {
+    fn new(event_ws_tx: WsWatchTx, interval: TokioDuration) -> Self {
+        Self {
+            event_ws_tx,
+            interval,
+            last_push: Arc::new(DashMap::new()),
+            deferred: Arc::new(DashSet::new()),
+        }
+    }
+
+    fn push(&self, ssm: &SharedStateMap, account: &Account) {
+        let now = Instant::now();
+        match self.last_push.entry(account.id.copy()) {
+            Entry::Occupied(mut e) => {
+                let next = *e.get() + self.interval;
+                if now < next {
+                    drop(e);
+                    self.defer(ssm, &account.id, next);
+                    return;
+                }
+                e.insert(now);
+            }
+            Entry::Vacant(e) => {
+                e.insert(now);
+            }
+        }
+        let (account_data, positions_data) = get_account_dynamic_data(ssm, account);
+        ssm.account_dynamic_data
+            .insert(account.id.copy(), account_data.clone());
+        if let Err(e) = self
+            .event_ws_tx
+            .0
+            .send(WsSrvMessage::AccountUpdate(account_data))
+        {
+            error!("send account dynamic data error: {}", e);
+        }
+        for data in positions_data {
+            ssm.position_dynamic_data
+                .insert(data.id.copy(), data.clone());
+            if let Err(e) = self.event_ws_tx.0.send(WsSrvMessage::PositionUpdate(data)) {
+                error!("send position dynamic data error: {}", e);
+            }
+        }
+    }
+
+    // Push the latest state of the account at the end of the interval, once per account.
+    fn defer(&self, ssm: &SharedStateMap, id: &Address, at: Instant) {
+        if !self.deferred.insert(id.copy()) {
+            return;
+        }
+        let push = self.clone();
+        let ssm = ssm.clone();
+        let id = id.copy();
+        tokio::spawn(async move {
+            tokio_time::sleep_until(at).await;
+            push.deferred.remove(&id);
+            let account = ssm.account.get(&id).map(|a| a.value().clone());
+            if let Some(account) = account {
+                push.push(&ssm, &account);
+            }
+        });
+    }
+
+    // Forget the deleted account.
+    fn remove(&self, id: &Address) {
+        self.last_push.remove(id);
+    }
+}
+#[derive(Clone)]
+struct LiquidationQueue {
+    tx: flume::Sender<Address>,
+    queued: Arc<DsQueuedAccount>,
+}
+impl LiquidationQueue {
+    fn new() -> (Self, flume::Receiver<Address>) {
+        let (tx, rx) = flume::unbounded::<Address>();
+        (
+            Self {
+                tx,
+                queued: Arc::new(DashSet::new()),
+            },
+            rx,
+        )
+    }
+    // An account that is already waiting to be computed is not queued twice,
+    // so a burst of price updates does not pile up work for the same account.
+    fn push(&self, address: &Address) {
+        if !self.queued.insert(address.copy()) {
+            return;
+        }
+        if let Err(e) = self.tx.send(address.copy()) {
+            self.queued.remove(address);
+            error!("send address to liquidation queue error: {}", e);
+        }
+    }
+    fn done(&self, address: &Address) {
+        self.queued.remove(address);
+    }
+}
 pub struct Liquidation {
     state_update_task: Task,
-    // position_tasks: Vec<Task>,
-    // cron: Cron,
+    position_tasks: Vec<Task>,
+    cron: Cron,
 }
 
 impl Liquidation {
@@ -255,11 +474,30 @@ impl Liquidation {
         event_ws_tx: WsWatchTx,
         event_update_rx: EventUpdateRx,
         is_write_ws_event: bool,
+        cron_config: CronConfig,
+        ws_config: WsConfig,
         call: Arc<C>,
     ) -> anyhow::Result<Self>
     where
         C: MoveCall + Send + Sync + 'static,
     {
+        let tasks = tasks.max(1);
+        let cron = Cron::new().await?;
+        add_cron_jobs(&cron, ssm.clone(), &cron_config, call.clone()).await?;
+        let (queue, task_rx) = LiquidationQueue::new();
+        let liquidating: Arc<DsLiquidating> = Arc::new(DashSet::new());
+        let trigger_index = TriggerIndex::load(&ssm);
+        debug!("loaded {} stop surplus/loss positions", trigger_index.len());
+        let pending_book = PendingBook::load(&ssm);
+        debug!("loaded {} pending positions", pending_book.len());
+        let push = if is_write_ws_event {
+            Some(DynamicDataPush::new(
+                event_ws_tx,
+                TokioDuration::from_millis(ws_config.push_interval_ms),
+            ))
+        } else {
+            None
+        };
         let (shutdown_tx, shutdown_rx) = Task::new_shutdown_channel();
         Ok(Self {
             state_update_task: Task::new(
@@ -269,31 +507,137 @@ impl Liquidation {
                     ssm.clone(),
                     event_update_rx,
                     shutdown_rx,
-                    event_ws_tx,
+                    queue.clone(),
+                    liquidating.clone(),
+                    trigger_index,
+                    pending_book,
                     call.clone(),
                 )),
             ),
-            // position_tasks: Vec::new(),
-            // cron: Cron::new(ssm.clone(), tasks, event_ws_tx, is_write_ws_event, call.clone()).await?,
+            position_tasks: loop_position_task(ssm, tasks, queue, task_rx, liquidating, push, call),
+            cron,
         })
     }
 
+    pub fn get_jobs(&self) -> Vec<JobStatus> {
+        self.cron.get_jobs()
+    }
+
+    pub fn get_job_registry(&self) -> Arc<DmJobStatus> {
+        self.cron.get_registry()
+    }
+
+    /// The tasks in stop order, the cron goes first so no new job is started.
+    pub fn into_tasks(self) -> Vec<Task> {
+        let mut tasks = vec![self.cron.into_task(), self.state_update_task];
+        tasks.extend(self.position_tasks);
+        tasks
+    }
+
     pub async fn shutdown(self) {
         debug!("start shutdown liquidation...");
+        if let Err(e) = self.cron.shutdown().await {
+            error!("shutdown cron error:{}", e);
+        }
+        self.state_update_task.shutdown().await;
+        for task in self.position_tasks {
+            task.shutdown().await;
+        }
+    }
+}
+
+async fn add_cron_jobs<C>(
+    cron: &Cron,
+    ssm: SharedStateMap,
+    cron_config: &CronConfig,
+    call: Arc<C>,
+) -> anyhow::Result<()>
+where
+    C: MoveCall + Send + Sync + 'static,
+{
+    let fund_fee_ssm = ssm.clone();
+    let fund_fee_call = call.clone();
+    cron.add_named_job("fund_fee", cron_config.fund_fee.as_str(), move || {
+        process_fund_fee(fund_fee_ssm.clone(), fund_fee_call.clone())
+    })
+    .await?;
+    cron.add_named_job(
+        "opening_price",
+        cron_config.opening_price.as_str(),
+        move || update_opening_price(ssm.clone(), call.clone()),
+    )
+    .await?;
+    Ok(())
+}
+
+async fn process_fund_fee<C>(ssm: SharedStateMap, call: Arc<C>) -> anyhow::Result<()>
+where
+    C: MoveCall + Send + Sync + 'static,
+{
+    info!("start process fund fee, current time: {:?}", Utc::now());
+    // collect first, so no dashmap guard is held across the transactions
+    let accounts: Vec<Address> = ssm.account.iter().map(|v| v.key().copy()).collect();
+    let mut failed = 0;
+    for address in accounts.iter() {
+        if let Err(e) = call.process_fund_fee(address.copy()).await {
+            error!("process fund fee of account {} error: {}", address, e);
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        return Err(anyhow::anyhow!(
+            "process fund fee failed for {} of {} accounts",
+            failed,
+            accounts.len()
+        ));
+    }
+    Ok(())
+}
+
+async fn update_opening_price<C>(ssm: SharedStateMap, call: Arc<C>) -> anyhow::Result<()>
+where
+    C: MoveCall + Send + Sync + 'static,
+{
+    info!("start update opening price, current time: {:?}", Utc::now());
+    // the transaction takes the market object
+    let markets: Vec<(Address, String)> = ssm
+        .market
+        .iter()
+        .map(|v| (v.id.copy(), v.symbol.clone()))
+        .collect();
+    let mut failed = 0;
+    for (id, symbol) in markets.iter() {
+        if let Err(e) = call.trigger_update_opening_price(id.to_string()).await {
+            error!("update opening price of {} error: {}", symbol, e);
+            failed += 1;
+        }
     }
+    if failed > 0 {
+        return Err(anyhow::anyhow!(
+            "update opening price failed for {} of {} markets",
+            failed,
+            markets.len()
+        ));
+    }
+    Ok(())
 }
 
 async fn watch_state_update<C>(
     ssm: SharedStateMap,
     mut event_update_rx: EventUpdateRx,
     mut shutdown_rx: TaskStopRx,
-    event_ws_tx: WsWatchTx,
+    queue: LiquidationQueue,
+    liquidating: Arc<DsLiquidating>,
+    mut trigger_index: TriggerIndex,
+    mut pending_book: PendingBook,
     call: Arc<C>,
 ) -> anyhow::Result<()>
 where
     C: MoveCall + Send + Sync + 'static,
 {
-    info!("start scale data watch ...");
+    info!("start scale state update watch ...");
+    let auto_closing: Arc<DsAutoClosing> = Arc::new(DashSet::new());
+    let limit_opening: Arc<DsLimitOpening> = Arc::new(DashSet::new());
     loop {
         tokio::select! {
             r = &mut shutdown_rx => {
@@ -310,21 +654,52 @@ where
             r = event_update_rx.recv()=> {
                 match r {
                     Some(msg)=>{
-                        // debug!("data channel got data : {:?}",msg);
                         match msg {
                             EventUpdate::AccountUpdate(account) => {
-                                handle_account_state_update(ssm.clone(), account).await;
+                                handle_account_state_update(&queue, account);
+                            }
+                            // the worker finds no account and drops its push state
+                            EventUpdate::AccountDeleted(address) => {
+                                queue.push(&address);
                             }
                             EventUpdate::PositionUpdate(position) => {
-                                handle_position_state_update(ssm.clone(), position).await;
+                                let is_live = is_position_live(&ssm, &position);
+                                handle_position_trigger_update(
+                                    &mut trigger_index,
+                                    &auto_closing,
+                                    &position,
+                                    is_live,
+                                );
+                                handle_position_pending_update(
+                                    &mut pending_book,
+                                    &limit_opening,
+                                    &position,
+                                    is_live,
+                                );
+                                handle_position_state_update(&queue, &liquidating, position);
                             }
                             EventUpdate::Price(price) => {
-                                // handle_price_state_update(ssm.clone(), price).await;
+                                handle_price_trigger_update(
+                                    ssm.clone(),
+                                    &trigger_index,
+                                    &auto_closing,
+                                    &price,
+                                    call.clone(),
+                                );
+                                handle_price_pending_update(
+                                    ssm.clone(),
+                                    &pending_book,
+                                    &limit_opening,
+                                    &price,
+                                    call.clone(),
+                                );
+                                handle_price_state_update(ssm.clone(), &queue, price);
                             }
                         }
                     }
                     None=>{
-                        debug!("data channel got none : {:?}",r);
+                        debug!("event update channel got none : {:?}",r);
+                        break;
                     }
                 }
             }
@@ -333,5 +708,555 @@ where
     Ok(())
 }
 
-async fn handle_account_state_update(ssm: SharedStateMap, address: Account) {}
-async fn handle_position_state_update(ssm: SharedStateMap, address: Position) {}
+fn handle_account_state_update(queue: &LiquidationQueue, account: Account) {
+    queue.push(&account.id);
+}
+
+fn handle_position_state_update(
+    queue: &LiquidationQueue,
+    liquidating: &DsLiquidating,
+    position: Position,
+) {
+    // the position has left the normal status, so the liquidation transaction
+    // (if any) has landed and the position can be liquidated again if it reopens.
+    if position.status != PositionStatus::Normal {
+        liquidating.remove(&position.id);
+    }
+    queue.push(&position.account_id);
+}
+
+// A deleted position is removed from the state map but still sent as an update.
+fn is_position_live(ssm: &SharedStateMap, position: &Position) -> bool {
+    match ssm.position.get(&position.account_id) {
+        Some(p) => p.contains_key(&position.id),
+        None => false,
+    }
+}
+
+fn handle_position_trigger_update(
+    trigger_index: &mut TriggerIndex,
+    auto_closing: &DsAutoClosing,
+    position: &Position,
+    is_live: bool,
+) {
+    if position.status != PositionStatus::Normal {
+        auto_closing.remove(&position.id);
+    }
+    if is_live {
+        trigger_index.upsert(position);
+    } else {
+        trigger_index.remove(&position.id);
+    }
+}
+
+fn handle_position_pending_update(
+    pending_book: &mut PendingBook,
+    limit_opening: &DsLimitOpening,
+    position: &Position,
+    is_live: bool,
+) {
+    // the limit position has been opened (or canceled), the transaction has landed
+    if position.status != PositionStatus::Pending {
+        limit_opening.remove(&position.id);
+    }
+    // edits of the limit price and lot arrive as updates of the pending position
+    if is_live {
+        pending_book.upsert(position);
+    } else {
+        pending_book.remove(&position.id);
+    }
+}
+
+fn handle_price_trigger_update<C>(
+    ssm: SharedStateMap,
+    trigger_index: &TriggerIndex,
+    auto_closing: &Arc<DsAutoClosing>,
+    org_price: &OrgPrice,
+    call: Arc<C>,
+) where
+    C: MoveCall + Send + Sync + 'static,
+{
+    // the spread price is computed by the market when the org price is stored
+    let price = match ssm.price.get(&org_price.symbol) {
+        Some(p) => *p.value(),
+        None => return,
+    };
+    for tp in trigger_index.crossed(&org_price.symbol, &price) {
+        trigger::auto_close_position(call.clone(), auto_closing.clone(), tp);
+    }
+}
+
+fn handle_price_pending_update<C>(
+    ssm: SharedStateMap,
+    pending_book: &PendingBook,
+    limit_opening: &Arc<DsLimitOpening>,
+    org_price: &OrgPrice,
+    call: Arc<C>,
+) where
+    C: MoveCall + Send + Sync + 'static,
+{
+    let price = match ssm.price.get(&org_price.symbol) {
+        Some(p) => *p.value(),
+        None => return,
+    };
+    for pp in pending_book.crossed(&org_price.symbol, &price) {
+        pending::open_limit_position(call.clone(), limit_opening.clone(), pp);
+    }
+}
+
+fn handle_price_state_update(ssm: SharedStateMap, queue: &LiquidationQueue, price: OrgPrice) {
+    // Only the accounts holding a position of this symbol are affected by the new price.
+    for v in ssm.position.iter() {
+        if v.value().iter().any(|p| p.symbol == price.symbol) {
+            queue.push(v.key());
+        }
+    }
+}
+
+fn loop_position_task<C>(
+    ssm: SharedStateMap,
+    tasks: usize,
+    queue: LiquidationQueue,
+    task_rx: flume::Receiver<Address>,
+    liquidating: Arc<DsLiquidating>,
+    push: Option<DynamicDataPush>,
+    call: Arc<C>,
+) -> Vec<Task>
+where
+    C: MoveCall + Send + Sync + 'static,
+{
+    debug!("start {} liquidation position tasks...", tasks);
+    let mut workers: Vec<Task> = Vec::with_capacity(tasks);
+    for t in 0..tasks {
+        let (task_shutdown_tx, task_shutdown_rx) = Task::new_shutdown_channel();
+        let task = tokio::spawn(loop_position_by_user(
+            ssm.clone(),
+            queue.clone(),
+            task_rx.clone(),
+            liquidating.clone(),
+            task_shutdown_rx,
+            push.clone(),
+            call.clone(),
+        ));
+        workers.push(Task::new(
+            &format!("liquidation_position_task_{}", t),
+            task_shutdown_tx,
+            task,
+        ));
+    }
+    workers
+}
+
+async fn loop_position_by_user<C>(
+    ssm: SharedStateMap,
+    queue: LiquidationQueue,
+    task_rx: flume::Receiver<Address>,
+    liquidating: Arc<DsLiquidating>,
+    mut shutdown_rx: TaskStopRx,
+    push: Option<DynamicDataPush>,
+    call: Arc<C>,
+) -> anyhow::Result<()>
+where
+    C: MoveCall + Send + Sync,
+{
+    loop {
+        tokio::select! {
+            _ = &mut shutdown_rx => {
+                info!("got shutdown signal,break loop position!");
+                break;
+            },
+            account_address = task_rx.recv_async() => {
+                match account_address {
+                    Ok(address) => {
+                        queue.done(&address);
+                        // clone the account, the map guard must not be held across await points.
+                        let account = ssm.account.get(&address).map(|a| a.value().clone());
+                        match account {
+                            Some(account) => {
+                                compute_position(ssm.clone(), &account, &liquidating, call.clone()).await;
+                                if let Some(push) = &push {
+                                    push.push(&ssm, &account);
+                                }
+                            },
+                            None => {
+                                debug!("no account for state map : {:?}",address);
+                                if let Some(push) = &push {
+                                    push.remove(&address);
+                                }
+                            }
+                        }
+                    },
+                    Err(e) => {
+                        error!("recv account address error: {}",e);
+                        break;
+                    }
+                }
+            },
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct PositionSort {
+    pub position_address: Address,
+    pub profit: i64,
+    pub direction: Direction,
+    pub margin: u64,
+    pub symbol: String,
+}
+
+// Whether the equity is below the maintenance margin of the given margin.
+fn is_burst(equity: i64, margin: u64) -> bool {
+    if margin == 0 {
+        return false;
+    }
+    (equity as f64 / margin as f64) < BURST_RATE
+}
+
+// Pick the cross positions to be closed, the worst position first,
+// until the remaining cross margin is safe again.
+fn get_cross_burst_positions(
+    equity: i64,
+    margin_buy_total: u64,
+    margin_sell_total: u64,
+    mut positions: Vec<PositionSort>,
+) -> Vec<PositionSort> {
+    let mut margin_buy_total = margin_buy_total;
+    let mut margin_sell_total = margin_sell_total;
+    if !is_burst(equity, margin_buy_total.max(margin_sell_total)) {
+        return vec![];
+    }
+    positions.sort_by(|a, b| a.profit.cmp(&b.profit));
+    let mut rs = Vec::with_capacity(positions.len());
+    for p in positions {
+        match p.direction {
+            Direction::Buy => {
+                margin_buy_total = margin_buy_total.saturating_sub(p.margin);
+            }
+            Direction::Sell => {
+                margin_sell_total = margin_sell_total.saturating_sub(p.margin);
+            }
+            Direction::Flat => {}
+        }
+        rs.push(p);
+        // Reach the safety line of explosion
+        if !is_burst(equity, margin_buy_total.max(margin_sell_total)) {
+            break;
+        }
+    }
+    rs
+}
+
+async fn compute_position<C>(
+    ssm: SharedStateMap,
+    account: &Account,
+    liquidating: &DsLiquidating,
+    call: Arc<C>,
+) where
+    C: MoveCall,
+{
+    let positions: Vec<Position> = match ssm.position.get(&account.id) {
+        Some(p) => p.iter().map(|v| v.value().clone()).collect(),
+        None => {
+            debug!("no position for state map : {:?}", account.id.to_string());
+            return;
+        }
+    };
+    let mut cross_sort: Vec<PositionSort> = Vec::with_capacity(positions.len());
+    let mut isolated_burst: Vec<PositionSort> = Vec::new();
+    let mut pl_cross = 0i64;
+    // the cross equity is not reliable while the price of one of its markets is unhealthy
+    let mut cross_paused = false;
+    for position in positions.iter() {
+        if position.status != PositionStatus::Normal {
+            continue;
+        }
+        if ssm.price_health.contains_key(&position.symbol) {
+            debug!(
+                "price of {} is unhealthy, skip position: {}",
+                position.symbol, position.id
+            );
+            if position.position_type.is_cross() {
+                cross_paused = true;
+            }
+            continue;
+        }
+        let market = match ssm.market.get(&position.symbol) {
+            Some(m) => m.value().clone(),
+            None => {
+                error!("no market for position id: {}", position.id);
+                continue;
+            }
+        };
+        let price = match ssm.price.get(&position.symbol) {
+            Some(p) => *p.value(),
+            None => {
+                debug!("no price for position id: {}", position.id);
+                continue;
+            }
+        };
+        let pl_and_fund_fee = match position.get_pl_and_fund_fee(&price, &market) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("get pl of position id: {} error: {}", position.id, e);
+                if position.position_type.is_cross() {
+                    cross_paused = true;
+                }
+                continue;
+            }
+        };
+        let ps = PositionSort {
+            position_address: position.id.copy(),
+            profit: pl_and_fund_fee,
+            direction: position.direction,
+            margin: position.margin,
+            symbol: position.symbol.clone(),
+        };
+        if position.position_type.is_cross() {
+            pl_cross += pl_and_fund_fee;
+            cross_sort.push(ps);
+        } else if is_burst(
+            position.margin_balance as i64 + pl_and_fund_fee,
+            position.margin,
+        ) {
+            isolated_burst.push(ps);
+        }
+    }
+    for p in isolated_burst {
+        force_liquidation(
+            account,
+            p,
+            PositionType::Isolated,
+            liquidating,
+            call.clone(),
+        )
+        .await;
+    }
+    if cross_paused {
+        return;
+    }
+    let cross_burst = get_cross_burst_positions(
+        account.balance as i64 + pl_cross,
+        account.margin_cross_buy_total,
+        account.margin_cross_sell_total,
+        cross_sort,
+    );
+    for p in cross_burst {
+        force_liquidation(account, p, PositionType::Cross, liquidating, call.clone()).await;
+    }
+}
+
+/// Compute the floating profit (P/L and fund fee) of the normal positions of an account,
+/// and the equity and margin ratio of the account with the current prices.
+pub fn get_account_dynamic_data(
+    ssm: &StateMap,
+    account: &Account,
+) -> (AccountDynamicData, Vec<PositionDynamicData>) {
+    let positions: Vec<Position> = match ssm.position.get(&account.id) {
+        Some(p) => p.iter().map(|v| v.value().clone()).collect(),
+        None => vec![],
+    };
+    let mut positions_data: Vec<PositionDynamicData> = Vec::with_capacity(positions.len());
+    let mut profit = 0i64;
+    let mut pl_cross = 0i64;
+    for position in positions.iter() {
+        if position.status != PositionStatus::Normal {
+            continue;
+        }
+        let (market, price) = match (
+            ssm.market.get(&position.symbol),
+            ssm.price.get(&position.symbol),
+        ) {
+            (Some(m), Some(p)) => (m.value().clone(), *p.value()),
+            _ => continue,
+        };
+        let pl_and_fund_fee = match position.get_pl_and_fund_fee(&price, &market) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("get pl of position id: {} error: {}", position.id, e);
+                continue;
+            }
+        };
+        profit += pl_and_fund_fee;
+        if position.position_type.is_cross() {
+            pl_cross += pl_and_fund_fee;
+        }
+        positions_data.push(PositionDynamicData {
+            id: position.id.copy(),
+            account_id: account.id.copy(),
+            profit_rate: get_rate(pl_and_fund_fee, position.margin),
+            profit: pl_and_fund_fee,
+        });
+    }
+    // the cross equity and margin checked by the liquidation, the isolated positions
+    // are liquidated by their own margin
+    let equity = account.balance as i64 + pl_cross;
+    let margin_cross = account
+        .margin_cross_buy_total
+        .max(account.margin_cross_sell_total);
+    let account_data = AccountDynamicData {
+        id: account.id.copy(),
+        balance: account.balance as i64,
+        profit,
+        margin_total: account.margin_total as i64,
+        margin_percentage: get_rate(equity, margin_cross),
+        equity,
+        profit_rate: get_rate(profit, account.margin_total),
+    };
+    (account_data, positions_data)
+}
+
+fn get_rate(value: i64, base: u64) -> f64 {
+    if base == 0 {
+        return 0.0;
+    }
+    com::f64_round_4(value as f64 / base as f64)
+}
+
+async fn force_liquidation<C>(
+    account: &Account,
+    position: PositionSort,
+    position_type: PositionType,
+    liquidating: &DsLiquidating,
+    call: Arc<C>,
+) where
+    C: MoveCall,
+{
+    if !liquidating.insert(position.position_address.copy()) {
+        debug!(
+            "position {} is being liquidated, skip it",
+            position.position_address
+        );
+        return;
+    }
+    info!(
+        "force liquidation position: {}, account: {}, profit: {}",
+        position.position_address, account.id, position.profit
+    );
+    let params = PositionParams {
+        id: position.position_address.copy(),
+        position_type: position_type as u8,
+        symbol: position.symbol,
+    };
+    match call.force_liquidation(account.id.copy(), params).await {
+        Ok(outcome) => {
+            info!(
+                "force liquidated position {}: {}",
+                position.position_address, outcome
+            );
+        }
+        Err(e) => {
+            error!(
+                "force liquidation position {} error: {}",
+                position.position_address, e
+            );
+            // let the next round retry it
+            liquidating.remove(&position.position_address);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::state::{MarketStatus, TxOutcome};
+    use async_trait::async_trait;
+    use std::sync::Mutex;
+
+    // Records the markets of the opening price calls.
+    #[derive(Default)]
+    struct MockCall {
+        opening_price: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl MoveCall for MockCall {
+        async fn trigger_update_opening_price(
+            &self,
+            market_id: String,
+        ) -> anyhow::Result<TxOutcome> {
+            self.opening_price.lock().unwrap().push(market_id);
+            Ok(TxOutcome::default())
+        }
+        async fn auto_close_position(
+            &self,
+            _account_id: Address,
+            _position: PositionParams,
+        ) -> anyhow::Result<TxOutcome> {
+            unimplemented!()
+        }
+        async fn force_liquidation(
+            &self,
+            _account_id: Address,
+            _position: PositionParams,
+        ) -> anyhow::Result<TxOutcome> {
+            unimplemented!()
+        }
+        async fn open_limit_position(
+            &self,
+            _account_id: Address,
+            _position: PositionParams,
+        ) -> anyhow::Result<TxOutcome> {
+            unimplemented!()
+        }
+        async fn process_fund_fee(&self, _account_id: Address) -> anyhow::Result<TxOutcome> {
+            unimplemented!()
+        }
+        async fn get_price(&self, _symbol: &str) -> anyhow::Result<TxOutcome> {
+            unimplemented!()
+        }
+        async fn update_pyth_price(
+            &self,
+            _feed_ids: Vec<String>,
+            _update_fee: u64,
+        ) -> anyhow::Result<TxOutcome> {
+            unimplemented!()
+        }
+        async fn receive_award(&self, _nft: String) -> anyhow::Result<TxOutcome> {
+            unimplemented!()
+        }
+        async fn receive_reward(&self) -> anyhow::Result<TxOutcome> {
+            unimplemented!()
+        }
+    }
+
+    fn market(id: Address, symbol: &str) -> Market {
+        Market {
+            id,
+            max_leverage: 125,
+            insurance_fee: 5,
+            margin_fee: 10000,
+            fund_fee: 10,
+            fund_fee_manual: false,
+            spread_fee: 30,
+            spread_fee_manual: false,
+            status: MarketStatus::Normal,
+            long_position_total: 0,
+            short_position_total: 0,
+            symbol: symbol.to_string(),
+            symbol_short: symbol.to_string(),
+            icon: String::new(),
+            description: String::new(),
+            unit_size: 1,
+            opening_price: 0,
+            list_id: Address::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_update_opening_price_of_market_object() {
+        let ssm = Arc::new(StateMap::new(DashSet::new()).unwrap());
+        let id = Address::new(vec![7; 32]);
+        ssm.market
+            .insert("BTC/USD".to_string(), market(id.copy(), "BTC/USD"));
+        let call = Arc::new(MockCall::default());
+        update_opening_price(ssm, call.clone()).await.unwrap();
+        assert_eq!(*call.opening_price.lock().unwrap(), vec![id.to_string()]);
+        // the id is parsed as the market object on chain
+        assert_eq!(
+            id.to_string(),
+            "0x0707070707070707070707070707070707070707070707070707070707070707"
+        );
+    }
+}
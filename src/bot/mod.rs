@@ -1,9 +1,14 @@
 pub mod app;
 pub mod cron;
+pub mod guard;
 pub mod influxdb;
+pub mod kline;
 pub mod machine;
 pub mod oracle;
+pub mod pending;
 pub mod price;
 pub mod state;
 pub mod storage;
+pub mod supervisor;
+pub mod trigger;
 pub mod ws;
@@ -1,191 +1,302 @@
-// use crate::bot::state::OrgPrice;
-// use crate::bot::{state::MoveCall, ws::PriceWatchRx};
-// use crate::com::Task;
-// use dashmap::DashMap;
-// use log::*;
-// use std::sync::Arc;
-// use tokio::{
-//     sync::oneshot,
-//     time::{self, Duration},
-// };
-
-// // key: symbol , value: price
-// pub type DmPriceFeed = DashMap<String, PriceFeed>;
-// pub struct PriceOracle {
-//     task: Task,
-// }
-// #[derive(Debug, Clone)]
-// pub struct PriceFeed {
-//     pub feed_address: String,
-//     pub price: i64,
-//     pub timestamp: i64,
-// }
-
-// impl PriceOracle {
-//     pub async fn new<C>(
-//         dpf: Arc<DmPriceFeed>,
-//         price_ws_rx: PriceWatchRx,
-//         duration: i64,
-//         call: Arc<C>,
-//     ) -> Self
-//     where
-//         C: MoveCall + Send + Sync + 'static,
-//     {
-//         let (shutdown_tx, shutdown_rx) = Task::new_shutdown_channel();
-//         if duration == 0 {
-//             let task = Task::new(
-//                 "price now oracle",
-//                 shutdown_tx,
-//                 tokio::spawn(update_price_now(dpf, price_ws_rx, shutdown_rx, call)),
-//             );
-//             return Self { task };
-//         } else {
-//             let task = Task::new(
-//                 "price interval oracle",
-//                 shutdown_tx,
-//                 tokio::spawn(update_price_interval(
-//                     dpf,
-//                     price_ws_rx,
-//                     Duration::from_secs(duration as u64),
-//                     shutdown_rx,
-//                     call,
-//                 )),
-//             );
-//             return Self { task };
-//         }
-//     }
-//     pub async fn shutdown(self) {
-//         self.task.shutdown().await;
-//     }
-// }
-
-// async fn update_price_interval<C>(
-//     dpf: Arc<DmPriceFeed>,
-//     mut price_ws_rx: PriceWatchRx,
-//     duration: Duration,
-//     mut shutdown_rx: oneshot::Receiver<()>,
-//     call: Arc<C>,
-// ) -> anyhow::Result<()>
-// where
-//     C: MoveCall + Send + Sync + 'static,
-// {
-//     let mut timer = time::interval(duration);
-//     info!("start interval price oracle task");
-//     loop {
-//         tokio::select! {
-//             _ = (&mut shutdown_rx) => {
-//                 info!("got shutdown signal , break price broadcast!");
-//                 break;
-//             },
-//             Ok(price) = price_ws_rx.0.recv() => {
-//                 if let Err(e) = recv_price(dpf.clone(),&price) {
-//                     error!("receiver and save oracle price error: {}", e);
-//                 }
-//             }
-//             _ = timer.tick() => {
-//                 if let Err(e) = update_price_to_oracle(dpf.clone(), call.clone()).await {
-//                     error!("update price status error: {}", e);
-//                 }
-//             }
-//         }
-//     }
-//     Ok(())
-// }
-
-// async fn update_price_now<C>(
-//     dpf: Arc<DmPriceFeed>,
-//     mut price_ws_rx: PriceWatchRx,
-//     mut shutdown_rx: oneshot::Receiver<()>,
-//     call: Arc<C>,
-// ) -> anyhow::Result<()>
-// where
-//     C: MoveCall + Send + Sync + 'static,
-// {
-//     info!("start now price oracle task");
-//     loop {
-//         tokio::select! {
-//             _ = (&mut shutdown_rx) => {
-//                 info!("got shutdown signal , break price broadcast!");
-//                 break;
-//             },
-//             Ok(price) = price_ws_rx.0.recv() => {
-//                 if let Err(e) = update_time_now(dpf.clone(),&price,call.clone()).await {
-//                     error!("update price status error: {}", e);
-//                 }
-//             }
-//         }
-//     }
-//     Ok(())
-// }
-
-// async fn update_time_now<C>(
-//     dpf: Arc<DmPriceFeed>,
-//     org_price: &OrgPrice,
-//     call: Arc<C>,
-// ) -> anyhow::Result<()>
-// where
-//     C: MoveCall + Send + Sync + 'static,
-// {
-//     // let record = dpf.get(&org_price.symbol);
-//     // debug!("oracle update price now: {:?}", org_price);
-//     // if let Some(record) = record {
-//     //     let mut price_feed = record.value().clone();
-//     //     price_feed.price = org_price.price;
-//     //     price_feed.timestamp = org_price.update_time;
-//     //     if price_feed.price == 0 {
-//     //         warn!("price is 0, skip it: {:?}", org_price.symbol);
-//     //         return Ok(());
-//     //     }
-//     //     call.update_price(price_feed.feed_address.as_str(), price_feed.price as u64)
-//     //         .await?;
-//     // } else {
-//     //     debug!(
-//     //         "symbol {} ,cannot found price feed record",
-//     //         org_price.symbol
-//     //     );
-//     // }
-//     Ok(())
-// }
-
-// fn recv_price(dpf: Arc<DmPriceFeed>, org_price: &OrgPrice) -> anyhow::Result<()> {
-//     let record = dpf.get_mut(&org_price.symbol);
-//     debug!("oracle recv price: {:?}", org_price);
-//     if let Some(mut record) = record {
-//         let price_feed = record.value_mut();
-//         price_feed.price = org_price.price;
-//         price_feed.timestamp = org_price.update_time;
-//     } else {
-//         debug!(
-//             "symbol {} ,cannot found price feed record",
-//             org_price.symbol
-//         );
-//     }
-//     Ok(())
-// }
-
-// async fn update_price_to_oracle<C>(dpf: Arc<DmPriceFeed>, call: Arc<C>) -> anyhow::Result<()>
-// where
-//     C: MoveCall + Send + Sync + 'static,
-// {
-//     // for feed in dpf.iter() {
-//     //     debug!(
-//     //         "update price to oracle {:?} to {:?}",
-//     //         feed.key(),
-//     //         feed.value().price
-//     //     );
-//     //     if feed.value().price == 0 {
-//     //         warn!("price is 0, skip it: {:?}", feed.key());
-//     //         continue;
-//     //     }
-//     //     if Utc::now().timestamp() - feed.value().timestamp > 300 {
-//     //         warn!("price is too old, skip it: {:?}", feed.key());
-//     //         continue;
-//     //     }
-//     //     call.update_price(
-//     //         feed.value().feed_address.as_str(),
-//     //         feed.value().price as u64,
-//     //     )
-//     //     .await?;
-//     // }
-//     Ok(())
-// }
+// Push the pyth prices on chain for the oracle mode of the bot.
+// The due feeds are updated together in one transaction, and the pyth fees
+// paid in the last hour are capped.
+use crate::bot::machine::SharedStateMap;
+use crate::bot::state::MoveCall;
+use crate::com::{Task, TaskStopRx};
+use crate::config::{OracleConfig, PriceConfig};
+use log::*;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use tokio::time::{self, Duration, Instant};
+
+const FEE_WINDOW: Duration = Duration::from_secs(3600);
+
+// The last on chain update of a feed.
+struct Pushed {
+    price: u64,
+    time: Instant,
+}
+
+// A feed to update, the most urgent ones are updated first when the batch is full.
+struct DueFeed {
+    symbol: String,
+    feed_id: String,
+    price: u64,
+    // the deviation rate from the last update, 0 for a heartbeat update
+    deviation: f64,
+    // the time since the last update, None if never updated
+    age: Option<Duration>,
+}
+
+/// The pyth fees paid in the last hour.
+struct FeeBudget {
+    max_per_hour: u64,
+    paid: VecDeque<(Instant, u64)>,
+}
+
+impl FeeBudget {
+    fn new(max_per_hour: u64) -> Self {
+        Self {
+            max_per_hour,
+            paid: VecDeque::new(),
+        }
+    }
+
+    /// The fee that can still be paid, None if there is no limit.
+    fn available(&mut self, now: Instant) -> Option<u64> {
+        if self.max_per_hour == 0 {
+            return None;
+        }
+        while let Some((t, _)) = self.paid.front() {
+            if now.duration_since(*t) < FEE_WINDOW {
+                break;
+            }
+            self.paid.pop_front();
+        }
+        let paid: u64 = self.paid.iter().map(|(_, fee)| fee).sum();
+        Some(self.max_per_hour.saturating_sub(paid))
+    }
+
+    fn pay(&mut self, now: Instant, fee: u64) {
+        self.paid.push_back((now, fee));
+    }
+}
+
+pub struct PriceOracle {
+    task: Task,
+}
+
+impl PriceOracle {
+    pub fn new<C>(ssm: SharedStateMap, price_config: PriceConfig, call: Arc<C>) -> Self
+    where
+        C: MoveCall + Send + Sync + 'static,
+    {
+        let config = price_config.oracle;
+        // key is symbol, value is feed id
+        let feeds: HashMap<String, String> = price_config
+            .pyth_symbol
+            .into_iter()
+            .filter(|s| config.symbols.is_empty() || config.symbols.contains(&s.symbol))
+            .map(|s| (s.symbol, s.pyth_feed))
+            .collect();
+        info!("start price oracle of symbols: {:?}", feeds.keys());
+        let (shutdown_tx, shutdown_rx) = Task::new_shutdown_channel();
+        let task = Task::new(
+            "price oracle",
+            shutdown_tx,
+            tokio::spawn(push_prices(ssm, config, feeds, shutdown_rx, call)),
+        );
+        Self { task }
+    }
+
+    pub fn into_tasks(self) -> Vec<Task> {
+        vec![self.task]
+    }
+}
+
+async fn push_prices<C>(
+    ssm: SharedStateMap,
+    config: OracleConfig,
+    feeds: HashMap<String, String>,
+    mut shutdown_rx: TaskStopRx,
+    call: Arc<C>,
+) -> anyhow::Result<()>
+where
+    C: MoveCall + Send + Sync + 'static,
+{
+    let heartbeat = Duration::from_secs(config.heartbeat_secs);
+    let mut budget = FeeBudget::new(config.max_fee_per_hour);
+    // key is symbol
+    let mut pushed: HashMap<String, Pushed> = HashMap::new();
+    let mut timer = time::interval(Duration::from_millis(config.interval_ms));
+    loop {
+        tokio::select! {
+            _ = &mut shutdown_rx => {
+                info!("got shutdown signal, break price oracle!");
+                break;
+            }
+            _ = timer.tick() => {
+                let now = Instant::now();
+                let mut due = get_due_feeds(&ssm, &config, &feeds, &pushed, heartbeat, now);
+                if due.is_empty() {
+                    continue;
+                }
+                let mut max_feeds = config.max_batch.max(1);
+                if let Some(available) = budget.available(now) {
+                    let affordable = match config.update_fee {
+                        0 => usize::MAX,
+                        fee => (available / fee) as usize,
+                    };
+                    if affordable < due.len().min(max_feeds) {
+                        warn!(
+                            "pyth fee limit {} per hour reached, skip {} of {} due feeds",
+                            config.max_fee_per_hour,
+                            due.len() - affordable.min(due.len()),
+                            due.len()
+                        );
+                    }
+                    max_feeds = max_feeds.min(affordable);
+                }
+                due.truncate(max_feeds);
+                if due.is_empty() {
+                    continue;
+                }
+                let feed_ids: Vec<String> = due.iter().map(|f| f.feed_id.clone()).collect();
+                let symbols: Vec<&str> = due.iter().map(|f| f.symbol.as_str()).collect();
+                debug!("update pyth prices of {:?}", symbols);
+                match call.update_pyth_price(feed_ids, config.update_fee).await {
+                    Ok(outcome) => {
+                        info!("updated pyth prices of {:?}: {}", symbols, outcome);
+                        let now = Instant::now();
+                        budget.pay(now, config.update_fee * due.len() as u64);
+                        for f in due {
+                            pushed.insert(f.symbol, Pushed { price: f.price, time: now });
+                        }
+                    }
+                    Err(e) => {
+                        error!("update pyth prices of {:?} error: {}", symbols, e);
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Get the feeds to update, the deviated ones first, then the longest not updated ones.
+fn get_due_feeds(
+    ssm: &SharedStateMap,
+    config: &OracleConfig,
+    feeds: &HashMap<String, String>,
+    pushed: &HashMap<String, Pushed>,
+    heartbeat: Duration,
+    now: Instant,
+) -> Vec<DueFeed> {
+    let mut due = vec![];
+    for (symbol, feed_id) in feeds.iter() {
+        let price = match ssm.price.get(symbol) {
+            Some(p) => p.real_price,
+            None => continue,
+        };
+        if ssm.price_health.contains_key(symbol) {
+            debug!("price of {} is unhealthy, skip its update", symbol);
+            continue;
+        }
+        let (deviation, age) = match pushed.get(symbol) {
+            Some(p) => {
+                let deviation = if p.price == 0 {
+                    0.0
+                } else {
+                    price.abs_diff(p.price) as f64 / p.price as f64
+                };
+                (deviation, Some(now.duration_since(p.time)))
+            }
+            None => (0.0, None),
+        };
+        let deviated = config.deviation_rate > 0.0 && deviation >= config.deviation_rate;
+        if !deviated && age.map_or(false, |a| a < heartbeat) {
+            continue;
+        }
+        due.push(DueFeed {
+            symbol: symbol.clone(),
+            feed_id: feed_id.clone(),
+            price,
+            deviation: if deviated { deviation } else { 0.0 },
+            age,
+        });
+    }
+    let age = |f: &DueFeed| f.age.unwrap_or(Duration::MAX);
+    due.sort_by(|a, b| {
+        b.deviation
+            .total_cmp(&a.deviation)
+            .then_with(|| age(b).cmp(&age(a)))
+    });
+    due
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::guard::{PriceHealth, PriceRejection};
+    use crate::bot::machine::StateMap;
+    use crate::bot::state::Price;
+    use dashmap::DashSet;
+
+    #[test]
+    fn test_fee_budget() {
+        let start = Instant::now();
+        assert_eq!(FeeBudget::new(0).available(start), None);
+        let mut budget = FeeBudget::new(10);
+        assert_eq!(budget.available(start), Some(10));
+        budget.pay(start, 4);
+        budget.pay(start + Duration::from_secs(1800), 5);
+        assert_eq!(budget.available(start + Duration::from_secs(1800)), Some(1));
+        budget.pay(start + Duration::from_secs(1800), 3);
+        assert_eq!(budget.available(start + Duration::from_secs(1801)), Some(0));
+        // the first fee leaves the window after an hour
+        assert_eq!(budget.available(start + FEE_WINDOW), Some(2));
+        assert_eq!(
+            budget.available(start + FEE_WINDOW + Duration::from_secs(1800)),
+            Some(10)
+        );
+        assert!(budget.paid.is_empty());
+    }
+
+    fn price(real_price: u64) -> Price {
+        Price {
+            buy_price: real_price,
+            sell_price: real_price,
+            real_price,
+            spread: 0,
+            update_time: 0,
+        }
+    }
+
+    #[test]
+    fn test_get_due_feeds() {
+        let start = Instant::now();
+        let now = start + Duration::from_secs(600);
+        let ssm = Arc::new(StateMap::new(DashSet::new()).unwrap());
+        let mut feeds = HashMap::new();
+        let mut pushed = HashMap::new();
+        // symbol, current price, last pushed price and seconds ago
+        let cases = [
+            ("DEVIATED", 10100, Some((10000, 10))),
+            ("MORE_DEVIATED", 10200, Some((10000, 10))),
+            ("HEARTBEAT", 10010, Some((10000, 120))),
+            ("NEVER", 10000, None),
+            ("FRESH", 10010, Some((10000, 10))),
+            ("UNHEALTHY", 20000, Some((10000, 120))),
+        ];
+        for (symbol, current, last) in cases {
+            feeds.insert(symbol.to_string(), format!("0x{}", symbol));
+            ssm.price.insert(symbol.to_string(), price(current));
+            if let Some((p, ago)) = last {
+                let time = now - Duration::from_secs(ago);
+                pushed.insert(symbol.to_string(), Pushed { price: p, time });
+            }
+        }
+        feeds.insert("NO_PRICE".to_string(), "0xNO_PRICE".to_string());
+        ssm.price_health.insert(
+            "UNHEALTHY".to_string(),
+            PriceHealth {
+                reason: PriceRejection::Deviation(0.5),
+                since: 0,
+                rejected: 1,
+            },
+        );
+        let config = OracleConfig::default();
+        let heartbeat = Duration::from_secs(config.heartbeat_secs);
+        let due = get_due_feeds(&ssm, &config, &feeds, &pushed, heartbeat, now);
+        let symbols: Vec<&str> = due.iter().map(|f| f.symbol.as_str()).collect();
+        // the deviated feeds first, then the longest not updated ones
+        assert_eq!(
+            symbols,
+            vec!["MORE_DEVIATED", "DEVIATED", "NEVER", "HEARTBEAT"]
+        );
+        assert_eq!(due[0].feed_id, "0xMORE_DEVIATED");
+        assert_eq!(due[0].price, 10200);
+        assert_eq!(due[3].deviation, 0.0);
+    }
+}
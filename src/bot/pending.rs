@@ -0,0 +1,275 @@
+// Pending (limit) positions waiting for the market to reach their opening price.
+// A long position is opened at the buy price and a short position at the sell price,
+// so each symbol keeps one book per direction, sorted by the limit price.
+use crate::bot::machine::StateMap;
+use crate::bot::state::{
+    Address, Direction, MoveCall, Position, PositionParams, PositionStatus, PositionType, Price,
+};
+use dashmap::DashSet;
+use log::*;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+
+// key is position address, the open limit transaction is in flight
+pub type DsLimitOpening = DashSet<Address>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingPosition {
+    pub id: Address,
+    pub account_id: Address,
+    pub symbol: String,
+    pub position_type: PositionType,
+    pub direction: Direction,
+    pub auto_open_price: u64,
+}
+
+impl From<&Position> for PendingPosition {
+    fn from(p: &Position) -> Self {
+        Self {
+            id: p.id.copy(),
+            account_id: p.account_id.copy(),
+            symbol: p.symbol.clone(),
+            position_type: p.position_type.clone(),
+            direction: p.direction,
+            auto_open_price: p.auto_open_price,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct SymbolPending {
+    // long limit positions, opened when the buy price falls to or below the limit price
+    buy: BTreeSet<(u64, Address)>,
+    // short limit positions, opened when the sell price rises to or above the limit price
+    sell: BTreeSet<(u64, Address)>,
+}
+
+impl SymbolPending {
+    fn is_empty(&self) -> bool {
+        self.buy.is_empty() && self.sell.is_empty()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PendingBook {
+    // key is symbol
+    symbols: HashMap<String, SymbolPending>,
+    // key is position address
+    positions: HashMap<Address, PendingPosition>,
+}
+
+impl PendingBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Build the book from the positions already loaded into the state map.
+    pub fn load(ssm: &StateMap) -> Self {
+        let mut book = Self::new();
+        for account_positions in ssm.position.iter() {
+            for p in account_positions.value().iter() {
+                book.upsert(p.value());
+            }
+        }
+        book
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// Insert or refresh a pending position, the limit price and lot can be edited
+    /// on chain while pending, a position that is no longer pending is removed.
+    pub fn upsert(&mut self, position: &Position) {
+        self.remove(&position.id);
+        if position.status != PositionStatus::Pending || position.auto_open_price == 0 {
+            return;
+        }
+        let pp = PendingPosition::from(position);
+        let sp = self.symbols.entry(pp.symbol.clone()).or_default();
+        match pp.direction {
+            Direction::Buy => sp.buy.insert((pp.auto_open_price, pp.id.copy())),
+            Direction::Sell => sp.sell.insert((pp.auto_open_price, pp.id.copy())),
+            Direction::Flat => return,
+        };
+        self.positions.insert(pp.id.copy(), pp);
+    }
+
+    pub fn remove(&mut self, id: &Address) {
+        let pp = match self.positions.remove(id) {
+            Some(pp) => pp,
+            None => return,
+        };
+        if let Some(sp) = self.symbols.get_mut(&pp.symbol) {
+            let key = (pp.auto_open_price, pp.id.copy());
+            match pp.direction {
+                Direction::Buy => sp.buy.remove(&key),
+                Direction::Sell => sp.sell.remove(&key),
+                Direction::Flat => false,
+            };
+            if sp.is_empty() {
+                self.symbols.remove(&pp.symbol);
+            }
+        }
+    }
+
+    /// Get the pending positions whose limit price is reached by the executable price.
+    pub fn crossed(&self, symbol: &str, price: &Price) -> Vec<PendingPosition> {
+        let sp = match self.symbols.get(symbol) {
+            Some(sp) => sp,
+            None => return vec![],
+        };
+        sp.buy
+            .iter()
+            .rev()
+            .take_while(|(p, _)| *p >= price.buy_price)
+            .chain(sp.sell.iter().take_while(|(p, _)| *p <= price.sell_price))
+            .filter_map(|(_, id)| self.positions.get(id).cloned())
+            .collect()
+    }
+}
+
+/// Send the open limit transaction of a pending position,
+/// a position whose transaction is still in flight is skipped.
+pub fn open_limit_position<C>(call: Arc<C>, limit_opening: Arc<DsLimitOpening>, pp: PendingPosition)
+where
+    C: MoveCall + Send + Sync + 'static,
+{
+    if !limit_opening.insert(pp.id.copy()) {
+        debug!("position {} is limit opening, skip it", pp.id);
+        return;
+    }
+    tokio::spawn(async move {
+        info!(
+            "open limit position: {}, account: {}, limit price: {}",
+            pp.id, pp.account_id, pp.auto_open_price
+        );
+        let params = PositionParams {
+            id: pp.id.copy(),
+            position_type: pp.position_type as u8,
+            symbol: pp.symbol,
+        };
+        if let Err(e) = call.open_limit_position(pp.account_id, params).await {
+            error!("open limit position {} error: {}", pp.id, e);
+            // let the next crossed price retry it
+            limit_opening.remove(&pp.id);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYMBOL: &str = "Crypto.BTC/USD";
+
+    fn position(id: u8, direction: Direction, auto_open_price: u64) -> Position {
+        Position {
+            id: Address::new(vec![id; 32]),
+            offset: 0,
+            margin: 0,
+            margin_balance: 0,
+            leverage: 10,
+            position_type: PositionType::Isolated,
+            status: PositionStatus::Pending,
+            direction,
+            unit_size: 1,
+            lot: 1,
+            open_price: auto_open_price,
+            open_spread: 0,
+            open_real_price: 0,
+            close_price: 0,
+            close_spread: 0,
+            close_real_price: 0,
+            profit: 0,
+            auto_open_price,
+            stop_surplus_price: 0,
+            stop_loss_price: 0,
+            create_time: 0,
+            open_time: 0,
+            close_time: 0,
+            open_operator: Address::default(),
+            close_operator: Address::default(),
+            market_id: Address::default(),
+            account_id: Address::default(),
+            symbol: SYMBOL.to_string(),
+            force_close_price: 0,
+        }
+    }
+
+    fn price(buy_price: u64, sell_price: u64) -> Price {
+        Price {
+            buy_price,
+            sell_price,
+            real_price: (buy_price + sell_price) / 2,
+            spread: buy_price - sell_price,
+            update_time: 0,
+        }
+    }
+
+    #[test]
+    fn test_crossed() {
+        // direction, limit price, buy price, sell price, fired
+        // the buy price is 50 above the sell price
+        let cases = [
+            // a buy limit opens when the buy price falls to the limit price
+            (Direction::Buy, 100, 100, 50, true),
+            (Direction::Buy, 100, 95, 45, true),
+            (Direction::Buy, 100, 105, 55, false),
+            // the sell price reaching it is not enough
+            (Direction::Buy, 100, 140, 90, false),
+            // a sell limit opens when the sell price rises to the limit price
+            (Direction::Sell, 100, 150, 100, true),
+            (Direction::Sell, 100, 155, 105, true),
+            (Direction::Sell, 100, 145, 95, false),
+            (Direction::Sell, 100, 110, 60, false),
+        ];
+        for (direction, limit, buy, sell, fired) in cases {
+            let mut book = PendingBook::new();
+            let p = position(1, direction, limit);
+            book.upsert(&p);
+            let expected = if fired {
+                vec![PendingPosition::from(&p)]
+            } else {
+                vec![]
+            };
+            assert_eq!(
+                book.crossed(SYMBOL, &price(buy, sell)),
+                expected,
+                "{:?} limit {} at buy {} sell {}",
+                direction,
+                limit,
+                buy,
+                sell
+            );
+        }
+    }
+
+    #[test]
+    fn test_leave_pending() {
+        let mut book = PendingBook::new();
+        book.upsert(&position(1, Direction::Buy, 100));
+        book.upsert(&position(2, Direction::Sell, 200));
+        assert_eq!(book.len(), 2);
+        // the limit price is edited while pending
+        book.upsert(&position(1, Direction::Buy, 90));
+        assert!(book.crossed(SYMBOL, &price(95, 45)).is_empty());
+        assert_eq!(book.crossed(SYMBOL, &price(90, 40)).len(), 1);
+        // the positions are opened or closed
+        let mut opened = position(1, Direction::Buy, 90);
+        opened.status = PositionStatus::Normal;
+        book.upsert(&opened);
+        assert!(book.crossed(SYMBOL, &price(90, 40)).is_empty());
+        assert_eq!(book.len(), 1);
+        let mut closed = position(2, Direction::Sell, 200);
+        closed.status = PositionStatus::NormalClosing;
+        book.upsert(&closed);
+        assert!(book.crossed(SYMBOL, &price(250, 200)).is_empty());
+        assert!(book.is_empty());
+        assert!(book.symbols.is_empty());
+    }
+}
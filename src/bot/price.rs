@@ -1,17 +1,28 @@
 // sub pyth.network price.
 // see https://docs.pyth.network/pythnet-price-feeds/best-practices
 // see ids: https://pyth.network/developers/price-feed-ids
+use crate::bot::guard::PriceGuard;
 use crate::bot::influxdb::Influxdb;
-use crate::bot::state::{Address, Event, Message, MessageSender, OrgPrice, State};
+use crate::bot::machine::SharedStateMap;
+use crate::bot::state::{Address, Event, FixedPrice, Message, MessageSender, OrgPrice, State};
 use crate::bot::ws::{SharedDmSymbolId, SubType, WsClient, WsClientMessage};
-use crate::com::{ClientError, DECIMALS};
+use crate::com::{ClientError, Task, TaskStopRx};
+use crate::config::{PriceConfig, PriceSourceKind};
+use async_trait::async_trait;
+use chrono::Utc;
 use futures::prelude::*;
 use influxdb2_client::api::write::Precision;
 use influxdb2_client::models::DataPoint;
 use log::*;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 use std::str::FromStr;
-use tokio::sync::{broadcast, mpsc::UnboundedSender};
+use tokio::sync::{broadcast, mpsc, mpsc::UnboundedSender};
+use tokio::time::{self, Duration, Instant};
+
+// how often the guard looks for the symbols without a live tick
+const PRICE_SWEEP_INTERVAL: Duration = Duration::from_secs(5);
 
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -43,19 +54,29 @@ pub struct PriceFeed {
 }
 
 impl PriceFeed {
+    pub fn to_org_price(&self, symbol: &str) -> anyhow::Result<OrgPrice> {
+        Ok(OrgPrice {
+            price: self.price.get_real_price()?,
+            update_time: self.price.publish_time,
+            symbol: symbol.to_string(),
+            conf: self.price.get_real_conf()?,
+            ema_price: self.ema_price.get_real_price()?,
+        })
+    }
+
     fn get_data_points(&self, measurement: String) -> anyhow::Result<Vec<DataPoint>> {
         // let now = chrono::Utc::now();
         // let ts = Utc.timestamp_millis_opt(111).unwrap();
         let r = vec![
             DataPoint::builder(measurement.clone())
-                .field("price", self.price.get_real_price())
-                .field("conf", self.price.conf.parse::<i64>().unwrap())
+                .field("price", self.price.get_real_price()?.raw())
+                .field("conf", self.price.conf.parse::<i64>()?)
                 .tag("feed", "price")
                 .timestamp(self.price.publish_time)
                 .build()?,
             DataPoint::builder(measurement)
-                .field("price", self.ema_price.get_real_price())
-                .field("conf", self.ema_price.conf.parse::<i64>().unwrap())
+                .field("price", self.ema_price.get_real_price()?.raw())
+                .field("conf", self.ema_price.conf.parse::<i64>()?)
                 .tag("feed", "ema_price")
                 .timestamp(self.ema_price.publish_time)
                 .build()?,
@@ -96,88 +117,438 @@ pub struct Price {
     pub publish_time: i64,
 }
 
+fn to_real_price(value: &str, expo: i64) -> anyhow::Result<FixedPrice> {
+    let v: i64 = value
+        .parse()
+        .map_err(|e| ClientError::JsonError(format!("invalid price {}: {}", value, e)))?;
+    FixedPrice::from_mantissa(v, expo)
+}
+
 impl Price {
-    pub fn get_real_price(&self) -> i64 {
-        let price: i64 = self.price.parse().unwrap();
-        price * (DECIMALS as i64) / 10u64.pow(self.expo.abs() as u32) as i64
+    pub fn get_real_price(&self) -> anyhow::Result<FixedPrice> {
+        to_real_price(self.price.as_str(), self.expo)
+    }
+    pub fn get_real_conf(&self) -> anyhow::Result<FixedPrice> {
+        to_real_price(self.conf.as_str(), self.expo)
     }
 }
 impl EmaPrice {
-    pub fn get_real_price(&self) -> i64 {
-        let price: i64 = self.price.parse().unwrap();
-        price * (DECIMALS as i64) / 10u64.pow(self.expo.abs() as u32) as i64
+    pub fn get_real_price(&self) -> anyhow::Result<FixedPrice> {
+        to_real_price(self.price.as_str(), self.expo)
     }
 }
 
-pub async fn sub_price(
-    watch_tx: MessageSender,
-    price_ws_url: String,
-    inf_db: Influxdb,
-    sds: SharedDmSymbolId,
-    full_node: bool,
-) -> anyhow::Result<WsClient> {
-    debug!("start sub price url: {:?}", price_ws_url);
-    let mut sub_req = Request {
-        type_field: SubType::Subscribe,
-        ids: vec![],
-        verbose: true,
-        binary: false,
-    };
-    for id in sds.iter() {
-        sub_req.ids.push(id.key().to_string());
-    }
-    let req = serde_json::to_string(&sub_req)?;
-    let ws_client = WsClient::new(
-        price_ws_url,
-        Some(WsClientMessage::Txt(req)),
-        move |msg, _send_tx| {
-            let sds = sds.clone();
-            let watch_tx = watch_tx.clone();
-            let influxdb_client = inf_db.client.clone();
-            let org = inf_db.org.clone();
-            let bucket = inf_db.bucket.clone();
-            Box::pin(async move {
-                if let WsClientMessage::Txt(txt) = msg {
-                    // debug!("price txt: {:?}", txt);
-                    let resp: Response = serde_json::from_str(&txt)?;
-                    let symbol_str = sds
-                        .get(&resp.price_feed.id)
-                        .ok_or_else(|| ClientError::UnknownSymbol)?;
-                    let op = OrgPrice {
-                        price: resp.price_feed.price.get_real_price(),
-                        update_time: resp.price_feed.price.publish_time,
-                        symbol: symbol_str.to_string(),
-                    };
-                    let watch_msg = Message {
-                        // address: Address::from_str(resp.price_feed.id.as_str())?,
-                        state: State::Price(op.clone()),
-                        event: Event::Created,
+pub type OrgPriceSender = mpsc::UnboundedSender<OrgPrice>;
+pub type OrgPriceReceiver = mpsc::UnboundedReceiver<OrgPrice>;
+
+/// A source of price ticks.
+#[async_trait]
+pub trait PriceSource: Send + Sized {
+    /// Start sending the price ticks, the source stops with the returned tasks.
+    async fn start(self, tx: OrgPriceSender) -> anyhow::Result<Vec<Task>>;
+}
+
+async fn write_price_feed(inf_db: &Influxdb, symbol: &str, feed: &PriceFeed) -> anyhow::Result<()> {
+    let db_rs = inf_db
+        .client
+        .write(
+            inf_db.org.as_str(),
+            inf_db.bucket.as_str(),
+            Precision::Seconds,
+            stream::iter(feed.get_data_points(symbol.to_string())?),
+        )
+        .await;
+    debug!("......write price resp.....: {:?}", db_rs);
+    Ok(())
+}
+
+/// The pyth websocket price feed, the full node also saves the prices to the influxdb.
+pub struct PythWsSource {
+    pub url: String,
+    pub inf_db: Influxdb,
+    pub sds: SharedDmSymbolId,
+    pub full_node: bool,
+}
+
+#[async_trait]
+impl PriceSource for PythWsSource {
+    async fn start(self, tx: OrgPriceSender) -> anyhow::Result<Vec<Task>> {
+        debug!("start sub price url: {:?}", self.url);
+        let mut sub_req = Request {
+            type_field: SubType::Subscribe,
+            ids: vec![],
+            verbose: true,
+            binary: false,
+        };
+        for id in self.sds.iter() {
+            sub_req.ids.push(id.key().to_string());
+        }
+        let req = serde_json::to_string(&sub_req)?;
+        let sds = self.sds;
+        let inf_db = self.inf_db;
+        let full_node = self.full_node;
+        let ws_client = WsClient::new(
+            self.url,
+            Some(WsClientMessage::Txt(req)),
+            move |msg, _send_tx| {
+                let sds = sds.clone();
+                let tx = tx.clone();
+                let inf_db = inf_db.clone();
+                Box::pin(async move {
+                    if let WsClientMessage::Txt(txt) = msg {
+                        // debug!("price txt: {:?}", txt);
+                        let resp: Response = serde_json::from_str(&txt)?;
+                        let symbol_str = sds
+                            .get(&resp.price_feed.id)
+                            .ok_or_else(|| ClientError::UnknownSymbol)?;
+                        let price = resp.price_feed.to_org_price(symbol_str.as_str())?;
+                        if let Err(e) = tx.send(price) {
+                            error!("send price error: {:?}", e);
+                        }
+                        if !full_node {
+                            return Ok(());
+                        }
+                        write_price_feed(&inf_db, symbol_str.as_str(), &resp.price_feed).await?;
+                    }
+                    Ok(())
+                })
+            },
+        )
+        .await?;
+        Ok(ws_client.into_tasks())
+    }
+}
+
+/// Poll the latest price feeds from the pyth price service, the same server of the vaa data.
+pub struct PythHttpSource {
+    pub url: String,
+    pub interval: Duration,
+    pub inf_db: Influxdb,
+    pub sds: SharedDmSymbolId,
+    pub full_node: bool,
+}
+
+#[async_trait]
+impl PriceSource for PythHttpSource {
+    async fn start(self, tx: OrgPriceSender) -> anyhow::Result<Vec<Task>> {
+        let (shutdown_tx, shutdown_rx) = Task::new_shutdown_channel();
+        Ok(vec![Task::new(
+            "pyth http price",
+            shutdown_tx,
+            tokio::spawn(poll_price(self, tx, shutdown_rx)),
+        )])
+    }
+}
+
+async fn get_latest_price_feeds(
+    c: &reqwest::Client,
+    price_server_url: &str,
+    feed_ids: &[String],
+) -> anyhow::Result<Vec<PriceFeed>> {
+    let base_url = format!(
+        "{}/api/latest_price_feeds",
+        price_server_url.trim_end_matches('/')
+    );
+    let mut params = vec![("verbose", "true")];
+    for id in feed_ids.iter() {
+        params.push(("ids[]", id.as_str()));
+    }
+    let url = reqwest::Url::parse_with_params(base_url.as_str(), params)?;
+    let feeds = c.get(url).send().await?.json::<Vec<PriceFeed>>().await?;
+    Ok(feeds)
+}
+
+async fn poll_price(
+    source: PythHttpSource,
+    tx: OrgPriceSender,
+    mut shutdown_rx: TaskStopRx,
+) -> anyhow::Result<()> {
+    let client = reqwest::Client::new();
+    let ids: Vec<String> = source.sds.iter().map(|v| v.key().to_string()).collect();
+    // key is feed id, value is the publish time of the last sent price
+    let mut last_publish_time: HashMap<String, i64> = HashMap::new();
+    let mut timer = time::interval(source.interval);
+    loop {
+        tokio::select! {
+            _ = &mut shutdown_rx => {
+                info!("got shutdown signal, break poll price!");
+                break;
+            }
+            _ = timer.tick() => {
+                let feeds = match get_latest_price_feeds(&client, source.url.as_str(), &ids).await {
+                    Ok(feeds) => feeds,
+                    Err(e) => {
+                        error!("get latest price feeds error: {}", e);
+                        continue;
+                    }
+                };
+                for feed in feeds {
+                    let symbol = match source.sds.get(&feed.id) {
+                        Some(s) => s.value().clone(),
+                        None => {
+                            debug!("unknown price feed id: {}", feed.id);
+                            continue;
+                        }
                     };
-                    if let Err(e) = watch_tx.send(watch_msg) {
-                        error!("send watch msg error: {:?}", e);
+                    let publish_time = feed.price.publish_time;
+                    if last_publish_time.get(&feed.id).map_or(false, |t| *t >= publish_time) {
+                        continue;
                     }
-                    if !full_node {
+                    last_publish_time.insert(feed.id.clone(), publish_time);
+                    let price = match feed.to_org_price(symbol.as_str()) {
+                        Ok(p) => p,
+                        Err(e) => {
+                            error!("invalid price feed of {}: {}", symbol, e);
+                            continue;
+                        }
+                    };
+                    if let Err(e) = tx.send(price) {
+                        error!("send price error: {:?}", e);
                         return Ok(());
                     }
-                    let _db_rs = influxdb_client
-                        .write(
-                            org.as_str(),
-                            bucket.as_str(),
-                            Precision::Seconds,
-                            stream::iter(resp.price_feed.get_data_points(symbol_str.to_string())?),
-                        )
-                        .await;
-
-                    // debug!(
-                    //     "write price to db success! {:?}",
-                    //     resp.price_feed.get_data_points(symbol_str.to_string())?
-                    // );
-                    debug!("......write price resp.....: {:?}", _db_rs);
+                    if source.full_node {
+                        // the history is only lost for this feed, the polling goes on
+                        if let Err(e) = write_price_feed(&source.inf_db, symbol.as_str(), &feed).await {
+                            error!("write price feed of {} error: {}", symbol, e);
+                        }
+                    }
                 }
-                Ok(())
-            })
-        },
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Replay the prices of a file, one json `OrgPrice` per line, for testing and backfilling.
+pub struct ReplaySource {
+    pub path: PathBuf,
+    pub interval: Duration,
+}
+
+#[async_trait]
+impl PriceSource for ReplaySource {
+    async fn start(self, tx: OrgPriceSender) -> anyhow::Result<Vec<Task>> {
+        let content = tokio::fs::read_to_string(&self.path).await?;
+        let (shutdown_tx, shutdown_rx) = Task::new_shutdown_channel();
+        Ok(vec![Task::new(
+            "replay price",
+            shutdown_tx,
+            tokio::spawn(replay_price(content, self.interval, tx, shutdown_rx)),
+        )])
+    }
+}
+
+async fn replay_price(
+    content: String,
+    interval: Duration,
+    tx: OrgPriceSender,
+    mut shutdown_rx: TaskStopRx,
+) -> anyhow::Result<()> {
+    for (i, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let price: OrgPrice = match serde_json::from_str(line) {
+            Ok(p) => p,
+            Err(e) => {
+                error!("invalid replay price at line {}: {}", i + 1, e);
+                continue;
+            }
+        };
+        if let Err(e) = tx.send(price) {
+            error!("send price error: {:?}", e);
+            return Ok(());
+        }
+        tokio::select! {
+            _ = &mut shutdown_rx => {
+                info!("got shutdown signal, break replay price!");
+                return Ok(());
+            }
+            _ = time::sleep(interval) => {}
+        }
+    }
+    info!("replay price finished");
+    Ok(())
+}
+
+async fn start_price_source(
+    kind: &PriceSourceKind,
+    price_config: &PriceConfig,
+    inf_db: Influxdb,
+    sds: SharedDmSymbolId,
+    full_node: bool,
+    tx: OrgPriceSender,
+) -> anyhow::Result<Vec<Task>> {
+    let sc = &price_config.source;
+    info!("start price source: {:?}", kind);
+    match kind {
+        PriceSourceKind::PythWs => {
+            PythWsSource {
+                url: price_config.ws_url.clone(),
+                inf_db,
+                sds,
+                full_node,
+            }
+            .start(tx)
+            .await
+        }
+        PriceSourceKind::PythHttp => {
+            PythHttpSource {
+                url: price_config.price_server_url.clone(),
+                interval: Duration::from_millis(sc.poll_interval_ms.max(1)),
+                inf_db,
+                sds,
+                full_node,
+            }
+            .start(tx)
+            .await
+        }
+        PriceSourceKind::Replay => {
+            let path = sc.replay_file.clone().ok_or_else(|| {
+                ClientError::ConfigError("replay_file of the replay price source".to_string())
+            })?;
+            ReplaySource {
+                path,
+                interval: Duration::from_millis(sc.replay_interval_ms),
+            }
+            .start(tx)
+            .await
+        }
+    }
+}
+
+/// The price sources and the task sending their prices to the watch.
+pub struct PriceSub {
+    tasks: Vec<Task>,
+}
+
+impl PriceSub {
+    pub fn into_tasks(self) -> Vec<Task> {
+        self.tasks
+    }
+}
+
+pub async fn sub_price(
+    ssm: SharedStateMap,
+    watch_tx: MessageSender,
+    price_config: PriceConfig,
+    inf_db: Influxdb,
+    sds: SharedDmSymbolId,
+    full_node: bool,
+) -> anyhow::Result<PriceSub> {
+    let sc = price_config.source.clone();
+    let (primary_tx, primary_rx) = mpsc::unbounded_channel();
+    let mut tasks = start_price_source(
+        &sc.primary,
+        &price_config,
+        inf_db.clone(),
+        sds.clone(),
+        full_node,
+        primary_tx,
     )
     .await?;
-    Ok(ws_client)
+    let fallback_rx = match &sc.fallback {
+        Some(kind) => {
+            let (fallback_tx, fallback_rx) = mpsc::unbounded_channel();
+            tasks.extend(
+                start_price_source(kind, &price_config, inf_db, sds, full_node, fallback_tx)
+                    .await?,
+            );
+            Some(fallback_rx)
+        }
+        None => None,
+    };
+    let (shutdown_tx, shutdown_rx) = Task::new_shutdown_channel();
+    tasks.push(Task::new(
+        "price router",
+        shutdown_tx,
+        tokio::spawn(route_price(
+            ssm,
+            PriceGuard::new(&price_config),
+            watch_tx,
+            (primary_rx, sc.primary != PriceSourceKind::Replay),
+            fallback_rx.map(|rx| (rx, sc.fallback != Some(PriceSourceKind::Replay))),
+            Duration::from_secs(sc.stale_secs),
+            shutdown_rx,
+        )),
+    ));
+    Ok(PriceSub { tasks })
+}
+
+async fn recv_fallback(rx: &mut Option<(OrgPriceReceiver, bool)>) -> Option<OrgPrice> {
+    match rx {
+        Some((rx, _)) => rx.recv().await,
+        None => future::pending().await,
+    }
+}
+
+fn send_price(watch_tx: &MessageSender, price: OrgPrice) {
+    let watch_msg = Message {
+        state: State::Price(price),
+        event: Event::Created,
+    };
+    if let Err(e) = watch_tx.send(watch_msg) {
+        error!("send watch msg error: {:?}", e);
+    }
+}
+
+// The prices of the primary source are always sent, the prices of the fallback source are
+// only sent for the symbols the primary source has no accepted price of for the stale duration.
+// The prices rejected by the guard are dropped. The flag of a source is whether the age
+// of its prices is checked, the replayed prices are old.
+async fn route_price(
+    ssm: SharedStateMap,
+    mut guard: PriceGuard,
+    watch_tx: MessageSender,
+    (mut primary_rx, primary_live): (OrgPriceReceiver, bool),
+    mut fallback_rx: Option<(OrgPriceReceiver, bool)>,
+    stale: Duration,
+    mut shutdown_rx: TaskStopRx,
+) -> anyhow::Result<()> {
+    let fallback_live = fallback_rx.as_ref().map_or(true, |(_, live)| *live);
+    let mut sweep = time::interval(PRICE_SWEEP_INTERVAL);
+    let start = Instant::now();
+    // key is symbol, value is the time of the last primary price
+    let mut last_primary: HashMap<String, Instant> = HashMap::new();
+    // the symbols using the fallback source
+    let mut fallback_symbols: HashSet<String> = HashSet::new();
+    loop {
+        tokio::select! {
+            _ = &mut shutdown_rx => {
+                info!("got shutdown signal, break price router!");
+                break;
+            }
+            _ = sweep.tick() => {
+                guard.sweep(&ssm.price_health, Utc::now().timestamp());
+            }
+            Some(price) = primary_rx.recv() => {
+                // a rejected price does not count, the fallback source takes over
+                if !guard.guard(&ssm.price_health, &price, primary_live) {
+                    continue;
+                }
+                if fallback_symbols.remove(&price.symbol) {
+                    info!("primary price source of {} recovered", price.symbol);
+                }
+                last_primary.insert(price.symbol.clone(), Instant::now());
+                send_price(&watch_tx, price);
+            }
+            Some(price) = recv_fallback(&mut fallback_rx) => {
+                let last = last_primary.get(&price.symbol).copied().unwrap_or(start);
+                if last.elapsed() < stale {
+                    continue;
+                }
+                if fallback_symbols.insert(price.symbol.clone()) {
+                    warn!(
+                        "primary price source of {} is stale for {:?}, use the fallback source",
+                        price.symbol,
+                        last.elapsed()
+                    );
+                }
+                if guard.guard(&ssm.price_health, &price, fallback_live) {
+                    send_price(&watch_tx, price);
+                }
+            }
+        }
+    }
+    Ok(())
 }
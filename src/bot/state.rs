@@ -165,6 +165,7 @@ pub fn new_event_sync_channel() -> (EventSyncTx, EventSyncRx) {
 #[derive(Debug, Clone)]
 pub enum EventUpdate {
     AccountUpdate(Account),
+    AccountDeleted(Address),
     PositionUpdate(Position),
     Price(OrgPrice),
 }
@@ -263,17 +264,25 @@ pub struct Market {
     pub list_id: Address,
 }
 impl Market {
-    pub fn get_price(&self, real_price: u64) -> Price {
-        let spread = self.get_spread_fee(real_price) * real_price;
+    pub fn get_price(&self, real_price: FixedPrice) -> anyhow::Result<Price> {
+        let real_price = real_price.to_u64()?;
+        let spread = self.get_spread_fee(real_price) as u128 * real_price as u128;
         // To increase the calculation accuracy
         let half_spread = spread / 2;
-        Price {
-            buy_price: (real_price * DENOMINATOR + half_spread) / DENOMINATOR,
-            sell_price: (real_price * DENOMINATOR - half_spread) / DENOMINATOR,
-            real_price: real_price,
-            spread: spread as u64,
+        let price = real_price as u128 * DENOMINATOR as u128;
+        let sell_price = price.checked_sub(half_spread).ok_or_else(|| {
+            com::ClientError::ArithmeticOverflow(format!(
+                "spread {} is larger than the price {}",
+                spread, real_price
+            ))
+        })?;
+        Ok(Price {
+            buy_price: to_u64((price + half_spread) / DENOMINATOR as u128, "buy price")?,
+            sell_price: to_u64(sell_price / DENOMINATOR as u128, "sell price")?,
+            real_price,
+            spread: to_u64(spread, "spread")?,
             update_time: chrono::Utc::now().timestamp(),
-        }
+        })
     }
     pub fn get_spread_fee(&self, real_price: u64) -> u64 {
         if self.spread_fee_manual {
@@ -283,12 +292,12 @@ impl Market {
         if self.opening_price == 0 {
             return 150;
         };
-        let change = change_price * DENOMINATOR / self.opening_price;
+        let change = change_price as u128 * DENOMINATOR as u128 / self.opening_price as u128;
         if change <= 300 {
             return 30;
         };
         if change > 300 && change <= 1000 {
-            return change / 10;
+            return (change / 10) as u64;
         };
         return 150;
     }
@@ -324,7 +333,7 @@ impl Market {
         if exposure == 0 || total_liquidity == 0 {
             return 0;
         };
-        let exposure_rate = exposure * DENOMINATOR / total_liquidity;
+        let exposure_rate = exposure as u128 * DENOMINATOR as u128 / total_liquidity as u128;
         if exposure_rate <= 1000 {
             return 3;
         };
@@ -346,6 +355,16 @@ impl Market {
         return 70;
     }
 }
+fn overflow(msg: String) -> anyhow::Error {
+    com::ClientError::ArithmeticOverflow(msg).into()
+}
+fn to_u64(v: u128, name: &str) -> anyhow::Result<u64> {
+    u64::try_from(v).map_err(|_| overflow(format!("{} {}", name, v)))
+}
+fn to_i64<T: TryInto<i64> + fmt::Display + Copy>(v: T, name: &str) -> anyhow::Result<i64> {
+    v.try_into()
+        .map_err(|_| overflow(format!("{} {}", name, v)))
+}
 #[derive(Clone, Debug, TryFromPrimitive, PartialEq, Deserialize, Serialize)]
 #[repr(u8)]
 pub enum MarketStatus {
@@ -432,6 +451,9 @@ pub struct Position {
     pub close_real_price: u64,
     // PL
     pub profit: i64,
+    /// Limit price of a pending position, the position is opened once the market reaches it
+    #[serde(default)]
+    pub auto_open_price: u64,
     /// Automatic profit stop price
     pub stop_surplus_price: u64,
     /// Automatic stop loss price
@@ -451,64 +473,91 @@ pub struct Position {
     pub force_close_price: i64,
 }
 
+// The position math follows the contract: the products are computed in u128
+// and every division truncates, in the same order as the contract does.
 impl Position {
-    pub fn get_fund_size(&self) -> u64 {
+    pub fn get_fund_size(&self) -> anyhow::Result<u64> {
         Self::fund_size(self.unit_size, self.lot, self.open_real_price)
     }
 
-    fn fund_size(size: u64, lot: u64, price: u64) -> u64 {
-        size * (lot / com::DENOMINATOR128) * price
+    fn fund_size(size: u64, lot: u64, price: u64) -> anyhow::Result<u64> {
+        let fund_size = (size as u128 * lot as u128)
+            .checked_mul(price as u128)
+            .ok_or_else(|| overflow(format!("fund size {} * {} * {}", size, lot, price)))?;
+        to_u64(fund_size / com::DENOMINATOR128 as u128, "fund size")
     }
 
-    pub fn get_size(&self) -> u64 {
+    pub fn get_size(&self) -> anyhow::Result<u64> {
         Self::size(self.lot, self.unit_size)
     }
 
-    fn size(lot: u64, size: u64) -> u64 {
-        size * (lot / com::DENOMINATOR128)
+    fn size(lot: u64, size: u64) -> anyhow::Result<u64> {
+        to_u64(
+            size as u128 * lot as u128 / com::DENOMINATOR128 as u128,
+            "size",
+        )
     }
 
-    pub fn get_margin_size(&self, market: &Market) -> u64 {
+    pub fn get_margin_size(&self, market: &Market) -> anyhow::Result<u64> {
         Self::margin_size(
-            self.get_fund_size(),
+            self.get_fund_size()?,
             self.leverage as u64,
             market.margin_fee,
         )
     }
 
-    fn margin_size(fund_size: u64, leverage: u64, margin_fee: u64) -> u64 {
-        fund_size / leverage * margin_fee / DENOMINATOR
+    fn margin_size(fund_size: u64, leverage: u64, margin_fee: u64) -> anyhow::Result<u64> {
+        if leverage == 0 {
+            return Err(overflow("margin size with 0 leverage".to_string()));
+        }
+        to_u64(
+            (fund_size / leverage) as u128 * margin_fee as u128 / DENOMINATOR as u128,
+            "margin size",
+        )
     }
 
     /// get Floating P/L
-    pub fn get_pl(&self, price: &Price) -> i64 {
-        if self.direction == Direction::Buy {
-            Self::fund_size(self.unit_size, self.lot, price.sell_price) as i64
-                - self.get_fund_size() as i64
+    pub fn get_pl(&self, price: &Price) -> anyhow::Result<i64> {
+        let open = self.get_fund_size()? as i128;
+        let pl = if self.direction == Direction::Buy {
+            Self::fund_size(self.unit_size, self.lot, price.sell_price)? as i128 - open
         } else {
-            self.get_fund_size() as i64
-                - Self::fund_size(self.unit_size, self.lot, price.buy_price) as i64
-        }
+            open - Self::fund_size(self.unit_size, self.lot, price.buy_price)? as i128
+        };
+        to_i64(pl, "P/L")
     }
 
-    pub fn get_position_fund_fee(&self, market: &Market) -> i64 {
+    pub fn get_position_fund_fee(&self, market: &Market) -> anyhow::Result<i64> {
         let dominant_direction = market.get_dominant_direction();
         if dominant_direction == Direction::Flat {
-            return 0;
+            return Ok(0);
         };
+        let fund_size = self.get_fund_size()? as u128;
+        let fund_fee = market.get_fund_fee() as u128;
         if self.direction == dominant_direction {
-            -((self.get_fund_size() * market.get_fund_fee() / com::DENOMINATOR) as i64)
+            let r = fund_size * fund_fee / com::DENOMINATOR as u128;
+            to_i64(r, "fund fee").map(|r| -r)
         } else {
-            let max = market.long_position_total.max(market.short_position_total);
-            let min = market.long_position_total.min(market.short_position_total);
+            let max = market.long_position_total.max(market.short_position_total) as u128;
+            let min = market.long_position_total.min(market.short_position_total) as u128;
             if min == 0 {
-                return 0;
+                return Ok(0);
             }
-            // todo check overflow
-            let r = max * market.get_fund_fee() / com::DENOMINATOR * self.get_fund_size() / min;
-            r as i64
+            // max * fund_fee fits u128, the product with the fund size may not
+            let r = (max * fund_fee / com::DENOMINATOR as u128)
+                .checked_mul(fund_size)
+                .ok_or_else(|| overflow(format!("fund fee of position {}", self.id)))?;
+            to_i64(r / min, "fund fee")
         }
     }
+
+    /// The floating P/L plus the fund fee of the position.
+    pub fn get_pl_and_fund_fee(&self, price: &Price, market: &Market) -> anyhow::Result<i64> {
+        let pl = self.get_pl(price)?;
+        let fund_fee = self.get_position_fund_fee(market)?;
+        pl.checked_add(fund_fee)
+            .ok_or_else(|| overflow(format!("P/L {} + fund fee {}", pl, fund_fee)))
+    }
 }
 #[derive(Clone, Debug, TryFromPrimitive, PartialEq, Deserialize, Serialize)]
 #[repr(u8)]
@@ -554,11 +603,73 @@ pub struct Price {
     pub spread: u64,
     pub update_time: i64,
 }
+/// A price with DECIMALS_EXPO fixed decimal places, e.g. 1.5 is 1500000.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize,
+)]
+#[serde(transparent)]
+pub struct FixedPrice(i64);
+
+impl FixedPrice {
+    pub const fn from_raw(raw: i64) -> Self {
+        Self(raw)
+    }
+
+    pub fn raw(&self) -> i64 {
+        self.0
+    }
+
+    /// Scale a price given as mantissa * 10^expo, as the pyth prices are.
+    /// The digits beyond the fixed decimal places are truncated toward zero.
+    pub fn from_mantissa(mantissa: i64, expo: i64) -> anyhow::Result<Self> {
+        let scale = com::DECIMALS_EXPO as i64 + expo;
+        let pow = |n: i64| u32::try_from(n).ok().and_then(|n| 10i128.checked_pow(n));
+        let v = if scale >= 0 {
+            pow(scale).and_then(|p| (mantissa as i128).checked_mul(p))
+        } else {
+            // a divisor beyond i128 truncates any mantissa to 0
+            Some(pow(-scale).map_or(0, |p| mantissa as i128 / p))
+        };
+        v.and_then(|v| i64::try_from(v).ok())
+            .map(Self)
+            .ok_or_else(|| {
+                com::ClientError::ArithmeticOverflow(format!("price {}e{}", mantissa, expo)).into()
+            })
+    }
+
+    pub fn to_u64(&self) -> anyhow::Result<u64> {
+        u64::try_from(self.0).map_err(|_| {
+            com::ClientError::ArithmeticOverflow(format!("negative price {}", self.0)).into()
+        })
+    }
+}
+
+impl fmt::Display for FixedPrice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let v = self.0.unsigned_abs();
+        write!(
+            f,
+            "{}{}.{:0width$}",
+            sign,
+            v / com::DECIMALS,
+            v % com::DECIMALS,
+            width = com::DECIMALS_EXPO as usize
+        )
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct OrgPrice {
-    pub price: i64,
+    pub price: FixedPrice,
     pub update_time: i64,
     pub symbol: String,
+    /// The confidence interval of the price, 0 if the source has none
+    #[serde(default)]
+    pub conf: FixedPrice,
+    /// The exponential moving average price, 0 if the source has none
+    #[serde(default)]
+    pub ema_price: FixedPrice,
 }
 #[derive(Debug, Clone)]
 pub struct PositionParams {
@@ -566,37 +677,338 @@ pub struct PositionParams {
     pub position_type: u8,
     pub symbol: String,
 }
+/// The result of an executed transaction.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TxOutcome {
+    pub digest: String,
+    pub status: TxStatus,
+    pub computation_cost: u64,
+    pub storage_cost: u64,
+    pub storage_rebate: u64,
+    pub created: Vec<Address>,
+    pub mutated: Vec<Address>,
+    pub deleted: Vec<Address>,
+    pub balance_changes: Vec<BalanceChange>,
+    /// The transaction was dry run only, nothing is on chain
+    pub simulated: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BalanceChange {
+    pub owner: String,
+    pub coin_type: String,
+    pub amount: i128,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub enum TxStatus {
+    #[default]
+    Success,
+    Failure(String),
+}
+
+impl TxOutcome {
+    pub fn is_success(&self) -> bool {
+        self.status == TxStatus::Success
+    }
+
+    /// The gas paid, the storage rebate is deducted and may exceed the costs.
+    pub fn net_gas_cost(&self) -> i64 {
+        self.computation_cost as i64 + self.storage_cost as i64 - self.storage_rebate as i64
+    }
+}
+
+impl fmt::Display for TxOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.simulated {
+            write!(f, "(dry run) ")?;
+        }
+        match &self.status {
+            TxStatus::Success => write!(f, "{} success", self.digest)?,
+            TxStatus::Failure(e) => write!(f, "{} failure: {}", self.digest, e)?,
+        }
+        write!(
+            f,
+            ", gas: {}, created: {}, mutated: {}, deleted: {}",
+            self.net_gas_cost(),
+            self.created.len(),
+            self.mutated.len(),
+            self.deleted.len()
+        )
+    }
+}
 #[async_trait]
 pub trait MoveCall {
-    async fn trigger_update_opening_price(&self, symbol: String) -> anyhow::Result<()>;
+    async fn trigger_update_opening_price(&self, market_id: String) -> anyhow::Result<TxOutcome>;
     async fn auto_close_position(
         &self,
         account_id: Address,
         position: PositionParams,
-    ) -> anyhow::Result<()>;
+    ) -> anyhow::Result<TxOutcome>;
     async fn force_liquidation(
         &self,
         account_id: Address,
         position: PositionParams,
-    ) -> anyhow::Result<()>;
+    ) -> anyhow::Result<TxOutcome>;
     async fn open_limit_position(
         &self,
         account_id: Address,
         position: PositionParams,
-    ) -> anyhow::Result<()>;
-    async fn process_fund_fee(&self, account_id: Address) -> anyhow::Result<()>;
-    async fn get_price(&self, symbol: &str) -> anyhow::Result<()>;
-    async fn receive_award(&self, nft: String) -> anyhow::Result<()>;
-    async fn receive_reward(&self) -> anyhow::Result<()>;
+    ) -> anyhow::Result<TxOutcome>;
+    async fn process_fund_fee(&self, account_id: Address) -> anyhow::Result<TxOutcome>;
+    async fn get_price(&self, symbol: &str) -> anyhow::Result<TxOutcome>;
+    /// Update the on chain pyth prices of the feeds in one transaction,
+    /// paying `update_fee` of pyth fee per feed.
+    async fn update_pyth_price(
+        &self,
+        feed_ids: Vec<String>,
+        update_fee: u64,
+    ) -> anyhow::Result<TxOutcome>;
+    async fn receive_award(&self, nft: String) -> anyhow::Result<TxOutcome>;
+    async fn receive_reward(&self) -> anyhow::Result<TxOutcome>;
 }
 #[async_trait]
 pub trait Storage {
     async fn save_one(&self, state: State) -> anyhow::Result<()>;
     async fn load_all(&self, sender: MessageSender) -> anyhow::Result<()>;
+    /// Make sure all the saved states are persisted, called before the bot exits.
+    async fn flush(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
 }
 #[cfg(test)]
 mod tests {
-    // use super::*;
+    use super::*;
+    use proptest::prelude::*;
+    use rust_decimal::prelude::ToPrimitive;
+    use rust_decimal::Decimal;
+
     #[test]
     fn test_str_to_address() {}
+
+    fn market(long_total: u64, short_total: u64) -> Market {
+        Market {
+            id: Address::default(),
+            max_leverage: 125,
+            insurance_fee: 5,
+            margin_fee: 10000,
+            fund_fee: 10,
+            fund_fee_manual: true,
+            spread_fee: 30,
+            spread_fee_manual: true,
+            status: MarketStatus::Normal,
+            long_position_total: long_total,
+            short_position_total: short_total,
+            symbol: "Crypto.BTC/USD".to_string(),
+            symbol_short: "BTC/USD".to_string(),
+            icon: String::new(),
+            description: String::new(),
+            unit_size: 1,
+            opening_price: 0,
+            list_id: Address::default(),
+        }
+    }
+
+    fn position(direction: Direction, lot: u64, open_real_price: u64) -> Position {
+        Position {
+            id: Address::default(),
+            offset: 0,
+            margin: 0,
+            margin_balance: 0,
+            leverage: 10,
+            position_type: PositionType::Cross,
+            status: PositionStatus::Normal,
+            direction,
+            unit_size: 1,
+            lot,
+            open_price: open_real_price,
+            open_spread: 0,
+            open_real_price,
+            close_price: 0,
+            close_spread: 0,
+            close_real_price: 0,
+            profit: 0,
+            auto_open_price: 0,
+            stop_surplus_price: 0,
+            stop_loss_price: 0,
+            create_time: 0,
+            open_time: 0,
+            close_time: 0,
+            open_operator: Address::default(),
+            close_operator: Address::default(),
+            market_id: Address::default(),
+            account_id: Address::default(),
+            symbol: "Crypto.BTC/USD".to_string(),
+            force_close_price: 0,
+        }
+    }
+
+    fn price(buy_price: u64, sell_price: u64) -> Price {
+        Price {
+            buy_price,
+            sell_price,
+            real_price: (buy_price + sell_price) / 2,
+            spread: 0,
+            update_time: 0,
+        }
+    }
+
+    #[test]
+    fn test_fixed_price_reference_vectors() {
+        // (mantissa, expo, fixed price)
+        let vectors = [
+            (2_712_345_678_901i64, -8, Some(27_123_456_789)),
+            (-2_712_345_678_901, -8, Some(-27_123_456_789)),
+            (15, -1, Some(1_500_000)),
+            (15, 2, Some(1_500_000_000)),
+            (1, -7, Some(0)),
+            (1, -100, Some(0)),
+            (i64::MAX, -6, Some(i64::MAX)),
+            (i64::MAX, -5, None),
+            (1, 100, None),
+        ];
+        for (mantissa, expo, expected) in vectors {
+            let rs = FixedPrice::from_mantissa(mantissa, expo)
+                .ok()
+                .map(|p| p.raw());
+            assert_eq!(rs, expected, "{}e{}", mantissa, expo);
+        }
+        assert_eq!(FixedPrice::from_raw(-1_500_000).to_string(), "-1.500000");
+        assert!(FixedPrice::from_raw(-1).to_u64().is_err());
+    }
+
+    #[test]
+    fn test_position_reference_vectors() {
+        // (size, lot, price, fund size)
+        let vectors = [
+            (1u64, 10000u64, 30_000_000_000u64, Some(30_000_000_000u64)),
+            // a fractional lot is not truncated
+            (1, 5000, 2_000_000, Some(1_000_000)),
+            (1, 1, 1, Some(0)),
+            (u64::MAX, 10000, 2, None),
+            (u64::MAX, u64::MAX, 2, None),
+        ];
+        for (size, lot, price, expected) in vectors {
+            let rs = Position::fund_size(size, lot, price).ok();
+            assert_eq!(rs, expected, "{} {} {}", size, lot, price);
+        }
+        // (fund size, leverage, margin fee, margin size)
+        let vectors = [
+            (1_000_000u64, 10u64, 10000u64, Some(100_000u64)),
+            (1_000_005, 10, 5000, Some(50_000)),
+            (u64::MAX, 1, 20000, None),
+            (1_000_000, 0, 10000, None),
+        ];
+        for (fund_size, leverage, margin_fee, expected) in vectors {
+            let rs = Position::margin_size(fund_size, leverage, margin_fee).ok();
+            assert_eq!(rs, expected, "{} {} {}", fund_size, leverage, margin_fee);
+        }
+
+        let long = position(Direction::Buy, 10000, 1_000_000);
+        let short = position(Direction::Sell, 10000, 1_000_000);
+        let p = price(1_100_000, 1_050_000);
+        assert_eq!(long.get_pl(&p).unwrap(), 50_000);
+        assert_eq!(short.get_pl(&p).unwrap(), -100_000);
+
+        let m = market(3_000_000, 1_000_000);
+        assert_eq!(long.get_position_fund_fee(&m).unwrap(), -1000);
+        assert_eq!(short.get_position_fund_fee(&m).unwrap(), 3000);
+        assert_eq!(long.get_pl_and_fund_fee(&p, &m).unwrap(), 49_000);
+        assert_eq!(short.get_position_fund_fee(&market(1, 1)).unwrap(), 0);
+        assert_eq!(short.get_position_fund_fee(&market(1, 0)).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_market_reference_vectors() {
+        let m = market(0, 0);
+        let p = m.get_price(FixedPrice::from_raw(1_000_000)).unwrap();
+        assert_eq!(
+            (p.buy_price, p.sell_price, p.spread),
+            (1_001_500, 998_500, 30_000_000)
+        );
+        let mut m = market(0, 0);
+        m.spread_fee = 20001;
+        assert!(m.get_price(FixedPrice::from_raw(1_000_000)).is_err());
+
+        let mut m = market(0, 0);
+        m.spread_fee_manual = false;
+        m.opening_price = 100;
+        // (real price, spread fee)
+        for (real_price, expected) in [(102u64, 30u64), (105, 50), (120, 150), (u64::MAX, 150)] {
+            assert_eq!(m.get_spread_fee(real_price), expected, "{}", real_price);
+        }
+        m.opening_price = 0;
+        assert_eq!(m.get_spread_fee(100), 150);
+    }
+
+    fn decimal_fund_size(size: u64, lot: u64, price: u64) -> Option<u64> {
+        (Decimal::from(size) * Decimal::from(lot) * Decimal::from(price) / Decimal::from(10000))
+            .trunc()
+            .to_u64()
+    }
+
+    proptest! {
+        #[test]
+        fn prop_fund_size_matches_decimal(
+            size in 0u64..10_000,
+            lot in 0u64..10_000_000_000,
+            price in 0u64..1_000_000_000_000,
+        ) {
+            prop_assert_eq!(
+                Position::fund_size(size, lot, price).ok(),
+                decimal_fund_size(size, lot, price)
+            );
+        }
+
+        #[test]
+        fn prop_fund_size_checked(size: u64, lot: u64, price: u64) {
+            let wide = size as u128 * lot as u128;
+            match Position::fund_size(size, lot, price) {
+                Ok(v) => prop_assert_eq!(v as u128, wide * price as u128 / 10000),
+                Err(_) => prop_assert!(
+                    wide.checked_mul(price as u128).map_or(true, |v| v / 10000 > u64::MAX as u128)
+                ),
+            }
+        }
+
+        #[test]
+        fn prop_fixed_price_matches_decimal(mantissa: i64, expo in -20i64..20) {
+            let scale = 6 + expo;
+            let expected = if scale >= 0 {
+                Decimal::from(mantissa).checked_mul(Decimal::from_i128_with_scale(
+                    10i128.pow(scale as u32),
+                    0,
+                ))
+            } else {
+                Some(Decimal::new(mantissa, (-scale) as u32).trunc())
+            };
+            prop_assert_eq!(
+                FixedPrice::from_mantissa(mantissa, expo).ok().map(|p| p.raw()),
+                expected.and_then(|d| d.to_i64())
+            );
+        }
+
+        #[test]
+        fn prop_price_spread_around_real_price(real_price in 1i64..i64::MAX, spread_fee in 0u64..10000) {
+            let mut m = market(0, 0);
+            m.spread_fee = spread_fee;
+            let rs = m.get_price(FixedPrice::from_raw(real_price));
+            // the spread of a huge price does not fit in u64
+            if spread_fee as u128 * real_price as u128 > u64::MAX as u128 {
+                prop_assert!(rs.is_err());
+                return Ok(());
+            }
+            let p = rs.unwrap();
+            prop_assert!(p.sell_price <= p.real_price && p.real_price <= p.buy_price);
+            prop_assert!((p.buy_price - p.sell_price) as u128 <= p.spread as u128 / 10000 + 1);
+        }
+
+        #[test]
+        fn prop_pl_is_antisymmetric(lot: u32, open_price: u32, close_price: u32) {
+            let long = position(Direction::Buy, lot as u64, open_price as u64);
+            let short = position(Direction::Sell, lot as u64, open_price as u64);
+            let p = price(close_price as u64, close_price as u64);
+            prop_assert_eq!(long.get_pl(&p).unwrap(), -short.get_pl(&p).unwrap());
+        }
+    }
 }
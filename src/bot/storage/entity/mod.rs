@@ -284,6 +284,8 @@ pub struct DbPosition {
     pub close_real_price: i64,
     // PL
     pub profit: i64,
+    /// Limit price of a pending position
+    pub auto_open_price: i64,
     /// Automatic profit stop price
     pub stop_surplus_price: i64,
     /// Automatic stop loss price
@@ -322,6 +324,7 @@ impl From<Position> for DbPosition {
             close_spread: value.close_spread as i64,
             close_real_price: value.close_real_price as i64,
             profit: value.profit as i64,
+            auto_open_price: value.auto_open_price as i64,
             stop_surplus_price: value.stop_surplus_price as i64,
             stop_loss_price: value.stop_loss_price as i64,
             create_time: value.create_time as i64,
@@ -357,6 +360,7 @@ impl From<DbPosition> for Position {
             close_spread: value.close_spread as u64,
             close_real_price: value.close_real_price as u64,
             profit: value.profit,
+            auto_open_price: value.auto_open_price as u64,
             stop_surplus_price: value.stop_surplus_price as u64,
             stop_loss_price: value.stop_loss_price as u64,
             create_time: value.create_time as u64,
@@ -371,3 +375,24 @@ impl From<DbPosition> for Position {
         }
     }
 }
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, sqlx::FromRow)]
+pub struct DbProfitSummary {
+    /// The number of positions
+    pub positions: i64,
+    /// Realized P/L of the positions
+    pub profit: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, sqlx::FromRow)]
+pub struct DbMarketStats {
+    pub symbol: String,
+    /// The number of positions
+    pub positions: i64,
+    /// Total lot of the positions
+    pub lot: i64,
+    /// Total opening value of the positions
+    pub volume: Decimal,
+    /// Realized P/L of the positions
+    pub profit: i64,
+}
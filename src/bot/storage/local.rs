@@ -98,6 +98,15 @@ impl Storage for Local {
         }
         Ok(())
     }
+    async fn flush(&self) -> anyhow::Result<()> {
+        let size = self
+            .db
+            .flush_async()
+            .await
+            .map_err(|e| com::ClientError::DBError(e.to_string()))?;
+        debug!("flush local storage, {} bytes", size);
+        Ok(())
+    }
 }
 impl Local {
     pub fn new(store_path: PathBuf) -> anyhow::Result<Self> {
@@ -1,16 +1,57 @@
 use crate::{
-    bot::state::{Account, List, Market, MessageSender, Event,Message, Position, State, Storage},
-    bot::storage::entity::{DbAccount, DbList, DbMarket, DbPosition},
+    bot::state::{Account, Event, List, Market, Message, MessageSender, Position, State, Storage},
+    bot::storage::entity::{
+        DbAccount, DbList, DbMarket, DbMarketStats, DbPosition, DbProfitSummary,
+    },
     com::ClientError,
     config::SqlDbConfig,
 };
 use anyhow::Ok;
 use async_trait::async_trait;
-use sqlx::postgres::{PgPool, PgPoolOptions};
+use chrono::Utc;
+use sqlx::postgres::{PgPool, PgPoolOptions, Postgres};
+use sqlx::QueryBuilder;
 pub struct PG {
     db: PgPool,
 }
 
+/// Filter of the position history queries, the time range is on `close_time`.
+/// Without a status, only the closed positions (not normal or pending) are matched.
+#[derive(Debug, Clone, Default)]
+pub struct PositionQuery {
+    pub account_id: Option<String>,
+    pub symbol: Option<String>,
+    pub status: Option<i16>,
+    pub start_time: Option<i64>,
+    pub end_time: Option<i64>,
+    pub offset: i64,
+    pub limit: i64,
+}
+
+fn push_position_filter<'a>(qb: &mut QueryBuilder<'a, Postgres>, q: &'a PositionQuery) {
+    qb.push(" WHERE 1 = 1");
+    if let Some(account_id) = &q.account_id {
+        qb.push(" AND account_id = ").push_bind(account_id);
+    }
+    if let Some(symbol) = &q.symbol {
+        qb.push(" AND symbol = ").push_bind(symbol);
+    }
+    match q.status {
+        Some(status) => {
+            qb.push(" AND status = ").push_bind(status);
+        }
+        None => {
+            qb.push(" AND status NOT IN (1, 4)");
+        }
+    }
+    if let Some(start_time) = q.start_time {
+        qb.push(" AND close_time >= ").push_bind(start_time);
+    }
+    if let Some(end_time) = q.end_time {
+        qb.push(" AND close_time < ").push_bind(end_time);
+    }
+}
+
 pub async fn new(conf: SqlDbConfig) -> anyhow::Result<PG> {
     let db = PgPoolOptions::new()
         .max_connections(conf.pool_max_conn)
@@ -66,6 +107,33 @@ impl PG {
         .await?;
         Ok(())
     }
+    /// The last checkpoint fully handled by the indexer of the name.
+    pub async fn get_indexer_checkpoint(&self, name: &str) -> anyhow::Result<Option<u64>> {
+        let rs = sqlx::query_scalar!(
+            r#"
+            SELECT checkpoint FROM tb_indexer_progress WHERE name = $1
+            "#,
+            name
+        )
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(rs.map(|c| c as u64))
+    }
+    pub async fn save_indexer_checkpoint(&self, name: &str, checkpoint: u64) -> anyhow::Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO tb_indexer_progress (name,checkpoint,update_time)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (name) DO UPDATE SET checkpoint = $2, update_time = $3
+            "#,
+            name,
+            checkpoint as i64,
+            Utc::now().timestamp()
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
     pub async fn save_market(&self, data: Market) -> anyhow::Result<()> {
         let ins: DbMarket = data.into();
         sqlx::query!(
@@ -81,7 +149,7 @@ impl PG {
             ins.fund_fee,
             ins.fund_fee_manual,
             ins.spread_fee,
-            ins.spread_fee_manual, 
+            ins.spread_fee_manual,
             ins.status,
             ins.long_position_total,
             ins.short_position_total,
@@ -125,9 +193,9 @@ impl PG {
         let ins: DbPosition = data.into();
         sqlx::query!(
             r#"
-            INSERT INTO tb_position (id, offset_idx, margin, margin_balance, leverage, position_type, status, direction, unit_size, lot, open_price, open_spread, open_real_price, close_price, close_spread, close_real_price, profit, stop_surplus_price, stop_loss_price, create_time, open_time, close_time, open_operator, close_operator, market_id, account_id, symbol, force_close_price)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10,$11,$12,$13,$14,$15,$16,$17,$18,$19,$20,$21,$22,$23,$24,$25,$26,$27,$28)
-            ON CONFLICT (id) DO UPDATE SET offset_idx = $2, margin = $3, margin_balance = $4, leverage = $5, position_type = $6, status = $7, direction = $8, unit_size = $9, lot = $10, open_price = $11, open_spread = $12, open_real_price = $13, close_price = $14, close_spread = $15, close_real_price = $16, profit = $17, stop_surplus_price = $18, stop_loss_price = $19, create_time = $20, open_time = $21, close_time = $22, open_operator = $23, close_operator = $24, market_id = $25, account_id = $26, symbol=$27, force_close_price = $28
+            INSERT INTO tb_position (id, offset_idx, margin, margin_balance, leverage, position_type, status, direction, unit_size, lot, open_price, open_spread, open_real_price, close_price, close_spread, close_real_price, profit, stop_surplus_price, stop_loss_price, create_time, open_time, close_time, open_operator, close_operator, market_id, account_id, symbol, force_close_price, auto_open_price)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10,$11,$12,$13,$14,$15,$16,$17,$18,$19,$20,$21,$22,$23,$24,$25,$26,$27,$28,$29)
+            ON CONFLICT (id) DO UPDATE SET offset_idx = $2, margin = $3, margin_balance = $4, leverage = $5, position_type = $6, status = $7, direction = $8, unit_size = $9, lot = $10, open_price = $11, open_spread = $12, open_real_price = $13, close_price = $14, close_spread = $15, close_real_price = $16, profit = $17, stop_surplus_price = $18, stop_loss_price = $19, create_time = $20, open_time = $21, close_time = $22, open_operator = $23, close_operator = $24, market_id = $25, account_id = $26, symbol=$27, force_close_price = $28, auto_open_price = $29
             "#,
             ins.id,
             ins.offset_idx,
@@ -157,9 +225,58 @@ impl PG {
             ins.account_id,
             ins.symbol,
             ins.force_close_price,
+            ins.auto_open_price,
         ).execute(&self.db).await?;
         Ok(())
     }
+    /// Get a page of the positions ordered by the close time desc, and the total count.
+    pub async fn query_position_history(
+        &self,
+        q: &PositionQuery,
+    ) -> anyhow::Result<(i64, Vec<Position>)> {
+        let mut qb = QueryBuilder::<Postgres>::new("SELECT COUNT(*) FROM tb_position");
+        push_position_filter(&mut qb, q);
+        let total: i64 = qb.build_query_scalar().fetch_one(&self.db).await?;
+        let mut qb = QueryBuilder::<Postgres>::new("SELECT * FROM tb_position");
+        push_position_filter(&mut qb, q);
+        qb.push(" ORDER BY close_time DESC, id LIMIT ")
+            .push_bind(q.limit)
+            .push(" OFFSET ")
+            .push_bind(q.offset);
+        let list: Vec<DbPosition> = qb.build_query_as().fetch_all(&self.db).await?;
+        Ok((total, list.into_iter().map(|p| p.into()).collect()))
+    }
+
+    /// Get the realized P/L of the matched positions, usually filtered by account.
+    pub async fn query_realized_profit(
+        &self,
+        q: &PositionQuery,
+    ) -> anyhow::Result<DbProfitSummary> {
+        let mut qb = QueryBuilder::<Postgres>::new(
+            "SELECT COUNT(*) AS positions, COALESCE(SUM(profit), 0)::bigint AS profit FROM tb_position",
+        );
+        push_position_filter(&mut qb, q);
+        let rs: DbProfitSummary = qb.build_query_as().fetch_one(&self.db).await?;
+        Ok(rs)
+    }
+
+    /// Get the volume and realized P/L of the matched positions per market.
+    pub async fn query_market_stats(
+        &self,
+        q: &PositionQuery,
+    ) -> anyhow::Result<Vec<DbMarketStats>> {
+        let mut qb = QueryBuilder::<Postgres>::new(
+            r#"SELECT symbol, COUNT(*) AS positions, COALESCE(SUM(lot), 0)::bigint AS lot,
+            COALESCE(SUM(unit_size::numeric * lot::numeric / 10000 * open_real_price::numeric), 0) AS volume,
+            COALESCE(SUM(profit), 0)::bigint AS profit
+            FROM tb_position"#,
+        );
+        push_position_filter(&mut qb, q);
+        qb.push(" GROUP BY symbol ORDER BY symbol");
+        let rs: Vec<DbMarketStats> = qb.build_query_as().fetch_all(&self.db).await?;
+        Ok(rs)
+    }
+
     async fn load_all_list(&self, send: MessageSender) -> anyhow::Result<()> {
         let limit = 100;
         let mut offset = 0;
@@ -174,15 +291,17 @@ impl PG {
                 "#,
                 limit,
                 offset
-            ).fetch_all(&self.db).await?;
+            )
+            .fetch_all(&self.db)
+            .await?;
             if list.len() == 0 {
                 break;
             }
             for item in list {
                 let data: List = item.into();
-                send.send(Message{
+                send.send(Message {
                     state: State::List(data),
-                    event: Event::None
+                    event: Event::None,
                 })?;
             }
             offset += limit;
@@ -203,15 +322,17 @@ impl PG {
                 "#,
                 limit,
                 offset
-            ).fetch_all(&self.db).await?;
+            )
+            .fetch_all(&self.db)
+            .await?;
             if list.len() == 0 {
                 break;
             }
             for item in list {
                 let data: Market = item.into();
-                send.send(Message{
+                send.send(Message {
                     state: State::Market(data),
-                    event: Event::None
+                    event: Event::None,
                 })?;
             }
             offset += limit;
@@ -232,15 +353,17 @@ impl PG {
                 "#,
                 limit,
                 offset
-            ).fetch_all(&self.db).await?;
+            )
+            .fetch_all(&self.db)
+            .await?;
             if list.len() == 0 {
                 break;
             }
             for item in list {
                 let data: Account = item.into();
-                send.send(Message{
+                send.send(Message {
                     state: State::Account(data),
-                    event: Event::None
+                    event: Event::None,
                 })?;
             }
             offset += limit;
@@ -261,15 +384,17 @@ impl PG {
                 "#,
                 limit,
                 offset
-            ).fetch_all(&self.db).await?;
+            )
+            .fetch_all(&self.db)
+            .await?;
             if list.len() == 0 {
                 break;
             }
             for item in list {
                 let data: Position = item.into();
-                send.send(Message{
+                send.send(Message {
                     state: State::Position(data),
-                    event: Event::None
+                    event: Event::None,
                 })?;
             }
             offset += limit;
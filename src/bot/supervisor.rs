@@ -0,0 +1,83 @@
+use crate::com::Task;
+use futures::future::join_all;
+use log::*;
+use tokio::signal;
+use tokio::time::Duration;
+
+// The tasks of one stage are stopped together.
+struct Stage {
+    name: String,
+    tasks: Vec<Task>,
+}
+
+/// Owns the bot tasks and stops them stage by stage, in the order the stages are added.
+/// Add the producers first, so the consumers can drain what was already produced.
+pub struct Supervisor {
+    stages: Vec<Stage>,
+    grace: Duration,
+}
+
+impl Supervisor {
+    pub fn new(grace: Duration) -> Self {
+        Self {
+            stages: vec![],
+            grace,
+        }
+    }
+
+    pub fn add_stage(&mut self, name: &str, tasks: Vec<Task>) {
+        self.stages.push(Stage {
+            name: name.to_string(),
+            tasks,
+        });
+    }
+
+    /// Stop all the tasks, each stage waits at most the grace period.
+    /// Returns the names of the tasks that did not stop in time and were aborted.
+    pub async fn shutdown(self) -> Vec<String> {
+        let mut not_stopped = vec![];
+        for stage in self.stages {
+            info!("shutdown stage {}, {} tasks", stage.name, stage.tasks.len());
+            let grace = self.grace;
+            let rs = join_all(stage.tasks.into_iter().map(|task| async move {
+                let name = task.name().to_string();
+                (name, task.shutdown_timeout(grace).await)
+            }))
+            .await;
+            for (name, stopped) in rs {
+                if !stopped {
+                    not_stopped.push(format!("{}/{}", stage.name, name));
+                }
+            }
+        }
+        if not_stopped.is_empty() {
+            info!("all tasks stopped");
+        } else {
+            warn!("tasks not stopped in {:?}: {:?}", self.grace, not_stopped);
+        }
+        not_stopped
+    }
+}
+
+/// Wait for Ctrl-C or SIGTERM.
+pub async fn wait_for_signal() -> anyhow::Result<()> {
+    #[cfg(unix)]
+    {
+        let mut term = signal::unix::signal(signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            r = signal::ctrl_c() => {
+                r?;
+                info!("Ctrl-C received, shutting down");
+            }
+            _ = term.recv() => {
+                info!("SIGTERM received, shutting down");
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        signal::ctrl_c().await?;
+        info!("Ctrl-C received, shutting down");
+    }
+    Ok(())
+}
@@ -0,0 +1,332 @@
+// Stop-loss / take-profit triggers of the normal positions.
+// A long position is closed at the sell price and a short position at the buy price,
+// so each symbol keeps one index per direction and per trigger side, sorted by trigger price.
+use crate::bot::machine::StateMap;
+use crate::bot::state::{
+    Address, Direction, MoveCall, Position, PositionParams, PositionStatus, PositionType, Price,
+};
+use dashmap::DashSet;
+use log::*;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+
+// key is position address, the auto close transaction is in flight
+pub type DsAutoClosing = DashSet<Address>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TriggerPosition {
+    pub id: Address,
+    pub account_id: Address,
+    pub symbol: String,
+    pub position_type: PositionType,
+    pub direction: Direction,
+    /// Automatic profit stop price, 0 means not set
+    pub stop_surplus_price: u64,
+    /// Automatic stop loss price, 0 means not set
+    pub stop_loss_price: u64,
+}
+
+impl From<&Position> for TriggerPosition {
+    fn from(p: &Position) -> Self {
+        Self {
+            id: p.id.copy(),
+            account_id: p.account_id.copy(),
+            symbol: p.symbol.clone(),
+            position_type: p.position_type.clone(),
+            direction: p.direction,
+            stop_surplus_price: p.stop_surplus_price,
+            stop_loss_price: p.stop_loss_price,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct SymbolTrigger {
+    // fired when the close price rises to or above the trigger price
+    long_upper: BTreeSet<(u64, Address)>,
+    // fired when the close price falls to or below the trigger price
+    long_lower: BTreeSet<(u64, Address)>,
+    short_upper: BTreeSet<(u64, Address)>,
+    short_lower: BTreeSet<(u64, Address)>,
+}
+
+impl SymbolTrigger {
+    fn is_empty(&self) -> bool {
+        self.long_upper.is_empty()
+            && self.long_lower.is_empty()
+            && self.short_upper.is_empty()
+            && self.short_lower.is_empty()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct TriggerIndex {
+    // key is symbol
+    symbols: HashMap<String, SymbolTrigger>,
+    // key is position address
+    positions: HashMap<Address, TriggerPosition>,
+}
+
+impl TriggerIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Build the index from the positions already loaded into the state map.
+    pub fn load(ssm: &StateMap) -> Self {
+        let mut index = Self::new();
+        for account_positions in ssm.position.iter() {
+            for p in account_positions.value().iter() {
+                index.upsert(p.value());
+            }
+        }
+        index
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// Insert or refresh the triggers of a position,
+    /// a position that is no longer normal or has no trigger price is removed.
+    pub fn upsert(&mut self, position: &Position) {
+        self.remove(&position.id);
+        if position.status != PositionStatus::Normal
+            || (position.stop_surplus_price == 0 && position.stop_loss_price == 0)
+        {
+            return;
+        }
+        let tp = TriggerPosition::from(position);
+        let st = self.symbols.entry(tp.symbol.clone()).or_default();
+        let (surplus_set, loss_set) = match tp.direction {
+            Direction::Buy => (&mut st.long_upper, &mut st.long_lower),
+            Direction::Sell => (&mut st.short_lower, &mut st.short_upper),
+            Direction::Flat => return,
+        };
+        if tp.stop_surplus_price > 0 {
+            surplus_set.insert((tp.stop_surplus_price, tp.id.copy()));
+        }
+        if tp.stop_loss_price > 0 {
+            loss_set.insert((tp.stop_loss_price, tp.id.copy()));
+        }
+        self.positions.insert(tp.id.copy(), tp);
+    }
+
+    pub fn remove(&mut self, id: &Address) {
+        let tp = match self.positions.remove(id) {
+            Some(tp) => tp,
+            None => return,
+        };
+        if let Some(st) = self.symbols.get_mut(&tp.symbol) {
+            let surplus = (tp.stop_surplus_price, tp.id.copy());
+            let loss = (tp.stop_loss_price, tp.id.copy());
+            match tp.direction {
+                Direction::Buy => {
+                    st.long_upper.remove(&surplus);
+                    st.long_lower.remove(&loss);
+                }
+                Direction::Sell => {
+                    st.short_lower.remove(&surplus);
+                    st.short_upper.remove(&loss);
+                }
+                Direction::Flat => {}
+            }
+            if st.is_empty() {
+                self.symbols.remove(&tp.symbol);
+            }
+        }
+    }
+
+    /// Get the positions whose trigger price is crossed by the price,
+    /// each position is returned at most once.
+    pub fn crossed(&self, symbol: &str, price: &Price) -> Vec<TriggerPosition> {
+        let st = match self.symbols.get(symbol) {
+            Some(st) => st,
+            None => return vec![],
+        };
+        let mut ids: BTreeSet<&Address> = BTreeSet::new();
+        // long positions are closed at the sell price
+        ids.extend(
+            st.long_upper
+                .iter()
+                .take_while(|(p, _)| *p <= price.sell_price)
+                .map(|(_, id)| id),
+        );
+        ids.extend(
+            st.long_lower
+                .iter()
+                .rev()
+                .take_while(|(p, _)| *p >= price.sell_price)
+                .map(|(_, id)| id),
+        );
+        // short positions are closed at the buy price
+        ids.extend(
+            st.short_upper
+                .iter()
+                .take_while(|(p, _)| *p <= price.buy_price)
+                .map(|(_, id)| id),
+        );
+        ids.extend(
+            st.short_lower
+                .iter()
+                .rev()
+                .take_while(|(p, _)| *p >= price.buy_price)
+                .map(|(_, id)| id),
+        );
+        ids.into_iter()
+            .filter_map(|id| self.positions.get(id).cloned())
+            .collect()
+    }
+}
+
+/// Send the auto close transaction of a triggered position,
+/// a position whose transaction is still in flight is skipped.
+pub fn auto_close_position<C>(call: Arc<C>, auto_closing: Arc<DsAutoClosing>, tp: TriggerPosition)
+where
+    C: MoveCall + Send + Sync + 'static,
+{
+    if !auto_closing.insert(tp.id.copy()) {
+        debug!("position {} is auto closing, skip it", tp.id);
+        return;
+    }
+    tokio::spawn(async move {
+        info!(
+            "auto close position: {}, account: {}, stop surplus price: {}, stop loss price: {}",
+            tp.id, tp.account_id, tp.stop_surplus_price, tp.stop_loss_price
+        );
+        let params = PositionParams {
+            id: tp.id.copy(),
+            position_type: tp.position_type as u8,
+            symbol: tp.symbol,
+        };
+        if let Err(e) = call.auto_close_position(tp.account_id, params).await {
+            error!("auto close position {} error: {}", tp.id, e);
+            // let the next crossed price retry it
+            auto_closing.remove(&tp.id);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYMBOL: &str = "Crypto.BTC/USD";
+
+    fn position(id: u8, direction: Direction, surplus: u64, loss: u64) -> Position {
+        Position {
+            id: Address::new(vec![id; 32]),
+            offset: 0,
+            margin: 0,
+            margin_balance: 0,
+            leverage: 10,
+            position_type: PositionType::Cross,
+            status: PositionStatus::Normal,
+            direction,
+            unit_size: 1,
+            lot: 1,
+            open_price: 100,
+            open_spread: 0,
+            open_real_price: 100,
+            close_price: 0,
+            close_spread: 0,
+            close_real_price: 0,
+            profit: 0,
+            auto_open_price: 0,
+            stop_surplus_price: surplus,
+            stop_loss_price: loss,
+            create_time: 0,
+            open_time: 0,
+            close_time: 0,
+            open_operator: Address::default(),
+            close_operator: Address::default(),
+            market_id: Address::default(),
+            account_id: Address::default(),
+            symbol: SYMBOL.to_string(),
+            force_close_price: 0,
+        }
+    }
+
+    fn price(buy_price: u64, sell_price: u64) -> Price {
+        Price {
+            buy_price,
+            sell_price,
+            real_price: (buy_price + sell_price) / 2,
+            spread: buy_price - sell_price,
+            update_time: 0,
+        }
+    }
+
+    #[test]
+    fn test_crossed() {
+        // direction, stop surplus, stop loss, buy price, sell price, fired
+        // the buy price is 50 above the sell price, a long trigger checked
+        // against the buy price or a short one against the sell price fails
+        let cases = [
+            // long take profit at the sell price
+            (Direction::Buy, 110, 0, 160, 110, true),
+            (Direction::Buy, 110, 0, 165, 115, true),
+            (Direction::Buy, 110, 0, 155, 105, false),
+            // long stop loss at the sell price
+            (Direction::Buy, 0, 90, 140, 90, true),
+            (Direction::Buy, 0, 90, 135, 85, true),
+            (Direction::Buy, 0, 90, 145, 95, false),
+            // short take profit at the buy price
+            (Direction::Sell, 90, 0, 90, 40, true),
+            (Direction::Sell, 90, 0, 85, 35, true),
+            (Direction::Sell, 90, 0, 95, 45, false),
+            // short stop loss at the buy price
+            (Direction::Sell, 0, 110, 110, 60, true),
+            (Direction::Sell, 0, 110, 115, 65, true),
+            (Direction::Sell, 0, 110, 105, 55, false),
+        ];
+        for (direction, surplus, loss, buy, sell, fired) in cases {
+            let mut index = TriggerIndex::new();
+            let p = position(1, direction, surplus, loss);
+            index.upsert(&p);
+            let crossed = index.crossed(SYMBOL, &price(buy, sell));
+            let expected = if fired {
+                vec![TriggerPosition::from(&p)]
+            } else {
+                vec![]
+            };
+            assert_eq!(
+                crossed, expected,
+                "{:?} surplus {} loss {} at buy {} sell {}",
+                direction, surplus, loss, buy, sell
+            );
+            assert!(index
+                .crossed("Crypto.ETH/USD", &price(buy, sell))
+                .is_empty());
+        }
+    }
+
+    #[test]
+    fn test_move_and_remove_trigger() {
+        let mut index = TriggerIndex::new();
+        index.upsert(&position(1, Direction::Buy, 110, 90));
+        index.upsert(&position(2, Direction::Buy, 130, 0));
+        assert_eq!(index.len(), 2);
+        // the take profit moves up, the old price no longer fires
+        index.upsert(&position(1, Direction::Buy, 120, 90));
+        assert_eq!(index.len(), 2);
+        assert!(index.crossed(SYMBOL, &price(165, 115)).is_empty());
+        let crossed = index.crossed(SYMBOL, &price(170, 120));
+        assert_eq!(crossed.len(), 1);
+        assert_eq!(crossed[0].stop_surplus_price, 120);
+        // both triggers cleared
+        index.upsert(&position(1, Direction::Buy, 0, 0));
+        assert!(index.crossed(SYMBOL, &price(170, 120)).is_empty());
+        assert!(index.crossed(SYMBOL, &price(130, 80)).is_empty());
+        // a position leaving the normal status
+        let mut closing = position(2, Direction::Buy, 130, 0);
+        closing.status = PositionStatus::AutoClosing;
+        index.upsert(&closing);
+        assert!(index.is_empty());
+        assert!(index.symbols.is_empty());
+    }
+}
@@ -1,3 +1,4 @@
+use crate::bot::kline::Kline;
 use crate::bot::state::{Address, OrgPrice};
 use crate::com::{ClientError, Task, TaskStopRx};
 use crate::config::PythSymbol;
@@ -86,6 +87,7 @@ impl WsServerState {
 pub struct WsWatchTx(pub broadcast::Sender<WsSrvMessage>);
 pub struct WsWatchRx(pub broadcast::Receiver<WsSrvMessage>);
 pub struct PriceStatusWatchRx(pub broadcast::Receiver<PriceStatus>);
+pub struct KlineWatchRx(pub broadcast::Receiver<Kline>);
 
 // impl Clone for PriceWatchRx {
 //     fn clone(&self) -> Self {
@@ -103,6 +105,11 @@ impl Clone for PriceStatusWatchRx {
         Self(self.0.resubscribe())
     }
 }
+impl Clone for KlineWatchRx {
+    fn clone(&self) -> Self {
+        Self(self.0.resubscribe())
+    }
+}
 impl Clone for WsWatchTx {
     fn clone(&self) -> Self {
         Self(self.0.clone())
@@ -132,6 +139,13 @@ pub enum WsSrvMessage {
     PriceEvent(OrgPrice),
     PriceUpdate(PriceStatus),
     SpreadUpdate(SpreadData),
+    KlineUpdate(Kline),
+    AuthChallenge(AuthChallenge),
+    AuthSuccess(AuthSuccess),
+    AuthError(String),
+    Ack(WsAck),
+    Error(WsError),
+    Heartbeat(i64),
     Close,
 }
 
@@ -174,6 +188,33 @@ impl WsSrvMessage {
                     .unwrap_or_default()
                     .as_str(),
             ),
+            Self::KlineUpdate(kline) => Self::json_warp(
+                "kline_update",
+                serde_json::to_string(&kline).unwrap_or_default().as_str(),
+            ),
+            Self::AuthChallenge(challenge) => Self::json_warp(
+                "auth_challenge",
+                serde_json::to_string(&challenge)
+                    .unwrap_or_default()
+                    .as_str(),
+            ),
+            Self::AuthSuccess(auth) => Self::json_warp(
+                "auth_success",
+                serde_json::to_string(&auth).unwrap_or_default().as_str(),
+            ),
+            Self::AuthError(e) => Self::json_warp(
+                "auth_error",
+                serde_json::to_string(&e).unwrap_or_default().as_str(),
+            ),
+            Self::Ack(ack) => Self::json_warp(
+                "ack",
+                serde_json::to_string(&ack).unwrap_or_default().as_str(),
+            ),
+            Self::Error(e) => Self::json_warp(
+                "error",
+                serde_json::to_string(&e).unwrap_or_default().as_str(),
+            ),
+            Self::Heartbeat(time) => Self::json_warp("heartbeat", time.to_string().as_str()),
             Self::Close => Self::json_warp("close", ""),
         }
     }
@@ -207,10 +248,64 @@ pub struct SpreadData {
     #[serde(skip_serializing)]
     pub symbol: String,
 }
-#[derive(Debug, Clone)]
+/// The message the client must sign with the owner key of the account,
+/// the nonce makes it usable only once.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct AuthChallenge {
+    pub account: Address,
+    pub message: String,
+}
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct AuthSuccess {
+    pub account: Address,
+    pub owner: Address,
+}
+/// The version of the websocket client protocol,
+/// the requests without version are the legacy single symbol price subscriptions.
+pub const WS_PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SubChannel {
+    Price,
+    Spread,
+    Account,
+    Positions,
+    Kline,
+}
+impl SubChannel {
+    /// The private channels are bound to the authenticated account, not to a symbol.
+    pub fn is_private(&self) -> bool {
+        matches!(self, Self::Account | Self::Positions)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Subscription {
+    pub channel: SubChannel,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub symbol: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WsAck {
+    pub id: Option<u64>,
+    pub sub_type: SubType,
+    /// The subscriptions of the connection after the request
+    pub subscriptions: Vec<Subscription>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WsError {
+    pub id: Option<u64>,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum SubType {
     Unsubscribe,
     Subscribe,
+    List,
     None,
 }
 
@@ -222,6 +317,7 @@ impl Serialize for SubType {
         let t = match *self {
             Self::Unsubscribe => "unsubscribe",
             Self::Subscribe => "subscribe",
+            Self::List => "list",
             _ => "",
         };
         serializer.serialize_str(t)
@@ -236,6 +332,7 @@ impl<'de> Deserialize<'de> for SubType {
         let r = match s.as_str() {
             "unsubscribe" => SubType::Unsubscribe,
             "subscribe" => SubType::Subscribe,
+            "list" => SubType::List,
             _ => SubType::None,
         };
         Ok(r)
@@ -305,6 +402,9 @@ impl WsClient {
     pub async fn shutdown(self) {
         self.task.shutdown().await;
     }
+    pub fn into_tasks(self) -> Vec<Task> {
+        vec![self.task]
+    }
 }
 
 async fn handle<F>(
@@ -3,10 +3,13 @@ use crate::aptos::config::Config as aptosConfig;
 use crate::bot;
 use crate::com;
 use crate::config::{self, Config};
-use crate::sui::{config::Config as suiConfig, tool};
-use clap::{arg, ArgAction, Command};
+use crate::sui::{config::Config as suiConfig, signer, tool};
+use clap::{arg, Arg, ArgAction, Command};
 use log::debug;
+use std::net::TcpListener;
 use std::path::PathBuf;
+use std::str::FromStr;
+use sui_types::base_types::SuiAddress;
 extern crate chrono;
 extern crate env_logger;
 extern crate log;
@@ -22,6 +25,8 @@ fn cli() -> Command {
         .arg(arg!(-f --file <CONFIG_FILE> "The custom config file.").value_parser(clap::value_parser!(PathBuf)))
         .arg(arg!(-l --log <LOG> "write log to this file.").value_parser(clap::value_parser!(PathBuf)))
         .arg(arg!(-g --gasbudget <GAS_BUDGET> "Gas budget for running module initializers.").value_parser(clap::value_parser!(u64)))
+        .arg(arg!(--signer <SIGNER> "The keystore address signing the sui transactions, the default signer of the config or the active address if not set.").global(true))
+        .arg(Arg::new("dry_run").long("dry-run").action(ArgAction::SetTrue).global(true).help("Only dry run the sui transactions and print the expected effects, balance changes and gas, nothing is signed."))
         .subcommand(
             Command::new("sui")
                 .about("sui blok chain")
@@ -32,7 +37,8 @@ fn cli() -> Command {
                 .subcommand(sui_trade())
                 .subcommand(sui_coin())
                 .subcommand(sui_nft())
-                .subcommand(sui_oracle()),
+                .subcommand(sui_oracle())
+                .subcommand(sui_signer()),
         )
         .subcommand(
             Command::new("aptos")
@@ -51,6 +57,9 @@ fn cli() -> Command {
                 .arg(arg!(-i --ip <IP> "The IP address bound to the web server. The default is 127.0.0.1."))
                 .arg(arg!(-b --blockchain <BLOCKCHAIN> "Target blockchain, optional value: sui , aptos").default_value("sui").value_parser(["sui","aptos"]))
                 .arg(arg!(-f --full_node <FULL_NODE> "If set to true, a full node will be started, and it is necessary to specify an external InfluxDB database and PostgreSQL database in order to start.").default_value("true").value_parser(clap::value_parser!(bool)))
+                .arg(arg!(-o --oracle <ORACLE> "If set to true, the robot also pushes the pyth prices on chain, on the deviation or heartbeat policy of the oracle price config.").default_value("false").value_parser(clap::value_parser!(bool)))
+                .arg(arg!(--indexer <INDEXER> "If set to true, the robot walks the sui checkpoints for the object changes instead of subscribing to the events, continuing from the last checkpoint saved in PostgreSQL. Only on a full node.").default_value("false").value_parser(clap::value_parser!(bool)))
+                .arg(arg!(--grace <GRACE> "The seconds to wait for each stage of the robot tasks to stop on Ctrl-C or SIGTERM, 10 by default.").value_parser(clap::value_parser!(u64)))
         )
 }
 
@@ -93,6 +102,15 @@ fn sui_coin() -> Command {
                         .value_parser(clap::value_parser!(u64)),
                 ),
         )
+        .subcommand(Command::new("rebalance_gas").about(
+            "Merge the dust SUI gas coins and split the SUI into the gas pool size of the config.",
+        ))
+}
+fn sui_signer() -> Command {
+    Command::new("signer")
+        .about("Start a remote signer, signing the transactions of the allowed calls with the sui keystore.")
+        .arg(arg!(-i --ip <IP> "The IP address bound to the signer. The default is 127.0.0.1.").default_value("127.0.0.1"))
+        .arg(arg!(-p --port <PORT> "The port of the signer. The default value is 3001.").default_value("3001").value_parser(clap::value_parser!(u16)))
 }
 fn sui_oracle() -> Command {
     Command::new("oracle")
@@ -285,7 +303,7 @@ fn sui_trade() -> Command {
             Command::new("trigger_update_opening_price")
             .arg_required_else_help(true)
                 .about("trigger update opening price.")
-                .arg(arg!(-s --symbol <SYMBOL> "The market symbol. "))
+                .arg(arg!(-s --symbol <SYMBOL> "The market object id. "))
                 ,
         )
         .subcommand(
@@ -583,11 +601,19 @@ pub fn run() -> anyhow::Result<()> {
     let config_file = matches.get_one::<PathBuf>("file").map(|p| p.to_path_buf());
     let log_file = matches.get_one::<PathBuf>("log");
     let gas_budget = *matches.get_one::<u64>("gasbudget").unwrap_or(&1000);
+    let dry_run = matches.get_flag("dry_run");
+    let signer = matches.get_one::<String>("signer");
     init_log(log_file);
     match matches.subcommand() {
         Some(("sui", matches)) => {
             let mut conf = suiConfig::default();
             config::config(&mut conf, config_file)?;
+            conf.executor_config.simulate = dry_run;
+            if let Some(signer) = signer {
+                let address = SuiAddress::from_str(signer)
+                    .map_err(|e| com::ClientError::InvalidCliParams(format!("signer: {}", e)))?;
+                conf.signer_config.default = Some(address);
+            }
             match matches.subcommand() {
                 Some(("config", matches)) => match matches.subcommand() {
                     Some(("get", _)) => {
@@ -613,6 +639,9 @@ pub fn run() -> anyhow::Result<()> {
                         Some(("mint", matches)) => {
                             tool.coin_mint(matches).await?;
                         }
+                        Some(("rebalance_gas", _matches)) => {
+                            tool.rebalance_gas().await?;
+                        }
                         _ => unreachable!(),
                     }
                     Ok::<(), anyhow::Error>(())
@@ -667,6 +696,15 @@ pub fn run() -> anyhow::Result<()> {
                     }
                     Ok::<(), anyhow::Error>(())
                 })?,
+                Some(("signer", matches)) => com::new_tokio_one_thread().block_on(async {
+                    let ip = matches.get_one::<String>("ip").unwrap();
+                    let port = matches.get_one::<u16>("port").unwrap();
+                    let listener = TcpListener::bind(format!("{}:{}", ip, port))?;
+                    let keystore = signer::load_keystore(&conf.sui_cli_config_file)?;
+                    let policy = signer::SignPolicy::from_config(&conf)?;
+                    let auth_token = conf.signer_config.auth_token.clone();
+                    signer::serve(listener, keystore, policy, auth_token).await
+                })?,
                 Some(("trade", matches)) => com::new_tokio_one_thread().block_on(async {
                     let tool = tool::Tool::new(conf, gas_budget).await?;
                     match matches.subcommand() {
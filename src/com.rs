@@ -13,6 +13,7 @@ pub const SUI_NFT_PUBLISH_TX: &str = "GAhGoVua9D5MGq9k9AqhLodQ4YYHkA75jE8Vs2Rcyx
 pub const SUI_SCALE_PUBLISH_TX: &str = "3R6uzorr88rR8DrBKybnZgYyiNWg1jeNj92VuMDtJX6N";
 
 pub const DECIMALS: u64 = 1000000;
+pub const DECIMALS_EXPO: u32 = 6;
 pub const DENOMINATOR: u64 = 10000;
 pub const DENOMINATOR128: u64 = 10000;
 
@@ -50,6 +51,16 @@ pub enum ClientError {
     InvalidRange,
     #[error("invalid ws address signer")]
     InvalidWsAddressSigner,
+    #[error("invalid signature: {0}")]
+    InvalidSignature(String),
+    #[error("websocket session is not authenticated")]
+    WsNotAuthenticated,
+    #[error("unsupported websocket protocol version: {0}")]
+    UnsupportedWsVersion(u32),
+    #[error("invalid websocket request: {0}")]
+    InvalidWsRequest(String),
+    #[error("arithmetic overflow: {0}")]
+    ArithmeticOverflow(String),
     #[error("Get object error: {0}")]
     GetObjectError(String),
     #[error("Not Active Account: {0}")]
@@ -60,6 +71,14 @@ pub enum ClientError {
     ObjectNotFound(String),
     #[error("transaction execution failure: {0}")]
     TransactionExecutionFailure(String),
+    #[error("move abort in {0} with code {1}")]
+    MoveAbort(String, u64),
+    #[error("insufficient gas for transaction: {0}")]
+    InsufficientGas(String),
+    #[error("object version conflict: {0}")]
+    ObjectVersionConflict(String),
+    #[error("remote signer error: {0}")]
+    SignerError(String),
     #[error("PTB context not init, please call init first")]
     PTBCtxNotInit,
     #[error("no gas coin in account")]
@@ -84,6 +103,8 @@ pub fn new_tokio_one_thread() -> tokio::runtime::Runtime {
         .build()
         .expect("build tokio runtime")
 }
+// The default time to wait for a task to stop after the stop signal is sent.
+pub const TASK_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
 pub type TaskStopTx = oneshot::Sender<()>;
 pub type TaskStopRx = oneshot::Receiver<()>;
 pub struct Task {
@@ -104,20 +125,38 @@ impl Task {
         }
     }
 
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
     pub async fn shutdown(self) {
+        self.shutdown_timeout(TASK_SHUTDOWN_TIMEOUT).await;
+    }
+
+    /// Send the stop signal and wait for the job at most `grace`,
+    /// the job is aborted if it has not stopped in time. Returns whether it stopped by itself.
+    pub async fn shutdown_timeout(self, grace: Duration) -> bool {
         debug!("shutdown task {} ...", self.name);
         let _ = self.shutdown_tx.send(());
-        if let Err(e) = time::timeout(Duration::from_micros(100), async {
-            if let Err(e) = self.job.await {
+        let mut job = self.job;
+        match time::timeout(grace, &mut job).await {
+            Ok(Ok(Ok(_))) => true,
+            Ok(Ok(Err(e))) => {
+                error!("task {} exit with error: {:?}", self.name, e);
+                true
+            }
+            Ok(Err(e)) => {
                 error!("task shutdown error: {:?}", e);
+                true
+            }
+            Err(e) => {
+                error!(
+                    "task shutdown await timeout: {:?}, error: {:?}",
+                    self.name, e
+                );
+                job.abort();
+                false
             }
-        })
-        .await
-        {
-            error!(
-                "task shutdown await timeout: {:?}, error: {:?}",
-                self.name, e
-            );
         }
     }
 }
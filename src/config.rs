@@ -41,6 +41,8 @@ pub trait Config {
     fn get_influxdb_config(&self) -> InfluxdbConfig;
     fn get_sql_db_config(&self) -> SqlDbConfig;
     fn get_price_config(&self) -> PriceConfig;
+    fn get_cron_config(&self) -> CronConfig;
+    fn get_ws_config(&self) -> WsConfig;
     fn get(&mut self);
 }
 
@@ -64,6 +66,12 @@ pub struct PriceConfig {
     pub pyth_package: String,
     pub pyth_state: String,
     pub pyth_symbol: Vec<PythSymbol>,
+    #[serde(default)]
+    pub source: PriceSourceConfig,
+    #[serde(default)]
+    pub guard: PriceGuardConfig,
+    #[serde(default)]
+    pub oracle: OracleConfig,
 }
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SqlDbConfig {
@@ -110,6 +118,101 @@ impl Default for PriceConfig {
             pyth_package: "0x0".to_string(),
             pyth_state: "0x0".to_string(),
             pyth_symbol: vec![PythSymbol::default()],
+            source: PriceSourceConfig::default(),
+            guard: PriceGuardConfig::default(),
+            oracle: OracleConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PriceSourceKind {
+    /// The pyth websocket of `ws_url`
+    PythWs,
+    /// Poll the latest price feeds of `price_server_url`
+    PythHttp,
+    /// Replay the prices of `replay_file`
+    Replay,
+}
+
+/// Where the bot gets the prices from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PriceSourceConfig {
+    pub primary: PriceSourceKind,
+    /// Used for a symbol when the primary source has no price of it for `stale_secs`
+    pub fallback: Option<PriceSourceKind>,
+    pub stale_secs: u64,
+    pub poll_interval_ms: u64,
+    /// One json price (`{"price":..,"update_time":..,"symbol":..}`) per line
+    pub replay_file: Option<PathBuf>,
+    pub replay_interval_ms: u64,
+}
+/// The checks of the price ticks, a check is disabled with 0.
+/// The liquidations of a market are paused while its prices are rejected.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PriceGuardConfig {
+    /// Reject the prices published more than `max_age_secs` ago, and mark a symbol unhealthy
+    /// without a tick for as long. The replayed prices are not checked
+    pub max_age_secs: u64,
+    /// Reject the prices whose confidence interval is more than this rate of the price
+    pub max_conf_rate: f64,
+    /// Reject the prices more than this rate away from the ema price
+    pub max_deviation_rate: f64,
+}
+impl Default for PriceGuardConfig {
+    fn default() -> Self {
+        Self {
+            max_age_secs: 60,
+            max_conf_rate: 0.02,
+            max_deviation_rate: 0.1,
+        }
+    }
+}
+
+/// The on chain pyth price updates of the oracle mode of the bot.
+/// A feed is updated when its price moved `deviation_rate` since its last update,
+/// or `heartbeat_secs` after its last update.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OracleConfig {
+    pub interval_ms: u64,
+    pub deviation_rate: f64,
+    pub heartbeat_secs: u64,
+    /// Max feeds updated in one transaction
+    pub max_batch: usize,
+    /// The pyth fee of one feed update, in MIST
+    pub update_fee: u64,
+    /// Max pyth fees paid in an hour, in MIST, 0 is no limit
+    pub max_fee_per_hour: u64,
+    /// The updated symbols, all the pyth symbols if empty
+    pub symbols: Vec<String>,
+}
+impl Default for OracleConfig {
+    fn default() -> Self {
+        Self {
+            interval_ms: 1000,
+            deviation_rate: 0.005,
+            heartbeat_secs: 60,
+            max_batch: 10,
+            update_fee: 1,
+            max_fee_per_hour: 0,
+            symbols: vec![],
+        }
+    }
+}
+
+impl Default for PriceSourceConfig {
+    fn default() -> Self {
+        Self {
+            primary: PriceSourceKind::PythWs,
+            fallback: None,
+            stale_secs: 30,
+            poll_interval_ms: 1000,
+            replay_file: None,
+            replay_interval_ms: 1000,
         }
     }
 }
@@ -124,6 +227,39 @@ impl Default for SqlDbConfig {
     }
 }
 
+/// Cron expressions (with seconds) of the bot scheduled jobs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CronConfig {
+    /// Charge the fund fee of all accounts
+    pub fund_fee: String,
+    /// Update the opening price of all markets
+    pub opening_price: String,
+}
+impl Default for CronConfig {
+    fn default() -> Self {
+        Self {
+            fund_fee: "0 0 0 * * *".to_string(),
+            opening_price: "0 0 0,8,16 * * *".to_string(),
+        }
+    }
+}
+
+/// Websocket push settings of the web server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WsConfig {
+    /// Minimum interval in milliseconds between two dynamic data pushes of one account
+    pub push_interval_ms: u64,
+}
+impl Default for WsConfig {
+    fn default() -> Self {
+        Self {
+            push_interval_ms: 500,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InfluxdbConfig {
     pub url: String,
@@ -146,6 +282,9 @@ pub struct PythSymbol {
     pub symbol: String,
     pub pyth_feed: String,
     // pub price_info_object_id: String,
+    /// Overrides the max confidence rate of the price guard
+    #[serde(default)]
+    pub max_conf_rate: Option<f64>,
 }
 
 impl Default for PythSymbol {
@@ -154,6 +293,7 @@ impl Default for PythSymbol {
             symbol: "Crypto.BTC/USD".to_string(),
             pyth_feed: "0x0".to_string(),
             // price_info_object_id: "0x0".to_string(),
+            max_conf_rate: None,
         }
     }
 }
@@ -2,11 +2,13 @@ use std::net::SocketAddr;
 
 use crate::bot::influxdb::Influxdb;
 use crate::bot::state::Address;
+use crate::bot::storage::postgres::{PositionQuery, PG};
 use crate::bot::{
+    cron::DmJobStatus,
     machine::SharedStateMap,
-    ws::{PriceStatusWatchRx, WsWatchRx},
+    ws::{KlineWatchRx, PriceStatusWatchRx, WsWatchRx},
 };
-use crate::com::ClientError;
+use crate::com::{ClientError, Task};
 use crate::http::query::empty_string_as_none;
 use crate::http::response::JsonResponse;
 use crate::http::service;
@@ -24,13 +26,12 @@ use serde::Deserialize;
 
 use std::sync::Arc;
 use std::{borrow::Cow, time::Duration};
-use tokio::sync::oneshot;
 use tower::{BoxError, ServiceBuilder};
 use tower_http::trace::{DefaultMakeSpan, TraceLayer};
 
 // use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 pub struct HttpServer {
-    shutdown_tx: oneshot::Sender<()>,
+    server_task: Task,
     price_broadcast: service::PriceBroadcast,
 }
 
@@ -40,37 +41,59 @@ impl HttpServer {
         ssm: SharedStateMap,
         db: Arc<Influxdb>,
         event_ws_rx: WsWatchRx,
+        pg: Option<Arc<PG>>,
+        jobs: Arc<DmJobStatus>,
     ) -> Self {
         let dps = service::new_price_status();
-        let (price_broadcast, price_status_rx) =
+        let (price_broadcast, price_status_rx, kline_rx) =
             service::PriceBroadcast::new(ssm.clone(), dps.clone(), event_ws_rx.clone(), db.clone())
                 .await;
-        let router = router(ssm.clone(), db.clone(), price_status_rx, event_ws_rx);
-        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        let router = router(
+            ssm.clone(),
+            db.clone(),
+            price_status_rx,
+            kline_rx,
+            event_ws_rx,
+            pg,
+            jobs,
+        );
+        let (shutdown_tx, shutdown_rx) = Task::new_shutdown_channel();
         let server = axum::Server::bind(&addr)
             .serve(router.into_make_service())
             .with_graceful_shutdown(async {
                 shutdown_rx.await.ok();
             });
         info!("start web server ...");
-        tokio::spawn(async move {
-            if let Err(e) = server.await {
-                error!("server error: {}", e);
-            }
-        });
+        let server_task = Task::new(
+            "http server",
+            shutdown_tx,
+            tokio::spawn(async move {
+                if let Err(e) = server.await {
+                    error!("server error: {}", e);
+                    return Err(ClientError::HttpServerError(e.to_string()).into());
+                }
+                Ok(())
+            }),
+        );
         tokio::spawn(async move {
             service::init_price_history_cache(ssm, db).await;
         });
         Self {
-            shutdown_tx,
+            server_task,
             price_broadcast,
         }
     }
 
     pub async fn shutdown(self) {
         info!("send http server shutdown signal");
-        let _ = self.shutdown_tx.send(());
-        let _ = self.price_broadcast.shutdown().await;
+        self.server_task.shutdown().await;
+        self.price_broadcast.shutdown().await;
+    }
+
+    pub fn into_tasks(self) -> Vec<Task> {
+        let mut tasks = vec![self.server_task];
+        tasks.extend(self.price_broadcast.into_tasks());
+        tasks
     }
 }
 
@@ -78,7 +101,10 @@ pub fn router(
     ssm: SharedStateMap,
     db: Arc<Influxdb>,
     price_status_rx: PriceStatusWatchRx,
+    kline_rx: KlineWatchRx,
     event_ws_rx: WsWatchRx,
+    pg: Option<Arc<PG>>,
+    jobs: Arc<DmJobStatus>,
 ) -> Router {
     let app: Router = Router::new()
         .route("/account/info/:address", get(get_user_info))
@@ -90,8 +116,11 @@ pub fn router(
             "/account/position/:address/:position_address",
             get(get_position_info),
         )
+        .route("/account/history", get(get_account_history))
         .route("/markets/:prefix", get(get_market_list))
+        .route("/market/stats", get(get_market_stats))
         .route("/symbols", get(get_symbol_list))
+        .route("/cron/jobs", get(get_cron_jobs))
         .route("/price/history", get(get_price_history))
         .route("/price/history_full", get(get_price_history_column))
         .route("/ws", get(ws_handler))
@@ -108,8 +137,11 @@ pub fn router(
         )
         .layer(Extension(ssm))
         .layer(Extension(price_status_rx))
+        .layer(Extension(kline_rx))
         .layer(Extension(event_ws_rx))
-        .layer(Extension(db));
+        .layer(Extension(db))
+        .layer(Extension(pg))
+        .layer(Extension(jobs));
     app.fallback(handler_404)
 }
 
@@ -124,29 +156,77 @@ async fn get_user_info(
     Path(address): Path<String>,
     Extension(state): Extension<SharedStateMap>,
 ) -> impl IntoResponse {
-    (
-        StatusCode::NOT_FOUND,
-        "Welcome to scale robot service. No resources found.",
-    )
+    JsonResponse::from(service::get_account_info(state, address)).to_json()
 }
 async fn get_position_info(
     Path((address, position_address)): Path<(String, String)>,
     Extension(state): Extension<SharedStateMap>,
 ) -> impl IntoResponse {
-    (
-        StatusCode::NOT_FOUND,
-        "Welcome to scale robot service. No resources found.",
-    )
+    JsonResponse::from(service::get_position_info(state, address, position_address)).to_json()
+}
+
+#[derive(Debug, Deserialize)]
+struct PageParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    page: Option<i64>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    size: Option<i64>,
 }
 
 async fn get_user_position_list(
     Path((prefix, address)): Path<(String, String)>,
+    Query(p): Query<PageParams>,
     Extension(state): Extension<SharedStateMap>,
+    Extension(pg): Extension<Option<Arc<PG>>>,
 ) -> impl IntoResponse {
-    (
-        StatusCode::NOT_FOUND,
-        "Welcome to scale robot service. No resources found.",
-    )
+    JsonResponse::from(service::get_position_list(state, pg, prefix, address, p.page, p.size).await)
+        .to_json()
+}
+
+#[derive(Debug, Deserialize)]
+struct PositionHistoryParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    account: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    symbol: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    status: Option<i16>,
+    /// Close time range [start, end)
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    start: Option<i64>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    end: Option<i64>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    page: Option<i64>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    size: Option<i64>,
+}
+impl PositionHistoryParams {
+    fn into_query(self) -> PositionQuery {
+        service::new_position_query(
+            self.account,
+            self.symbol,
+            self.status,
+            self.start,
+            self.end,
+            self.page,
+            self.size,
+        )
+    }
+}
+
+async fn get_account_history(
+    Query(q): Query<PositionHistoryParams>,
+    Extension(pg): Extension<Option<Arc<PG>>>,
+) -> impl IntoResponse {
+    JsonResponse::from(service::get_account_history(pg, q.into_query()).await).to_json()
+}
+
+async fn get_market_stats(
+    Query(q): Query<PositionHistoryParams>,
+    Extension(pg): Extension<Option<Arc<PG>>>,
+) -> impl IntoResponse {
+    JsonResponse::from(service::get_market_stats(pg, q.into_query()).await).to_json()
 }
 
 #[derive(Debug, Deserialize)]
@@ -182,16 +262,16 @@ async fn get_market_list(
     Path(prefix): Path<String>,
     Extension(state): Extension<SharedStateMap>,
 ) -> impl IntoResponse {
-    (
-        StatusCode::NOT_FOUND,
-        "Welcome to scale robot service. No resources found.",
-    )
+    JsonResponse::from(service::get_market_list(state, prefix)).to_json()
 }
 
 async fn get_symbol_list(Extension(state): Extension<SharedStateMap>) -> impl IntoResponse {
     let r = service::get_symbol_list(state).await;
     JsonResponse::from(r).to_json()
 }
+async fn get_cron_jobs(Extension(jobs): Extension<Arc<DmJobStatus>>) -> impl IntoResponse {
+    JsonResponse::from(service::get_cron_jobs(jobs)).to_json()
+}
 async fn handle_error(error: BoxError) -> impl IntoResponse {
     if error.is::<tower::timeout::error::Elapsed>() {
         return (StatusCode::REQUEST_TIMEOUT, Cow::from("request timed out"));
@@ -208,11 +288,14 @@ async fn handle_error(error: BoxError) -> impl IntoResponse {
     )
 }
 
+// The `account` param only asks for an auth challenge, the private events of the account
+// are sent after the client replies with the challenge signed by the account owner.
 async fn ws_handler(
     ws: WebSocketUpgrade,
     Query(q): Query<WsParams>,
     Extension(state): Extension<SharedStateMap>,
     Extension(price_status_ws_rx): Extension<PriceStatusWatchRx>,
+    Extension(kline_ws_rx): Extension<KlineWatchRx>,
     Extension(event_ws_rx): Extension<WsWatchRx>,
 ) -> impl IntoResponse {
     let jr = JsonResponse::<()>::default();
@@ -228,6 +311,13 @@ async fn ws_handler(
         }
     }
     return ws.on_upgrade(|socket| {
-        service::handle_ws(state, socket, address, price_status_ws_rx, event_ws_rx)
+        service::handle_ws(
+            state,
+            socket,
+            address,
+            price_status_ws_rx,
+            kline_ws_rx,
+            event_ws_rx,
+        )
     });
 }
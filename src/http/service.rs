@@ -1,26 +1,278 @@
 use crate::bot::{
     self,
+    guard::PriceHealth,
     influxdb::Influxdb,
+    kline::{Kline, KlineAggregator, KLINE_RANGES},
     state::{Account, Address, Market, OrgPrice, Position, State},
-    ws::{PriceStatus, PriceStatusWatchRx, SubType, WsSrvMessage, WsWatchRx},
+    ws::{
+        AccountDynamicData, AuthChallenge, AuthSuccess, KlineWatchRx, PriceStatus,
+        PriceStatusWatchRx, SubChannel, SubType, Subscription, WsAck, WsError, WsSrvMessage,
+        WsWatchRx, WS_PROTOCOL_VERSION,
+    },
 };
 
 use crate::bot::{
+    cron::{self, DmJobStatus, JobStatus},
     machine::SharedStateMap,
+    storage::entity::{DbMarketStats, DbProfitSummary},
     storage::local::{self, Local},
+    storage::postgres::{PositionQuery, PG},
 };
 use crate::com::{self, ClientError, Task, TaskStopRx, TaskStopTx};
+use crate::sui::signature;
 use axum::extract::ws::{Message, WebSocket};
 use cached::proc_macro::cached;
 use chrono::Utc;
 use csv::ReaderBuilder;
-use dashmap::{DashMap, DashSet};
+use dashmap::DashMap;
+use fastcrypto::encoding::{Encoding, Hex};
 use influxdb2_client::models::Query;
 use log::*;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
 use std::str::FromStr;
 use std::sync::Arc;
 use tokio::sync::{broadcast, oneshot};
+use tokio::time::{self, Duration, Instant};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Prefix {
+    Active,
+    History,
+}
+impl FromStr for Prefix {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "active" => Ok(Self::Active),
+            "history" => Ok(Self::History),
+            _ => Err(ClientError::HttpServerError(format!("unknown prefix: {}", s)).into()),
+        }
+    }
+}
+
+fn parse_address(address: &str) -> anyhow::Result<Address> {
+    Address::from_str(address).map_err(|e| ClientError::HttpServerError(e.to_string()).into())
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AccountInfo {
+    #[serde(flatten)]
+    pub account: Account,
+    pub dynamic: AccountDynamicData,
+}
+
+pub fn get_account_info(
+    ssm: SharedStateMap,
+    address: String,
+) -> anyhow::Result<Option<AccountInfo>> {
+    let address = parse_address(address.as_str())?;
+    let account = match ssm.account.get(&address) {
+        Some(a) => a.value().clone(),
+        None => return Ok(None),
+    };
+    let (dynamic, _) = bot::machine::get_account_dynamic_data(&ssm, &account);
+    Ok(Some(AccountInfo { account, dynamic }))
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PositionInfo {
+    #[serde(flatten)]
+    pub position: Position,
+    /// Floating P/L with the fund fee, only for the normal positions
+    pub floating_profit: Option<i64>,
+    pub floating_profit_rate: Option<f64>,
+}
+
+fn get_positions_info(ssm: &SharedStateMap, account_id: &Address) -> Vec<PositionInfo> {
+    let positions: Vec<Position> = match ssm.position.get(account_id) {
+        Some(p) => p.iter().map(|v| v.value().clone()).collect(),
+        None => return vec![],
+    };
+    let dynamic = match ssm.account.get(account_id) {
+        Some(a) => bot::machine::get_account_dynamic_data(ssm, a.value()).1,
+        None => vec![],
+    };
+    positions
+        .into_iter()
+        .map(|position| {
+            let d = dynamic.iter().find(|d| d.id == position.id);
+            PositionInfo {
+                floating_profit: d.map(|d| d.profit),
+                floating_profit_rate: d.map(|d| d.profit_rate),
+                position,
+            }
+        })
+        .collect()
+}
+
+pub fn get_position_info(
+    ssm: SharedStateMap,
+    address: String,
+    position_address: String,
+) -> anyhow::Result<Option<PositionInfo>> {
+    let address = parse_address(address.as_str())?;
+    let position_address = parse_address(position_address.as_str())?;
+    Ok(get_positions_info(&ssm, &address)
+        .into_iter()
+        .find(|p| p.position.id == position_address))
+}
+
+pub async fn get_position_list(
+    ssm: SharedStateMap,
+    pg: Option<Arc<PG>>,
+    prefix: String,
+    address: String,
+    page: Option<i64>,
+    size: Option<i64>,
+) -> anyhow::Result<Vec<PositionInfo>> {
+    let address = parse_address(address.as_str())?;
+    match Prefix::from_str(prefix.as_str())? {
+        // the normal and pending positions are kept in the state map
+        Prefix::Active => Ok(get_positions_info(&ssm, &address)),
+        // the closed positions are removed from the state map, only the full node keeps them
+        Prefix::History => {
+            let q = new_position_query(
+                Some(address.to_string()),
+                None,
+                None,
+                None,
+                None,
+                page,
+                size,
+            );
+            let (_, positions) = get_pg(pg)?.query_position_history(&q).await?;
+            Ok(positions
+                .into_iter()
+                .map(|position| PositionInfo {
+                    position,
+                    floating_profit: None,
+                    floating_profit_rate: None,
+                })
+                .collect())
+        }
+    }
+}
+
+pub const HISTORY_PAGE_SIZE: i64 = 20;
+pub const HISTORY_MAX_PAGE_SIZE: i64 = 100;
+
+fn get_pg(pg: Option<Arc<PG>>) -> anyhow::Result<Arc<PG>> {
+    pg.ok_or_else(|| {
+        ClientError::HttpServerError("history is only served by the full node".to_string()).into()
+    })
+}
+
+/// Page starts from 1.
+pub fn new_position_query(
+    account_id: Option<String>,
+    symbol: Option<String>,
+    status: Option<i16>,
+    start_time: Option<i64>,
+    end_time: Option<i64>,
+    page: Option<i64>,
+    size: Option<i64>,
+) -> PositionQuery {
+    let size = size
+        .unwrap_or(HISTORY_PAGE_SIZE)
+        .clamp(1, HISTORY_MAX_PAGE_SIZE);
+    let page = page.unwrap_or(1).max(1);
+    PositionQuery {
+        account_id,
+        symbol,
+        status,
+        start_time,
+        end_time,
+        offset: (page - 1) * size,
+        limit: size,
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PositionHistory {
+    pub total: i64,
+    /// Realized P/L of all the matched positions, not only this page
+    pub realized: DbProfitSummary,
+    pub positions: Vec<Position>,
+}
+
+// The account is stored in the form of Address, the query must match it.
+fn normalize_account(q: &mut PositionQuery) -> anyhow::Result<()> {
+    if let Some(account_id) = &q.account_id {
+        q.account_id = Some(parse_address(account_id.as_str())?.to_string());
+    }
+    Ok(())
+}
+
+pub async fn get_account_history(
+    pg: Option<Arc<PG>>,
+    mut q: PositionQuery,
+) -> anyhow::Result<PositionHistory> {
+    let pg = get_pg(pg)?;
+    if q.account_id.is_none() {
+        return Err(ClientError::HttpServerError("account is required".to_string()).into());
+    }
+    normalize_account(&mut q)?;
+    let (total, positions) = pg.query_position_history(&q).await?;
+    let realized = pg.query_realized_profit(&q).await?;
+    Ok(PositionHistory {
+        total,
+        realized,
+        positions,
+    })
+}
+
+pub async fn get_market_stats(
+    pg: Option<Arc<PG>>,
+    mut q: PositionQuery,
+) -> anyhow::Result<Vec<DbMarketStats>> {
+    normalize_account(&mut q)?;
+    get_pg(pg)?.query_market_stats(&q).await
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MarketInfo {
+    #[serde(flatten)]
+    pub market: Market,
+    /// Live buy/sell price, none before the first price of the symbol arrives
+    pub price: Option<bot::state::Price>,
+    pub current_fund_fee: u64,
+    pub current_spread_fee: Option<u64>,
+    /// Why the prices are rejected, none if the price is healthy
+    pub price_health: Option<PriceHealth>,
+}
+
+pub fn get_market_list(ssm: SharedStateMap, prefix: String) -> anyhow::Result<Vec<MarketInfo>> {
+    match Prefix::from_str(prefix.as_str())? {
+        Prefix::Active => {}
+        Prefix::History => {
+            return Err(ClientError::HttpServerError(
+                "market history is not kept in memory".to_string(),
+            )
+            .into())
+        }
+    }
+    let markets: Vec<Market> = ssm.market.iter().map(|m| m.value().clone()).collect();
+    let mut rs: Vec<MarketInfo> = Vec::with_capacity(markets.len());
+    for market in markets {
+        let price = ssm.price.get(&market.symbol).map(|p| *p.value());
+        rs.push(MarketInfo {
+            price_health: ssm
+                .price_health
+                .get(&market.symbol)
+                .map(|h| h.value().clone()),
+            current_fund_fee: market.get_fund_fee(),
+            current_spread_fee: price.map(|p| market.get_spread_fee(p.real_price)),
+            price,
+            market,
+        });
+    }
+    Ok(rs)
+}
+
+pub fn get_cron_jobs(jobs: Arc<DmJobStatus>) -> anyhow::Result<Vec<JobStatus>> {
+    Ok(cron::list_jobs(&jobs))
+}
 
 pub async fn get_symbol_list(ssm: SharedStateMap) -> anyhow::Result<Vec<String>> {
     let mut rs: Vec<String> = Vec::new();
@@ -80,6 +332,9 @@ fn get_price_history_query(
 
 fn get_start_and_window(range: &str) -> anyhow::Result<(String, String)> {
     match range {
+        "1m" => Ok(("-1d".to_string(), "1m".to_string())),
+        "5m" => Ok(("-5d".to_string(), "5m".to_string())),
+        "15m" => Ok(("-15d".to_string(), "15m".to_string())),
         "1H" => Ok(("-4d".to_string(), "1h".to_string())),
         "1D" => Ok(("-90d".to_string(), "1d".to_string())),
         "1W" => Ok(("-1y".to_string(), "1w".to_string())),
@@ -106,14 +361,7 @@ pub async fn init_price_history_cache(ssm: SharedStateMap, db: Arc<Influxdb>) {
 }
 
 async fn init_price_cache_with_symbol(symbol: String, db: Arc<Influxdb>) -> anyhow::Result<()> {
-    let ranges = vec![
-        "1H".to_string(),
-        "1D".to_string(),
-        "1W".to_string(),
-        "1M".to_string(),
-        "1Y".to_string(),
-    ];
-    for range in ranges {
+    for range in KLINE_RANGES.map(|r| r.to_string()) {
         if let Err(e) =
             get_price_history_with_cache(symbol.clone(), range.clone(), db.clone()).await
         {
@@ -353,16 +601,17 @@ fn get_broadcast_price_status(
     dps: &DmPriceStatus,
     org_price: &OrgPrice,
 ) -> anyhow::Result<Option<PriceStatus>> {
+    let price = org_price.price.raw();
     if let Some(mut price_status) = dps.get_mut(&org_price.symbol) {
-        if org_price.price > price_status.value().high_24h {
-            price_status.value_mut().high_24h = org_price.price;
+        if price > price_status.value().high_24h {
+            price_status.value_mut().high_24h = price;
         }
-        if org_price.price < price_status.value().low_24h {
-            price_status.value_mut().low_24h = org_price.price;
+        if price < price_status.value().low_24h {
+            price_status.value_mut().low_24h = price;
         }
         let mut price_status = price_status.value().clone();
-        price_status.current_price = org_price.price;
-        price_status.change = org_price.price - price_status.opening_price;
+        price_status.current_price = price;
+        price_status.change = price - price_status.opening_price;
         price_status.change_rate =
             com::f64_round_4(price_status.change as f64 / price_status.opening_price as f64);
         return Ok(Some(price_status));
@@ -379,10 +628,13 @@ impl PriceBroadcast {
         dps: DmPriceStatus,
         price_ws_rx: WsWatchRx,
         db: Arc<Influxdb>,
-    ) -> (Self, PriceStatusWatchRx) {
+    ) -> (Self, PriceStatusWatchRx, KlineWatchRx) {
         let (shutdown_tx, shutdown_rx) = Task::new_shutdown_channel();
         let (price_status_ws_tx, price_status_ws_rx) =
             broadcast::channel(ssm.ws_state.supported_symbol.len());
+        // a tick changes the klines of all the ranges, and closes some of them
+        let (kline_ws_tx, kline_ws_rx) =
+            broadcast::channel(ssm.ws_state.supported_symbol.len() * KLINE_RANGES.len() * 2);
         let task = Task::new(
             "price broadcast",
             shutdown_tx,
@@ -390,6 +642,7 @@ impl PriceBroadcast {
                 dps.clone(),
                 price_ws_rx,
                 price_status_ws_tx,
+                kline_ws_tx,
                 shutdown_rx,
             )),
         );
@@ -398,19 +651,28 @@ impl PriceBroadcast {
                 error!("init price status error: {}", e);
             }
         });
-        (Self { task }, PriceStatusWatchRx(price_status_ws_rx))
+        (
+            Self { task },
+            PriceStatusWatchRx(price_status_ws_rx),
+            KlineWatchRx(kline_ws_rx),
+        )
     }
     pub async fn shutdown(self) {
         self.task.shutdown().await;
     }
+    pub fn into_tasks(self) -> Vec<Task> {
+        vec![self.task]
+    }
 }
 
 async fn broadcast_price(
     dps: DmPriceStatus,
     mut price_ws_rx: WsWatchRx,
     price_status_ws_tx: broadcast::Sender<PriceStatus>,
+    kline_ws_tx: broadcast::Sender<Kline>,
     mut shutdown_rx: TaskStopRx,
 ) -> anyhow::Result<()> {
+    let mut klines = KlineAggregator::new();
     loop {
         tokio::select! {
             r = &mut shutdown_rx => {
@@ -430,16 +692,70 @@ async fn broadcast_price(
                         error!("broadcast price status error: {}", e);
                     }
                 }
+                for kline in klines.update(&price) {
+                    if let Err(e) = kline_ws_tx.send(kline) {
+                        error!("broadcast kline error: {}", e);
+                    }
+                }
             }
         }
     }
     Ok(())
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+pub const WS_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct SubRequest {
-    pub symbol: String,
+    /// The protocol version, 0 is the legacy single symbol request
+    #[serde(default)]
+    pub version: u32,
+    /// The request id, sent back in the ack or error reply
+    #[serde(default)]
+    pub id: Option<u64>,
+    #[serde(default)]
     pub sub_type: SubType,
+    #[serde(default)]
+    pub channels: Vec<SubChannel>,
+    #[serde(default)]
+    pub symbol: String,
+    #[serde(default)]
+    pub symbols: Vec<String>,
+}
+
+/// The personal message signature of the auth challenge, base64 encoded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthRequest {
+    pub signature: String,
+}
+
+fn new_auth_challenge(account: &Address) -> AuthChallenge {
+    let nonce: [u8; 16] = rand::random();
+    AuthChallenge {
+        account: account.copy(),
+        message: format!(
+            "Scale websocket login\naccount: {}\nnonce: {}",
+            account,
+            Hex::encode(nonce)
+        ),
+    }
+}
+
+/// Check the challenge is signed by the owner of the account.
+fn verify_auth(
+    ssm: &SharedStateMap,
+    challenge: &AuthChallenge,
+    req: &AuthRequest,
+) -> anyhow::Result<AuthSuccess> {
+    let signer =
+        signature::verify_personal_message(challenge.message.as_bytes(), req.signature.as_str())?;
+    match ssm.account.get(&challenge.account) {
+        Some(account) if account.owner == signer => Ok(AuthSuccess {
+            account: challenge.account.copy(),
+            owner: signer,
+        }),
+        _ => Err(ClientError::InvalidWsAddressSigner.into()),
+    }
 }
 
 pub async fn handle_ws(
@@ -447,30 +763,60 @@ pub async fn handle_ws(
     mut socket: WebSocket,
     address: Option<Address>,
     mut price_status_rx: PriceStatusWatchRx,
+    mut kline_rx: KlineWatchRx,
     mut event_ws_rx: WsWatchRx,
 ) {
     // let (tx, mut rx) = mpsc::channel::<WsSrvMessage>(10);
-    let symbols_set: DashSet<String> = DashSet::new();
-    // let is_login = address.is_some();
-    let mut user_account = Address::default();
+    let mut subscriptions: BTreeSet<Subscription> = BTreeSet::new();
+    let mut heartbeat = time::interval_at(
+        Instant::now() + WS_HEARTBEAT_INTERVAL,
+        WS_HEARTBEAT_INTERVAL,
+    );
+    // the private events are only sent once the account owner has signed the challenge
+    let mut user_account: Option<Address> = None;
+    let mut challenge: Option<AuthChallenge> = None;
     if let Some(addr) = address {
-        user_account = addr;
+        let c = new_auth_challenge(&addr);
+        if let Err(e) = socket
+            .send(Message::Text(
+                WsSrvMessage::AuthChallenge(c.clone()).into_txt(),
+            ))
+            .await
+        {
+            error!("send ws auth challenge error: {}", e);
+            return;
+        }
+        challenge = Some(c);
     }
     loop {
         tokio::select! {
+            _ = heartbeat.tick() => {
+                if let Err(e) = socket.send(Message::Text(WsSrvMessage::Heartbeat(Utc::now().timestamp()).into_txt())).await {
+                    error!("send ws heartbeat error: {}", e);
+                    break;
+                }
+            }
             Ok(price_status) = price_status_rx.0.recv() => {
                 // debug!("got price from ws broadcast channel: {:?}", price);
-                if symbols_set.contains(&price_status.symbol) {
+                if is_subscribed(&subscriptions, SubChannel::Price, Some(&price_status.symbol)) {
                     if let Err(e) = socket.send(Message::Text(WsSrvMessage::PriceUpdate(price_status).into_txt())).await {
                         error!("send ws message error: {}", e);
                         break;
                     }
                 }
             }
+            Ok(kline) = kline_rx.0.recv() => {
+                if is_subscribed(&subscriptions, SubChannel::Kline, Some(&kline.symbol)) {
+                    if let Err(e) = socket.send(Message::Text(WsSrvMessage::KlineUpdate(kline).into_txt())).await {
+                        error!("send ws message error: {}", e);
+                        break;
+                    }
+                }
+            }
             Ok(ws_event) = event_ws_rx.0.recv() => {
                 match ws_event {
                     WsSrvMessage::AccountUpdate(data) => {
-                        if data.id == user_account {
+                        if user_account.as_ref() == Some(&data.id) && is_subscribed(&subscriptions, SubChannel::Account, None) {
                             if let Err(e) = socket.send(Message::Text(WsSrvMessage::AccountUpdate(data).into_txt())).await {
                                 error!("send ws message error: {}", e);
                                 break;
@@ -478,7 +824,7 @@ pub async fn handle_ws(
                         }
                     },
                     WsSrvMessage::PositionUpdate(data) => {
-                        if data.account_id == user_account {
+                        if user_account.as_ref() == Some(&data.account_id) && is_subscribed(&subscriptions, SubChannel::Positions, None) {
                             debug!("got position update: {:?}", data);
                             if let Err(e) = socket.send(Message::Text(WsSrvMessage::PositionUpdate(data).into_txt())).await {
                                 error!("send ws message error: {}", e);
@@ -487,7 +833,7 @@ pub async fn handle_ws(
                         }
                     },
                     WsSrvMessage::PositionOpen(data) => {
-                        if data.account_id == user_account {
+                        if user_account.as_ref() == Some(&data.account_id) && is_subscribed(&subscriptions, SubChannel::Positions, None) {
                             if let Err(e) = socket.send(Message::Text(WsSrvMessage::PositionOpen(data).into_txt())).await {
                                 error!("send ws message error: {}", e);
                                 break;
@@ -495,7 +841,7 @@ pub async fn handle_ws(
                         }
                     },
                     WsSrvMessage::PositionClose(data) => {
-                        if data.account_id == user_account {
+                        if user_account.as_ref() == Some(&data.account_id) && is_subscribed(&subscriptions, SubChannel::Positions, None) {
                             if let Err(e) = socket.send(Message::Text(WsSrvMessage::PositionClose(data).into_txt())).await {
                                 error!("send ws message error: {}", e);
                                 break;
@@ -503,7 +849,7 @@ pub async fn handle_ws(
                         }
                     },
                     WsSrvMessage::SpreadUpdate(data) => {
-                        if symbols_set.contains(&data.symbol) {
+                        if is_subscribed(&subscriptions, SubChannel::Spread, Some(&data.symbol)) {
                             if let Err(e) = socket.send(Message::Text(WsSrvMessage::SpreadUpdate(data).into_txt())).await {
                                 error!("send ws message error: {}", e);
                                 break;
@@ -525,7 +871,47 @@ pub async fn handle_ws(
                     Some(Ok(msg))=>{
                         match msg {
                             Message::Text(t) => {
-                                handle_ws_events(ssm.clone(), t, &symbols_set);
+                                if let Ok(req) = serde_json::from_str::<AuthRequest>(t.as_str()) {
+                                    let c = match challenge.take() {
+                                        Some(c) => c,
+                                        None => {
+                                            debug!("got auth request without challenge");
+                                            continue;
+                                        }
+                                    };
+                                    let reply = match verify_auth(&ssm, &c, &req) {
+                                        Ok(auth) => {
+                                            info!("ws authenticated account: {}, owner: {}", auth.account, auth.owner);
+                                            user_account = Some(auth.account.copy());
+                                            // the private events are sent to an authenticated session by default
+                                            for channel in [SubChannel::Account, SubChannel::Positions] {
+                                                subscriptions.insert(Subscription { channel, symbol: None });
+                                            }
+                                            WsSrvMessage::AuthSuccess(auth)
+                                        }
+                                        Err(e) => {
+                                            debug!("ws auth error: {}", e);
+                                            // the nonce is used, send a new challenge for the next try
+                                            let next = new_auth_challenge(&c.account);
+                                            if let Err(e) = socket.send(Message::Text(WsSrvMessage::AuthError(e.to_string()).into_txt())).await {
+                                                error!("send ws message error: {}", e);
+                                                break;
+                                            }
+                                            challenge = Some(next.clone());
+                                            WsSrvMessage::AuthChallenge(next)
+                                        }
+                                    };
+                                    if let Err(e) = socket.send(Message::Text(reply.into_txt())).await {
+                                        error!("send ws message error: {}", e);
+                                        break;
+                                    }
+                                } else {
+                                    let reply = handle_ws_events(&ssm, t.as_str(), &mut subscriptions, user_account.is_some());
+                                    if let Err(e) = socket.send(Message::Text(reply.into_txt())).await {
+                                        error!("send ws message error: {}", e);
+                                        break;
+                                    }
+                                }
                             }
                             Message::Binary(_) => {
                                 debug!("client sent binary data");
@@ -565,28 +951,208 @@ pub async fn handle_ws(
     // info!("client disconnected, clean connection :{:?}", user_account);
 }
 
+fn is_subscribed(
+    subscriptions: &BTreeSet<Subscription>,
+    channel: SubChannel,
+    symbol: Option<&String>,
+) -> bool {
+    subscriptions.contains(&Subscription {
+        channel,
+        symbol: symbol.cloned(),
+    })
+}
+
+// Handle a client request and get the ack or error reply, a bad request never closes the connection.
 fn handle_ws_events(
-    ssm: SharedStateMap,
-    msg: String,
-    // address: &Address,
-    symbols_set: &DashSet<String>,
-) {
-    let sub_req: SubRequest = serde_json::from_str(msg.as_str()).unwrap();
-    let symbol = sub_req.symbol;
-    if !ssm.ws_state.is_supported_symbol(&symbol) {
-        return;
+    ssm: &SharedStateMap,
+    msg: &str,
+    subscriptions: &mut BTreeSet<Subscription>,
+    is_authenticated: bool,
+) -> WsSrvMessage {
+    let sub_req: SubRequest = match serde_json::from_str(msg) {
+        Ok(req) => req,
+        Err(e) => {
+            debug!("invalid ws request: {}, error: {}", msg, e);
+            return WsSrvMessage::Error(WsError {
+                id: None,
+                error: ClientError::InvalidWsRequest(e.to_string()).to_string(),
+            });
+        }
+    };
+    let id = sub_req.id;
+    match handle_sub_request(ssm, sub_req, subscriptions, is_authenticated) {
+        Ok(ack) => WsSrvMessage::Ack(ack),
+        Err(e) => WsSrvMessage::Error(WsError {
+            id,
+            error: e.to_string(),
+        }),
+    }
+}
+
+// The request is checked as a whole, nothing is changed if one of its subscriptions is invalid.
+fn handle_sub_request(
+    ssm: &SharedStateMap,
+    sub_req: SubRequest,
+    subscriptions: &mut BTreeSet<Subscription>,
+    is_authenticated: bool,
+) -> anyhow::Result<WsAck> {
+    if sub_req.version > WS_PROTOCOL_VERSION {
+        return Err(ClientError::UnsupportedWsVersion(sub_req.version).into());
     }
     let sub_type = sub_req.sub_type;
     match sub_type {
-        SubType::Subscribe => {
-            // ssm.ws_state.add_symbol_sub(symbol.clone(), address.copy());
-            symbols_set.insert(symbol.clone());
+        SubType::Subscribe | SubType::Unsubscribe => {}
+        SubType::List => {
+            return Ok(WsAck {
+                id: sub_req.id,
+                sub_type,
+                subscriptions: subscriptions.iter().cloned().collect(),
+            });
+        }
+        SubType::None => {
+            return Err(ClientError::InvalidWsRequest("unknown sub_type".to_string()).into());
+        }
+    }
+    let mut channels = sub_req.channels;
+    // the legacy request is the price and spread of one symbol
+    if sub_req.version == 0 && channels.is_empty() {
+        channels = vec![SubChannel::Price, SubChannel::Spread];
+    }
+    if channels.is_empty() {
+        return Err(ClientError::InvalidWsRequest("no channel".to_string()).into());
+    }
+    let mut symbols = sub_req.symbols;
+    if !sub_req.symbol.is_empty() {
+        symbols.push(sub_req.symbol);
+    }
+    let mut changes: Vec<Subscription> = vec![];
+    for channel in channels {
+        if channel.is_private() {
+            if sub_type == SubType::Subscribe && !is_authenticated {
+                return Err(ClientError::WsNotAuthenticated.into());
+            }
+            changes.push(Subscription {
+                channel,
+                symbol: None,
+            });
+            continue;
+        }
+        if symbols.is_empty() {
+            return Err(ClientError::InvalidWsRequest(format!(
+                "no symbol for channel {:?}",
+                channel
+            ))
+            .into());
+        }
+        for symbol in symbols.iter() {
+            if !ssm.ws_state.is_supported_symbol(symbol) {
+                return Err(
+                    ClientError::InvalidWsRequest(format!("unknown symbol: {}", symbol)).into(),
+                );
+            }
+            changes.push(Subscription {
+                channel,
+                symbol: Some(symbol.clone()),
+            });
+        }
+    }
+    for s in changes {
+        if sub_type == SubType::Subscribe {
+            subscriptions.insert(s);
+        } else {
+            subscriptions.remove(&s);
+        }
+    }
+    Ok(WsAck {
+        id: sub_req.id,
+        sub_type,
+        subscriptions: subscriptions.iter().cloned().collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::machine::StateMap;
+    use dashmap::DashSet;
+    use shared_crypto::intent::{Intent, PersonalMessage};
+    use std::collections::HashMap;
+    use sui_keys::keystore::{AccountKeystore, InMemKeystore, Keystore};
+    use sui_types::{base_types::SuiAddress, crypto::EncodeDecodeBase64};
+
+    fn account(id: Address, owner: Address) -> Account {
+        Account {
+            id,
+            owner,
+            offset: 0,
+            balance: 0,
+            isolated_balance: 0,
+            profit: 0,
+            margin_total: 0,
+            margin_cross_total: 0,
+            margin_isolated_total: 0,
+            margin_cross_buy_total: 0,
+            margin_cross_sell_total: 0,
+            margin_isolated_buy_total: 0,
+            margin_isolated_sell_total: 0,
+            cross_position_idx: HashMap::new(),
+            isolated_position_idx: vec![],
         }
-        SubType::Unsubscribe => {
-            // ssm.ws_state.remove_symbol_sub(&symbol, &address);
-            // symbols.retain(|s| s != &symbol);
-            symbols_set.remove(&symbol);
+    }
+
+    fn sign(keystore: &Keystore, signer: &SuiAddress, message: &str) -> AuthRequest {
+        let msg = PersonalMessage {
+            message: message.as_bytes().to_vec(),
+        };
+        let signature = keystore
+            .sign_secure(signer, &msg, Intent::personal_message())
+            .unwrap();
+        AuthRequest {
+            signature: signature.encode_base64(),
         }
-        SubType::None => {}
+    }
+
+    fn client_error(rs: anyhow::Result<AuthSuccess>) -> Option<ClientError> {
+        rs.err().and_then(|e| e.downcast::<ClientError>().ok())
+    }
+
+    #[test]
+    fn test_verify_auth() {
+        let keystore = Keystore::from(InMemKeystore::new_insecure_for_tests(2));
+        let addresses = keystore.addresses();
+        let (owner, other) = (addresses[0], addresses[1]);
+        let ssm = Arc::new(StateMap::new(DashSet::new()).unwrap());
+        let id = Address::new(vec![7; 32]);
+        ssm.account
+            .insert(id.copy(), account(id.copy(), Address::new(owner.to_vec())));
+        let challenge = new_auth_challenge(&id);
+
+        // signed by the owner of the account
+        let rs = verify_auth(
+            &ssm,
+            &challenge,
+            &sign(&keystore, &owner, &challenge.message),
+        );
+        let success = rs.unwrap();
+        assert_eq!(success.account, id);
+        assert_eq!(success.owner, Address::new(owner.to_vec()));
+        // a valid signature, but not of the owner
+        let rs = verify_auth(
+            &ssm,
+            &challenge,
+            &sign(&keystore, &other, &challenge.message),
+        );
+        assert!(matches!(
+            client_error(rs),
+            Some(ClientError::InvalidWsAddressSigner)
+        ));
+        // signed over the nonce of another challenge
+        let stale = new_auth_challenge(&id);
+        assert_ne!(stale.message, challenge.message);
+        let rs = verify_auth(&ssm, &challenge, &sign(&keystore, &owner, &stale.message));
+        assert!(matches!(
+            client_error(rs),
+            Some(ClientError::InvalidSignature(_))
+        ));
     }
 }
@@ -3,6 +3,7 @@ use crate::config::{self, Config as cfg};
 use log::debug;
 use std::sync::Arc;
 use std::{fs, path::PathBuf, str::FromStr, time::Duration};
+use sui_keys::keystore::AccountKeystore;
 use sui_sdk::rpc_types::{
     ObjectChange, SuiTransactionBlockResponse, SuiTransactionBlockResponseOptions,
 };
@@ -38,6 +39,122 @@ pub struct Config {
     pub scale_nft_admin_id: ObjectID,
     pub price_config: config::PriceConfig,
     pub sql_db_config: config::SqlDbConfig,
+    #[serde(default)]
+    pub cron_config: config::CronConfig,
+    #[serde(default)]
+    pub ws_config: config::WsConfig,
+    #[serde(default)]
+    pub gas_pool_config: GasPoolConfig,
+    #[serde(default)]
+    pub executor_config: ExecutorConfig,
+    #[serde(default)]
+    pub signer_config: SignerConfig,
+    #[serde(default)]
+    pub indexer_config: IndexerConfig,
+}
+
+/// The gas coins of the active address leased to the transactions.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GasPoolConfig {
+    /// The number of gas coins the SUI is split into, the max transactions in flight
+    pub size: usize,
+    /// The coins below this balance (in MIST) are merged back as dust
+    pub min_balance: u64,
+    /// Max milliseconds to wait for a free gas coin
+    pub acquire_timeout_ms: u64,
+}
+impl Default for GasPoolConfig {
+    fn default() -> Self {
+        Self {
+            size: 8,
+            min_balance: 500_000_000,
+            acquire_timeout_ms: 10000,
+        }
+    }
+}
+
+/// How the transactions are submitted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExecutorConfig {
+    /// Dry run the transaction before submitting it, a failed dry run is not submitted
+    pub dry_run: bool,
+    /// Max resubmissions on a transient rpc error or a stale gas coin
+    pub max_retries: u32,
+    /// Milliseconds to wait before the first retry, doubled on each retry
+    pub retry_interval_ms: u64,
+    /// Only dry run the transactions and never sign them, set by the --dry-run flag
+    #[serde(skip_serializing, skip_deserializing)]
+    pub simulate: bool,
+}
+impl Default for ExecutorConfig {
+    fn default() -> Self {
+        Self {
+            dry_run: false,
+            max_retries: 3,
+            retry_interval_ms: 500,
+            simulate: false,
+        }
+    }
+}
+
+/// Walk the checkpoints for the object changes instead of subscribing to the events,
+/// only on a full node, the progress is kept in postgres.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IndexerConfig {
+    /// Start the indexer instead of the event subscription, also set by --indexer
+    pub enable: bool,
+    /// The checkpoint to start from without progress, the one the package was published in.
+    /// If not set, the objects are synced and the indexer starts from the latest checkpoint
+    pub start_checkpoint: Option<u64>,
+    /// Milliseconds to wait for the next checkpoint at the tip of the chain
+    pub poll_interval_ms: u64,
+}
+impl Default for IndexerConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            start_checkpoint: None,
+            poll_interval_ms: 1000,
+        }
+    }
+}
+
+/// The roles signing the transactions, each role may use its own key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Role {
+    /// The commands of the cli and the calls of no other role
+    Default,
+    /// Force liquidation, auto close and limit opening of the positions
+    Liquidator,
+    /// The pyth price updates
+    Oracle,
+    /// The fund fee processing and the opening price updates
+    FundFee,
+}
+impl Role {
+    pub const ALL: [Role; 4] = [Role::Default, Role::Liquidator, Role::Oracle, Role::FundFee];
+}
+
+/// The keystore addresses signing for each role, None signs with the default one.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct SignerConfig {
+    /// The default signer, the active address of the sui cli if None, overridden by --signer
+    pub default: Option<SuiAddress>,
+    pub liquidator: Option<SuiAddress>,
+    pub oracle: Option<SuiAddress>,
+    pub fund_fee: Option<SuiAddress>,
+    /// The url of the remote signer holding the keys, the local keystore signs if None
+    pub remote_url: Option<String>,
+    /// The bearer token of the remote signer, required to serve it off the loopback address
+    pub auth_token: Option<String>,
+    /// The module::function of the enter and pyth_network modules the remote signer signs
+    pub allowed_functions: Vec<String>,
+    /// The packages the remote signer signs the calls of, besides the scale, pyth and sui ones
+    pub allowed_packages: Vec<ObjectID>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -77,6 +194,33 @@ impl Context {
             .ok_or_else(|| ClientError::NoActiveAccount("no active account".to_string()))?)
     }
 
+    /// The address signing for the role, each one must have its key in the keystore
+    /// unless a remote signer signs.
+    pub fn get_signer(&self, role: Role) -> anyhow::Result<SuiAddress> {
+        let signers = &self.config.signer_config;
+        let signer = match role {
+            Role::Default => None,
+            Role::Liquidator => signers.liquidator,
+            Role::Oracle => signers.oracle,
+            Role::FundFee => signers.fund_fee,
+        };
+        let address = match signer.or(signers.default) {
+            Some(address) => address,
+            None => self.get_active_address()?,
+        };
+        // the keys of a remote signer are not in the local keystore
+        if signers.remote_url.is_none()
+            && !self.wallet.config.keystore.addresses().contains(&address)
+        {
+            return Err(ClientError::ConfigError(format!(
+                "signer {} of {:?} is not in the keystore",
+                address, role
+            ))
+            .into());
+        }
+        Ok(address)
+    }
+
     pub fn get_feed_ids(&self) -> anyhow::Result<Vec<ObjectID>> {
         let mut ids = Vec::new();
         for i in self.config.price_config.get_feed_ids(None) {
@@ -154,6 +298,12 @@ impl Default for Config {
             scale_nft_admin_id: default_id,
             price_config: config::PriceConfig::default(),
             sql_db_config: config::SqlDbConfig::default(),
+            cron_config: config::CronConfig::default(),
+            ws_config: config::WsConfig::default(),
+            gas_pool_config: GasPoolConfig::default(),
+            executor_config: ExecutorConfig::default(),
+            signer_config: SignerConfig::default(),
+            indexer_config: IndexerConfig::default(),
         }
     }
 }
@@ -184,6 +334,13 @@ impl cfg for Config {
                 self.scale_nft_package_id = c.scale_nft_package_id;
                 self.scale_nft_admin_id = c.scale_nft_admin_id;
                 self.price_config = c.price_config;
+                self.sql_db_config = c.sql_db_config;
+                self.cron_config = c.cron_config;
+                self.ws_config = c.ws_config;
+                self.gas_pool_config = c.gas_pool_config;
+                self.executor_config = c.executor_config;
+                self.signer_config = c.signer_config;
+                self.indexer_config = c.indexer_config;
 
                 // if c.scale_package_id == ObjectID::from_str(DEFAULT_OBJECT_ID).unwrap() {
                 //     return self.init();
@@ -214,6 +371,12 @@ impl cfg for Config {
     fn get_price_config(&self) -> config::PriceConfig {
         self.price_config.clone()
     }
+    fn get_cron_config(&self) -> config::CronConfig {
+        self.cron_config.clone()
+    }
+    fn get_ws_config(&self) -> config::WsConfig {
+        self.ws_config.clone()
+    }
     fn get(&mut self) {
         if !self.scale_config_file.exists() {
             if let Err(e) = self.init() {
@@ -0,0 +1,318 @@
+// Sign and submit the transactions, returning their effects as a typed outcome.
+// A transaction may be dry run first, and is resubmitted on a transient rpc error
+// or when its gas coin version is stale. In the simulate mode it is only dry run.
+use crate::bot::state::{Address, BalanceChange, TxOutcome, TxStatus};
+use crate::com::ClientError;
+use crate::sui::config::{Ctx, ExecutorConfig};
+use crate::sui::gas::GasLease;
+use crate::sui::signer::SharedSigner;
+use log::*;
+use shared_crypto::intent::Intent;
+use sui_json_rpc_types::{
+    BalanceChange as SuiBalanceChange, SuiExecutionStatus, SuiTransactionBlockEffects,
+    SuiTransactionBlockEffectsAPI, SuiTransactionBlockResponseOptions,
+};
+use sui_types::{
+    base_types::ObjectID,
+    crypto::Signature,
+    quorum_driver_types::ExecuteTransactionRequestType,
+    transaction::{Transaction, TransactionData, TransactionDataAPI},
+};
+use tokio::time::{self, Duration};
+
+// the longest wait between two retries
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(30);
+
+// A transaction and the gas coin leased to it.
+pub struct TxData {
+    pub data: TransactionData,
+    pub gas: Option<GasLease>,
+}
+impl From<TransactionData> for TxData {
+    fn from(data: TransactionData) -> Self {
+        Self { data, gas: None }
+    }
+}
+
+pub struct Executor {
+    ctx: Ctx,
+    config: ExecutorConfig,
+    signer: SharedSigner,
+}
+
+impl Executor {
+    pub fn new(ctx: Ctx, config: ExecutorConfig, signer: SharedSigner) -> Self {
+        Self {
+            ctx,
+            config,
+            signer,
+        }
+    }
+
+    // signed by the key of the sender
+    async fn sign(&self, data: &TransactionData) -> anyhow::Result<Signature> {
+        self.signer
+            .sign(data.sender(), data, Intent::sui_transaction())
+            .await
+    }
+
+    /// Dry run the transaction, nothing is signed.
+    pub async fn dry_run(&self, data: &TransactionData) -> anyhow::Result<TxOutcome> {
+        let response = self
+            .ctx
+            .client
+            .read_api()
+            .dry_run_transaction_block(data.clone())
+            .await?;
+        let mut outcome = to_outcome(&response.effects, &response.balance_changes);
+        outcome.simulated = true;
+        Ok(outcome)
+    }
+
+    /// Submit the transaction, a failed transaction is returned as a classified error.
+    pub async fn execute(&self, tx: TxData) -> anyhow::Result<TxOutcome> {
+        let TxData { mut data, mut gas } = tx;
+        if self.config.simulate {
+            return self.dry_run(&data).await;
+        }
+        if self.config.dry_run {
+            let outcome = self.dry_run(&data).await?;
+            if let TxStatus::Failure(e) = &outcome.status {
+                return Err(classify_failure(e).into());
+            }
+            debug!("dry run: {}", outcome);
+        }
+        let mut retries = 0;
+        let mut signature = self.sign(&data).await?;
+        loop {
+            let opt = SuiTransactionBlockResponseOptions::new()
+                .with_effects()
+                .with_balance_changes();
+            let result = self
+                .ctx
+                .client
+                .quorum_driver_api()
+                .execute_transaction_block(
+                    Transaction::from_data(
+                        data.clone(),
+                        Intent::sui_transaction(),
+                        vec![signature.clone()],
+                    ),
+                    opt,
+                    Some(ExecuteTransactionRequestType::WaitForLocalExecution),
+                )
+                .await;
+            let err = match result {
+                Ok(response) => {
+                    if let Some(gas) = gas.take() {
+                        gas.release(&data, response.effects.as_ref());
+                    }
+                    let effects = response.effects.ok_or_else(|| {
+                        ClientError::TransactionExecutionFailure(format!(
+                            "no effects of transaction {}",
+                            response.digest
+                        ))
+                    })?;
+                    let balance_changes = response.balance_changes.unwrap_or_default();
+                    let outcome = to_outcome(&effects, &balance_changes);
+                    return match &outcome.status {
+                        TxStatus::Success => Ok(outcome),
+                        TxStatus::Failure(e) => Err(classify_failure(e).into()),
+                    };
+                }
+                Err(e) => e.to_string(),
+            };
+            let version_conflict = is_version_conflict(&err);
+            if retries >= self.config.max_retries || !(version_conflict || is_transient(&err)) {
+                return Err(if version_conflict {
+                    ClientError::ObjectVersionConflict(err).into()
+                } else {
+                    ClientError::RpcError(err).into()
+                });
+            }
+            retries += 1;
+            warn!(
+                "submit transaction error, retry {}/{}: {}",
+                retries, self.config.max_retries, err
+            );
+            time::sleep(retry_delay(self.config.retry_interval_ms, retries)).await;
+            // the gas coin is the only owned object the transaction may lock
+            if version_conflict {
+                if let Some(gas) = gas.as_mut() {
+                    gas.refresh().await?;
+                    data.gas_data_mut().payment = vec![gas.object_ref()];
+                    signature = self.sign(&data).await?;
+                }
+            }
+        }
+    }
+}
+
+// The wait before the retry, doubled from the interval on each retry.
+fn retry_delay(interval_ms: u64, retries: u32) -> Duration {
+    let factor = 1u64
+        .checked_shl(retries.saturating_sub(1))
+        .unwrap_or(u64::MAX);
+    Duration::from_millis(interval_ms.saturating_mul(factor)).min(RETRY_MAX_DELAY)
+}
+
+fn to_address(id: ObjectID) -> Address {
+    Address::new(id.to_vec())
+}
+
+pub fn to_outcome(
+    effects: &SuiTransactionBlockEffects,
+    balance_changes: &[SuiBalanceChange],
+) -> TxOutcome {
+    let gas = effects.gas_cost_summary();
+    TxOutcome {
+        digest: effects.transaction_digest().to_string(),
+        status: match effects.status() {
+            SuiExecutionStatus::Success => TxStatus::Success,
+            SuiExecutionStatus::Failure { error } => TxStatus::Failure(error.clone()),
+        },
+        computation_cost: gas.computation_cost,
+        storage_cost: gas.storage_cost,
+        storage_rebate: gas.storage_rebate,
+        created: effects
+            .created()
+            .iter()
+            .map(|o| to_address(o.reference.object_id))
+            .collect(),
+        mutated: effects
+            .mutated()
+            .iter()
+            .map(|o| to_address(o.reference.object_id))
+            .collect(),
+        deleted: effects
+            .deleted()
+            .iter()
+            .map(|o| to_address(o.object_id))
+            .collect(),
+        balance_changes: balance_changes
+            .iter()
+            .map(|c| BalanceChange {
+                owner: c.owner.to_string(),
+                coin_type: c.coin_type.to_string(),
+                amount: c.amount,
+            })
+            .collect(),
+        simulated: false,
+    }
+}
+
+fn is_version_conflict(err: &str) -> bool {
+    err.contains("ObjectVersionUnavailableForConsumption")
+        || err.contains("is not available for consumption")
+}
+
+fn is_transient(err: &str) -> bool {
+    let err = err.to_lowercase();
+    [
+        "timed out",
+        "timeout",
+        "connection",
+        "transport",
+        "too many requests",
+        "429",
+        "502",
+        "503",
+        "504",
+    ]
+    .iter()
+    .any(|s| err.contains(s))
+}
+
+/// Classify the execution failure of the effects.
+pub fn classify_failure(error: &str) -> ClientError {
+    if let Some((location, code)) = parse_move_abort(error) {
+        return ClientError::MoveAbort(location, code);
+    }
+    if error.starts_with("InsufficientGas") {
+        return ClientError::InsufficientGas(error.to_string());
+    }
+    ClientError::TransactionExecutionFailure(error.to_string())
+}
+
+// Parse the module, function and code of an abort like:
+// MoveAbort(MoveLocation { module: ModuleId { address: 0x.., name: Identifier("enter") },
+// function: 12, instruction: 34, function_name: Some("force_liquidation") }, 2) in command 4
+fn parse_move_abort(error: &str) -> Option<(String, u64)> {
+    let abort = error.strip_prefix("MoveAbort(")?;
+    let between = |s: &str, start: &str, end: &str| -> Option<String> {
+        let from = s.find(start)? + start.len();
+        let to = s[from..].find(end)? + from;
+        Some(s[from..to].to_string())
+    };
+    let module = between(abort, "name: Identifier(\"", "\")")?;
+    let location = match between(abort, "function_name: Some(\"", "\")") {
+        Some(function) => format!("{}::{}", module, function),
+        None => module,
+    };
+    // the code follows the location struct
+    let rest = &abort[abort.rfind("}, ")? + 3..];
+    let code = rest[..rest.find(')')?].trim().parse().ok()?;
+    Some((location, code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ABORT: &str = "MoveAbort(MoveLocation { module: ModuleId { address: 7b5e4b5f0e7d1f6a9ef5c5ad3cbd93e4b7ac0a6f4e0c71bdbc3e32b24c38b2d1, name: Identifier(\"enter\") }, function: 12, instruction: 34, function_name: Some(\"force_liquidation\") }, 2) in command 0";
+    const ABORT_NO_FUNCTION: &str = "MoveAbort(MoveLocation { module: ModuleId { address: 7b5e4b5f0e7d1f6a9ef5c5ad3cbd93e4b7ac0a6f4e0c71bdbc3e32b24c38b2d1, name: Identifier(\"position\") }, function: 3, instruction: 10, function_name: None }, 607) in command 1";
+
+    #[test]
+    fn test_parse_move_abort() {
+        assert_eq!(
+            parse_move_abort(ABORT),
+            Some(("enter::force_liquidation".to_string(), 2))
+        );
+        assert_eq!(
+            parse_move_abort(ABORT_NO_FUNCTION),
+            Some(("position".to_string(), 607))
+        );
+        assert_eq!(parse_move_abort("InsufficientGas"), None);
+        assert_eq!(parse_move_abort("MoveAbort(broken"), None);
+    }
+
+    #[test]
+    fn test_classify_failure() {
+        assert_eq!(
+            classify_failure(ABORT),
+            ClientError::MoveAbort("enter::force_liquidation".to_string(), 2)
+        );
+        assert_eq!(
+            classify_failure("InsufficientGas"),
+            ClientError::InsufficientGas("InsufficientGas".to_string())
+        );
+        assert_eq!(
+            classify_failure("InsufficientCoinBalance in command 0"),
+            ClientError::TransactionExecutionFailure(
+                "InsufficientCoinBalance in command 0".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_retryable_errors() {
+        let conflict = "Transaction execution failed due to issues with transaction inputs, please review the errors and try again: Object (0x5d8b2f0fbd3ebe2ff9d30a8a3eb0d5d5b98be1c9d6bda1a3b5c8c2a8ee7c1c7b, SequenceNumber(5), o#7nFS4K8wCdfPSzBbP5XzMkUPHTFyAVtjL2LeTkZXR8Kz) is not available for consumption, its current version: SequenceNumber(6).";
+        assert!(is_version_conflict(conflict));
+        assert!(!is_transient(conflict));
+        assert!(is_transient(
+            "Networking or low-level protocol error: Connection reset by peer"
+        ));
+        assert!(is_transient("Request timeout"));
+        assert!(is_transient("server returned an error status code: 503"));
+        assert!(!is_version_conflict(ABORT));
+        assert!(!is_transient(ABORT));
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(500, 1), Duration::from_millis(500));
+        assert_eq!(retry_delay(500, 3), Duration::from_millis(2000));
+        assert_eq!(retry_delay(500, 64), RETRY_MAX_DELAY);
+        assert_eq!(retry_delay(500, u32::MAX), RETRY_MAX_DELAY);
+    }
+}
@@ -0,0 +1,464 @@
+// The gas coins of a signer address, each coin is leased to one transaction at a time,
+// so the transactions submitted in parallel never lock the same gas object.
+use crate::com::ClientError;
+use crate::sui::config::{Ctx, GasPoolConfig};
+use log::*;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use sui_json_rpc_types::{
+    SuiObjectDataOptions, SuiTransactionBlockEffects, SuiTransactionBlockEffectsAPI,
+};
+use sui_types::{
+    base_types::{ObjectID, ObjectRef, SuiAddress},
+    programmable_transaction_builder::ProgrammableTransactionBuilder as PTB,
+    transaction::{
+        Argument, Command, ObjectArg, TransactionData, TransactionDataAPI, TransactionKind,
+    },
+};
+use tokio::sync::Notify;
+use tokio::time::{self, Duration, Instant};
+
+#[derive(Debug, Clone)]
+struct GasCoin {
+    object_ref: ObjectRef,
+    balance: u64,
+    // the version is unknown after a transaction without effects,
+    // or the balance after a command took the coin, it is reloaded before the next lease
+    stale: bool,
+}
+
+impl GasCoin {
+    // Update the coin with the effects of its transaction.
+    fn settle(&mut self, object_ref: ObjectRef, net_gas_usage: i64, uses_gas_coin: bool) {
+        self.object_ref = object_ref;
+        if uses_gas_coin {
+            self.stale = true;
+        } else {
+            self.balance = (self.balance as i64 - net_gas_usage).max(0) as u64;
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Coins {
+    loaded: bool,
+    idle: HashMap<ObjectID, GasCoin>,
+    leased: HashSet<ObjectID>,
+}
+
+#[derive(Debug)]
+enum Pick {
+    Lease(GasCoin),
+    // the coins are not loaded, or the picked one is stale
+    Refresh,
+    // only a leased coin may hold the budget
+    Wait,
+    Empty,
+}
+
+impl Coins {
+    // Lease the richest idle coin holding at least the budget.
+    fn lease(&mut self, budget: u64) -> Pick {
+        let coin = self
+            .idle
+            .values()
+            .filter(|c| c.balance >= budget)
+            .max_by_key(|c| c.balance)
+            .cloned();
+        match coin {
+            Some(c) if c.stale => Pick::Refresh,
+            Some(c) => {
+                self.idle.remove(&c.object_ref.0);
+                self.leased.insert(c.object_ref.0);
+                Pick::Lease(c)
+            }
+            None if !self.loaded => Pick::Refresh,
+            None if !self.leased.is_empty() => Pick::Wait,
+            None => Pick::Empty,
+        }
+    }
+
+    fn put_back(&mut self, coin: GasCoin) {
+        self.leased.remove(&coin.object_ref.0);
+        self.idle.insert(coin.object_ref.0, coin);
+    }
+}
+
+pub struct GasPool {
+    ctx: Ctx,
+    owner: SuiAddress,
+    config: GasPoolConfig,
+    coins: Mutex<Coins>,
+    released: Notify,
+}
+
+pub type SharedGasPool = Arc<GasPool>;
+
+/// A gas coin leased to a transaction, it goes back to the pool on release or drop.
+pub struct GasLease {
+    pool: SharedGasPool,
+    coin: Option<GasCoin>,
+}
+
+impl GasLease {
+    pub fn object_ref(&self) -> ObjectRef {
+        self.coin().object_ref
+    }
+
+    pub fn object_id(&self) -> ObjectID {
+        self.coin().object_ref.0
+    }
+
+    /// The owner of the coin, who sends and signs the transaction.
+    pub fn owner(&self) -> SuiAddress {
+        self.pool.owner
+    }
+
+    /// Reload the latest version of the coin, after the transaction was rejected
+    /// for locking an old version of it.
+    pub async fn refresh(&mut self) -> anyhow::Result<()> {
+        let id = self.object_id();
+        let response = self
+            .pool
+            .ctx
+            .client
+            .read_api()
+            .get_object_with_options(id, SuiObjectDataOptions::new())
+            .await?;
+        let object = response
+            .data
+            .ok_or_else(|| ClientError::ObjectNotFound(id.to_string()))?;
+        if let Some(coin) = self.coin.as_mut() {
+            coin.object_ref = object.object_ref();
+            coin.stale = false;
+        }
+        Ok(())
+    }
+
+    fn coin(&self) -> &GasCoin {
+        self.coin.as_ref().expect("gas coin of lease is released")
+    }
+
+    /// Return the coin with its version and balance after the transaction.
+    pub fn release(mut self, data: &TransactionData, effects: Option<&SuiTransactionBlockEffects>) {
+        if let Some(mut coin) = self.coin.take() {
+            match effects {
+                Some(e) => coin.settle(
+                    e.gas_object().reference.to_object_ref(),
+                    e.gas_cost_summary().net_gas_usage(),
+                    uses_gas_coin(data),
+                ),
+                None => coin.stale = true,
+            }
+            self.pool.put_back(coin);
+        }
+    }
+}
+
+impl Drop for GasLease {
+    fn drop(&mut self) {
+        if let Some(mut coin) = self.coin.take() {
+            coin.stale = true;
+            self.pool.put_back(coin);
+        }
+    }
+}
+
+impl GasPool {
+    pub fn new(ctx: Ctx, config: GasPoolConfig, owner: SuiAddress) -> SharedGasPool {
+        Arc::new(Self {
+            ctx,
+            owner,
+            config,
+            coins: Mutex::new(Coins::default()),
+            released: Notify::new(),
+        })
+    }
+
+    /// Reload the idle coins from the chain, the leased ones are kept as they are.
+    pub async fn refresh(&self) -> anyhow::Result<()> {
+        let gas_objects = self.ctx.wallet.gas_objects(self.owner).await?;
+        let mut coins = self.coins.lock().unwrap();
+        let leased = coins.leased.clone();
+        coins.idle = gas_objects
+            .into_iter()
+            .filter(|(_, o)| !leased.contains(&o.object_id))
+            .map(|(balance, o)| {
+                let coin = GasCoin {
+                    object_ref: o.object_ref(),
+                    balance,
+                    stale: false,
+                };
+                (o.object_id, coin)
+            })
+            .collect();
+        coins.loaded = true;
+        debug!(
+            "gas pool of {} refreshed, idle: {}, leased: {}",
+            self.owner,
+            coins.idle.len(),
+            coins.leased.len()
+        );
+        Ok(())
+    }
+
+    /// Lease the richest idle coin holding at least `budget`,
+    /// waits for a leased coin to come back if there is none.
+    pub async fn acquire(self: &Arc<Self>, budget: u64) -> anyhow::Result<GasLease> {
+        let deadline = Instant::now() + Duration::from_millis(self.config.acquire_timeout_ms);
+        let mut refreshed = false;
+        loop {
+            let notified = self.released.notified();
+            let pick = self.coins.lock().unwrap().lease(budget);
+            match pick {
+                Pick::Lease(c) => {
+                    return Ok(GasLease {
+                        pool: self.clone(),
+                        coin: Some(c),
+                    })
+                }
+                Pick::Refresh => {
+                    self.refresh().await?;
+                    refreshed = true;
+                }
+                // the owner may have been topped up since the coins were loaded
+                Pick::Empty if !refreshed => {
+                    self.refresh().await?;
+                    refreshed = true;
+                }
+                Pick::Empty => {
+                    return Err(ClientError::InsufficientGasBalance(format!(
+                        "no gas coin holds the budget {}",
+                        budget
+                    ))
+                    .into());
+                }
+                Pick::Wait => {
+                    if time::timeout_at(deadline, notified).await.is_err() {
+                        return Err(ClientError::NoGasCoin.into());
+                    }
+                }
+            }
+        }
+    }
+
+    fn put_back(&self, coin: GasCoin) {
+        self.coins.lock().unwrap().put_back(coin);
+        self.released.notify_waiters();
+    }
+
+    /// Build the transaction merging the dust coins into the richest coin
+    /// and splitting it until the pool has `size` coins above the min balance.
+    /// Returns None if the pool is balanced already, send it when no transaction is in flight.
+    pub async fn rebalance_transaction(
+        &self,
+        gas_budget: u64,
+    ) -> anyhow::Result<Option<TransactionData>> {
+        self.refresh().await?;
+        let owner = self.owner;
+        let mut idle: Vec<GasCoin> = {
+            let coins = self.coins.lock().unwrap();
+            coins.idle.values().cloned().collect()
+        };
+        idle.sort_by_key(|c| std::cmp::Reverse(c.balance));
+        let gas = match idle.first() {
+            Some(c) => c.clone(),
+            None => return Err(ClientError::NoGasCoin.into()),
+        };
+        let plan =
+            match plan_rebalance(&idle, self.config.size, self.config.min_balance, gas_budget) {
+                Some(p) => p,
+                None => return Ok(None),
+            };
+        let mut tx = PTB::new();
+        if !plan.dust.is_empty() {
+            let mut args = Vec::with_capacity(plan.dust.len());
+            for object_ref in plan.dust.iter() {
+                args.push(tx.obj(ObjectArg::ImmOrOwnedObject(*object_ref))?);
+            }
+            tx.command(Command::MergeCoins(Argument::GasCoin, args));
+        }
+        if plan.splits > 0 {
+            tx.pay_sui(vec![owner; plan.splits], vec![plan.amount; plan.splits])?;
+        }
+        info!(
+            "rebalance gas pool of {}: merge {} dust coins, split {} coins",
+            owner,
+            plan.dust.len(),
+            plan.splits
+        );
+        let gas_price = self.ctx.client.read_api().get_reference_gas_price().await?;
+        Ok(Some(TransactionData::new_programmable(
+            owner,
+            vec![gas.object_ref],
+            tx.finish(),
+            gas_budget,
+            gas_price,
+        )))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Rebalance {
+    // the coins merged into the richest one
+    dust: Vec<ObjectRef>,
+    splits: usize,
+    // the balance of each split coin
+    amount: u64,
+}
+
+// Plan the merge of the dust coins and the splits of the richest coin, the first of `idle`
+// sorted by balance, until there are `size` coins above the min balance.
+fn plan_rebalance(
+    idle: &[GasCoin],
+    size: usize,
+    min_balance: u64,
+    gas_budget: u64,
+) -> Option<Rebalance> {
+    let gas = idle.first()?;
+    let dust: Vec<&GasCoin> = idle[1..]
+        .iter()
+        .filter(|c| c.balance < min_balance)
+        .collect();
+    let usable = idle.len() - dust.len();
+    let total = gas.balance + dust.iter().map(|c| c.balance).sum::<u64>();
+    // the richest coin keeps a share too
+    let mut splits = size.saturating_sub(usable);
+    let spendable = total.saturating_sub(gas_budget);
+    let max_splits = (spendable / min_balance.max(1)).saturating_sub(1);
+    if splits as u64 > max_splits {
+        warn!(
+            "gas balance {} is too low to split {} coins of {} at least",
+            total, splits, min_balance
+        );
+        splits = max_splits as usize;
+    }
+    if splits == 0 && dust.is_empty() {
+        return None;
+    }
+    Some(Rebalance {
+        dust: dust.iter().map(|c| c.object_ref).collect(),
+        splits,
+        amount: spendable / (splits as u64 + 1),
+    })
+}
+
+// Whether a command of the transaction takes the gas coin, which then may spend more than the gas.
+fn uses_gas_coin(data: &TransactionData) -> bool {
+    let pt = match data.kind() {
+        TransactionKind::ProgrammableTransaction(pt) => pt,
+        _ => return false,
+    };
+    pt.commands.iter().any(|c| {
+        let args: Vec<&Argument> = match c {
+            Command::MoveCall(call) => call.arguments.iter().collect(),
+            Command::TransferObjects(objects, recipient) => {
+                objects.iter().chain(std::iter::once(recipient)).collect()
+            }
+            Command::SplitCoins(coin, amounts) => {
+                std::iter::once(coin).chain(amounts.iter()).collect()
+            }
+            Command::MergeCoins(coin, coins) => std::iter::once(coin).chain(coins.iter()).collect(),
+            Command::MakeMoveVec(_, args) => args.iter().collect(),
+            Command::Upgrade(.., ticket) => vec![ticket],
+            Command::Publish(..) => vec![],
+        };
+        args.into_iter().any(|a| matches!(a, Argument::GasCoin))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sui_types::base_types::random_object_ref;
+
+    fn coin(balance: u64) -> GasCoin {
+        GasCoin {
+            object_ref: random_object_ref(),
+            balance,
+            stale: false,
+        }
+    }
+
+    fn loaded(coins: Vec<GasCoin>) -> Coins {
+        Coins {
+            loaded: true,
+            idle: coins.into_iter().map(|c| (c.object_ref.0, c)).collect(),
+            leased: HashSet::new(),
+        }
+    }
+
+    fn leased(pick: Pick) -> GasCoin {
+        match pick {
+            Pick::Lease(c) => c,
+            p => panic!("expect a lease, got {:?}", p),
+        }
+    }
+
+    #[test]
+    fn test_lease_is_exclusive() {
+        let mut coins = loaded(vec![coin(3000), coin(2000), coin(500)]);
+        let first = leased(coins.lease(1000));
+        assert_eq!(first.balance, 3000);
+        let second = leased(coins.lease(1000));
+        assert_eq!(second.balance, 2000);
+        // the last idle coin is below the budget, a leased one may come back
+        assert!(matches!(coins.lease(1000), Pick::Wait));
+        coins.put_back(first);
+        assert_eq!(leased(coins.lease(1000)).balance, 3000);
+        assert!(coins.leased.contains(&second.object_ref.0));
+    }
+
+    #[test]
+    fn test_lease_refresh_and_empty() {
+        assert!(matches!(Coins::default().lease(1000), Pick::Refresh));
+        let mut coins = loaded(vec![coin(500)]);
+        assert!(matches!(coins.lease(1000), Pick::Empty));
+        let mut stale = coin(3000);
+        stale.stale = true;
+        coins.put_back(stale);
+        assert!(matches!(coins.lease(1000), Pick::Refresh));
+    }
+
+    #[test]
+    fn test_settle() {
+        let mut c = coin(3000);
+        let object_ref = random_object_ref();
+        c.settle(object_ref, 1200, false);
+        assert_eq!(
+            (c.object_ref, c.balance, c.stale),
+            (object_ref, 1800, false)
+        );
+        // a storage rebate larger than the cost adds to the balance
+        c.settle(object_ref, -200, false);
+        assert_eq!(c.balance, 2000);
+        c.settle(object_ref, 5000, false);
+        assert_eq!(c.balance, 0);
+        // the fee split from the gas coin is unknown
+        let mut c = coin(3000);
+        c.settle(object_ref, 1200, true);
+        assert!(c.stale);
+    }
+
+    #[test]
+    fn test_plan_rebalance() {
+        let idle = vec![coin(10_000), coin(150), coin(50), coin(30)];
+        let plan = plan_rebalance(&idle, 4, 100, 1000).unwrap();
+        assert_eq!(
+            plan,
+            Rebalance {
+                dust: vec![idle[2].object_ref, idle[3].object_ref],
+                splits: 2,
+                // (10_000 + 50 + 30 - 1000) / 3
+                amount: 3026,
+            }
+        );
+        // the splits are limited to keep every coin above the min balance
+        let idle = vec![coin(500)];
+        let plan = plan_rebalance(&idle, 4, 100, 200).unwrap();
+        assert_eq!((plan.splits, plan.amount), (2, 100));
+        // balanced already
+        assert_eq!(plan_rebalance(&[coin(1000), coin(1000)], 2, 100, 200), None);
+        assert_eq!(plan_rebalance(&[], 2, 100, 200), None);
+    }
+}
@@ -0,0 +1,329 @@
+// Walk the sui checkpoints for the changes of the scale objects, instead of the startup sync
+// and the event subscription. The objects changed in a checkpoint are pulled and sent to the
+// watch task, then the checkpoint is saved in postgres, a restart continues after it.
+// Without progress the objects are synced and the indexer starts from the latest checkpoint.
+// The objects deleted on chain are removed with their last known states.
+use crate::bot::machine::SharedStateMap;
+use crate::bot::state::{Address, Event, Message, MessageSender};
+use crate::bot::storage::postgres::PG;
+use crate::com::{Task, TaskStopRx};
+use crate::sui::config::Ctx;
+use crate::sui::object::{self, ObjectType};
+use crate::sui::subscribe::{self, EventResult};
+use log::*;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use sui_sdk::rpc_types::{
+    CheckpointId, ObjectChange, SuiTransactionBlockResponse, SuiTransactionBlockResponseOptions,
+};
+use sui_sdk::types::base_types::ObjectID;
+use tokio::time::{self, Duration};
+
+// the name of the progress in postgres
+const INDEXER_NAME: &str = "enter";
+// the max transactions of a multi get request
+const TRANSACTION_PAGE_SIZE: usize = 50;
+
+pub struct CheckpointIndexer {
+    task: Task,
+}
+
+impl CheckpointIndexer {
+    pub fn new(ctx: Ctx, pg: Arc<PG>, ssm: SharedStateMap, watch_tx: MessageSender) -> Self {
+        let (close_tx, close_rx) = Task::new_shutdown_channel();
+        Self {
+            task: Task::new(
+                "checkpoint indexer",
+                close_tx,
+                tokio::spawn(run(ctx, pg, ssm, watch_tx, close_rx)),
+            ),
+        }
+    }
+
+    pub fn into_tasks(self) -> Vec<Task> {
+        vec![self.task]
+    }
+}
+
+async fn run(
+    ctx: Ctx,
+    pg: Arc<PG>,
+    ssm: SharedStateMap,
+    watch_tx: MessageSender,
+    mut close_rx: TaskStopRx,
+) -> anyhow::Result<()> {
+    let poll_interval = Duration::from_millis(ctx.config.indexer_config.poll_interval_ms);
+    let mut next = loop {
+        match get_start_checkpoint(&ctx, &pg, &watch_tx).await {
+            Ok(c) => break c,
+            Err(e) => {
+                error!(
+                    "get start checkpoint error: {}, retry in {:?}",
+                    e, poll_interval
+                );
+                tokio::select! {
+                    _ = &mut close_rx => return Ok(()),
+                    _ = time::sleep(poll_interval) => {}
+                }
+            }
+        }
+    };
+    info!("checkpoint indexer start from checkpoint {}", next);
+    let mut tip = 0;
+    // wait for the poll interval before the next try
+    let mut waiting = false;
+    loop {
+        tokio::select! {
+            r = &mut close_rx => {
+                debug!("checkpoint indexer got close signal: {:?}", r);
+                break;
+            }
+            _ = time::sleep(poll_interval), if waiting => waiting = false,
+            rs = index_next(&ctx, &pg, &ssm, &watch_tx, next, &mut tip), if !waiting => {
+                match rs {
+                    Ok(true) => next += 1,
+                    Ok(false) => waiting = true,
+                    Err(e) => {
+                        error!(
+                            "index checkpoint {} error: {}, retry in {:?}",
+                            next, e, poll_interval
+                        );
+                        waiting = true;
+                    }
+                }
+            }
+        }
+    }
+    info!("checkpoint indexer stopped before checkpoint {}", next);
+    Ok(())
+}
+
+// The checkpoint after the saved progress. Without progress, the configured checkpoint,
+// or the latest one after the objects are synced like the event subscription does.
+async fn get_start_checkpoint(ctx: &Ctx, pg: &PG, watch_tx: &MessageSender) -> anyhow::Result<u64> {
+    if let Some(c) = pg.get_indexer_checkpoint(INDEXER_NAME).await? {
+        return Ok(c + 1);
+    }
+    if let Some(c) = ctx.config.indexer_config.start_checkpoint {
+        return Ok(c);
+    }
+    // the changes after the latest checkpoint are indexed, the objects may be pulled twice
+    let latest = ctx
+        .client
+        .read_api()
+        .get_latest_checkpoint_sequence_number()
+        .await?;
+    subscribe::sync_all_objects(ctx.clone(), watch_tx.clone()).await?;
+    Ok(latest)
+}
+
+// Index the checkpoint, false if the chain has not reached it yet.
+// The checkpoint is not saved if any object failed to pull, it is indexed again.
+async fn index_next(
+    ctx: &Ctx,
+    pg: &PG,
+    ssm: &SharedStateMap,
+    watch_tx: &MessageSender,
+    seq: u64,
+    tip: &mut u64,
+) -> anyhow::Result<bool> {
+    if seq > *tip {
+        *tip = ctx
+            .client
+            .read_api()
+            .get_latest_checkpoint_sequence_number()
+            .await?;
+        if seq > *tip {
+            return Ok(false);
+        }
+    }
+    let checkpoint = ctx
+        .client
+        .read_api()
+        .get_checkpoint(CheckpointId::SequenceNumber(seq))
+        .await?;
+    let mut txs = Vec::with_capacity(checkpoint.transactions.len());
+    for digests in checkpoint.transactions.chunks(TRANSACTION_PAGE_SIZE) {
+        let opt = SuiTransactionBlockResponseOptions::new()
+            .with_events()
+            .with_object_changes();
+        let page = ctx
+            .client
+            .read_api()
+            .multi_get_transactions_with_options(digests.to_vec(), opt)
+            .await?;
+        txs.extend(page);
+    }
+    let changes = get_changes(ctx.config.scale_package_id, &txs);
+    if !changes.is_empty() {
+        debug!("checkpoint {} changed {} objects", seq, changes.len());
+    }
+    let deleted_ids = get_deleted(&txs);
+    let (mut deleted, objects): (Vec<_>, Vec<_>) = changes
+        .into_iter()
+        .map(|c| (c.object_id, c.event))
+        .partition(|(id, _)| deleted_ids.contains(id));
+    let not_found =
+        object::pull_objects_with_events_and_send(ctx.clone(), objects, watch_tx).await?;
+    deleted.extend(not_found);
+    for (id, _) in deleted {
+        match ssm.get_state(&Address::new(id.to_vec())) {
+            Some(state) => {
+                let msg = Message {
+                    state,
+                    event: Event::Deleted,
+                };
+                if let Err(e) = watch_tx.send(msg) {
+                    error!("watch_tx send error: {:?}", e);
+                }
+            }
+            None => debug!("deleted object {} is not known", id),
+        }
+    }
+    pg.save_indexer_checkpoint(INDEXER_NAME, seq).await?;
+    Ok(true)
+}
+
+/// The list, market, account and position objects of the package changed by the transactions
+/// of a checkpoint, in the order they were first changed. The change is the one of the enter
+/// event of the object, or of the object change without event, merged by `merge_event`.
+pub fn get_changes(package: ObjectID, txs: &[SuiTransactionBlockResponse]) -> Vec<EventResult> {
+    let mut changes: Vec<EventResult> = vec![];
+    // the index of the object in changes
+    let mut index: HashMap<ObjectID, usize> = HashMap::new();
+    for tx in txs {
+        let mut tx_changes: Vec<EventResult> = vec![];
+        for event in tx.events.iter().flat_map(|e| e.data.iter()) {
+            if event.package_id != package || event.transaction_module.as_str() != "enter" {
+                continue;
+            }
+            if let Some(rs) = subscribe::get_change_object(event.clone()) {
+                tx_changes.push(rs);
+            }
+        }
+        for change in tx.object_changes.iter().flatten() {
+            let (object_type, object_id, event) = match change {
+                ObjectChange::Created {
+                    object_type,
+                    object_id,
+                    ..
+                } => (object_type, *object_id, Event::Created),
+                ObjectChange::Mutated {
+                    object_type,
+                    object_id,
+                    ..
+                } => (object_type, *object_id, Event::Updated),
+                ObjectChange::Deleted {
+                    object_type,
+                    object_id,
+                    ..
+                }
+                | ObjectChange::Wrapped {
+                    object_type,
+                    object_id,
+                    ..
+                } => (object_type, *object_id, Event::Deleted),
+                _ => continue,
+            };
+            if ObjectID::from(object_type.address) != package
+                || tx_changes.iter().any(|c| c.object_id == object_id)
+            {
+                continue;
+            }
+            tx_changes.push(EventResult {
+                object_type: object_type.name.as_str().into(),
+                object_id,
+                event,
+            });
+        }
+        for rs in tx_changes {
+            match rs.object_type {
+                ObjectType::List
+                | ObjectType::Market
+                | ObjectType::Account
+                | ObjectType::Position => {}
+                _ => continue,
+            }
+            match index.get(&rs.object_id) {
+                Some(i) => object::merge_event(&mut changes[*i].event, rs.event),
+                None => {
+                    index.insert(rs.object_id, changes.len());
+                    changes.push(rs);
+                }
+            }
+        }
+    }
+    changes
+}
+
+/// The objects deleted or wrapped by the transactions, which can not be pulled.
+pub fn get_deleted(txs: &[SuiTransactionBlockResponse]) -> HashSet<ObjectID> {
+    let mut deleted = HashSet::new();
+    for change in txs.iter().flat_map(|tx| tx.object_changes.iter().flatten()) {
+        match change {
+            ObjectChange::Deleted { object_id, .. } | ObjectChange::Wrapped { object_id, .. } => {
+                deleted.insert(*object_id);
+            }
+            // a wrapped object may be unwrapped later in the checkpoint
+            ObjectChange::Created { object_id, .. } | ObjectChange::Mutated { object_id, .. } => {
+                deleted.remove(object_id);
+            }
+            _ => {}
+        }
+    }
+    deleted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    const PACKAGE: &str = "0x4e719b0ab5baee73641096dcc5dfb72f0be04bbc8ba6bc417e75ba3b809c4517";
+
+    fn id(s: &str) -> ObjectID {
+        ObjectID::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_get_changes_of_checkpoint() {
+        // a position opened and deleted in the checkpoint, and a position of another package
+        let txs: Vec<SuiTransactionBlockResponse> =
+            serde_json::from_str(include_str!("fixtures/checkpoint_transactions.json")).unwrap();
+        let changes: Vec<(ObjectID, ObjectType, Event)> = get_changes(id(PACKAGE), &txs)
+            .into_iter()
+            .map(|c| (c.object_id, c.object_type, c.event))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                (
+                    id("0x5a452459503be70d2ed2fca46dd91e05e820a957f175686a77d03fff80f664fe"),
+                    ObjectType::Position,
+                    Event::Deleted
+                ),
+                (
+                    id("0x9af211329b2fc82e5efe906062c730082819b23fe8394bc435e0b1bf0458eb54"),
+                    ObjectType::Account,
+                    Event::Updated
+                ),
+                (
+                    id("0xe4d693d24e646cb7878426225d90d2607fd40e2e7c82f4cf8246b2cc3e0decb8"),
+                    ObjectType::Market,
+                    Event::Updated
+                ),
+                (
+                    id("0xa330395cc0a53ad1207736546afff4735940937564bbf75ce1edad40780d9139"),
+                    ObjectType::List,
+                    Event::Updated
+                ),
+            ]
+        );
+        assert_eq!(
+            get_deleted(&txs),
+            HashSet::from([id(
+                "0x5a452459503be70d2ed2fca46dd91e05e820a957f175686a77d03fff80f664fe"
+            )])
+        );
+    }
+}
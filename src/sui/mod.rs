@@ -1,4 +1,9 @@
 pub mod config;
+pub mod executor;
+pub mod gas;
+pub mod indexer;
 pub mod object;
+pub mod signature;
+pub mod signer;
 pub mod subscribe;
 pub mod tool;
@@ -4,6 +4,7 @@ use crate::bot::state::{
 };
 use crate::com::ClientError;
 use crate::sui::config::Ctx;
+use futures::stream::{self, StreamExt};
 use log::*;
 use move_core_types::language_storage::StructTag;
 use serde::{Deserialize, Serialize};
@@ -11,6 +12,7 @@ use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fmt;
+use std::future::Future;
 use std::str::FromStr;
 use sui_sdk::rpc_types::{
     SuiMoveStruct, SuiObjectDataFilter, SuiObjectDataOptions, SuiObjectResponse,
@@ -20,6 +22,7 @@ use sui_sdk::types::{
     balance::{Balance, Supply},
     base_types::{ObjectID, ObjectRef, SuiAddress},
     dynamic_field::DynamicFieldInfo,
+    error::SuiObjectResponseError,
     id::{ID, UID},
     object::{Object, Owner},
     transaction::ObjectArg,
@@ -29,6 +32,7 @@ use tokio::sync::mpsc::UnboundedSender;
 extern crate serde;
 
 const OBJECT_MAX_REQUEST_LIMIT: usize = 100;
+const OBJECT_MAX_CONCURRENT_REQUESTS: usize = 4;
 #[derive(Debug, Clone, PartialEq, Copy)]
 pub enum ObjectType {
     List,
@@ -100,55 +104,186 @@ pub async fn get_own_objects_with_type(
     Ok(objects)
 }
 
+/// The objects to pull and the event to send each with. An object pushed again keeps
+/// its place, its event is merged by `merge_event`.
+#[derive(Debug, Default)]
+pub struct ObjectBatch {
+    objects: Vec<(ObjectID, Event)>,
+    // the index of the object in objects
+    index: HashMap<ObjectID, usize>,
+}
+
+impl ObjectBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, id: ObjectID, event: Event) {
+        match self.index.get(&id) {
+            Some(i) => merge_event(&mut self.objects[*i].1, event),
+            None => {
+                self.index.insert(id, self.objects.len());
+                self.objects.push((id, event));
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    pub fn take(&mut self) -> Vec<(ObjectID, Event)> {
+        self.index.clear();
+        std::mem::take(&mut self.objects)
+    }
+}
+
+/// Merge a later event of an object, the latest state is pulled once for both:
+/// a created object stays created unless it is deleted too.
+pub fn merge_event(current: &mut Event, event: Event) {
+    if event == Event::Deleted || *current != Event::Created {
+        *current = event;
+    }
+}
+
 pub async fn pull_objects_and_send(
     ctx: Ctx,
-    mut ids: Vec<ObjectID>,
+    ids: Vec<ObjectID>,
     event: Event,
     watch_tx: UnboundedSender<Message>,
 ) -> anyhow::Result<()> {
-    while ids.len() > OBJECT_MAX_REQUEST_LIMIT {
-        let ids_new = ids.split_off(OBJECT_MAX_REQUEST_LIMIT);
-        pull_objects_with_limit_and_send(ctx.clone(), ids_new, event.clone(), watch_tx.clone())
-            .await?;
-    }
-    pull_objects_with_limit_and_send(ctx, ids, event.clone(), watch_tx).await?;
+    let objects = ids.into_iter().map(|id| (id, event.clone())).collect();
+    pull_objects_with_events_and_send(ctx, objects, &watch_tx).await?;
     Ok(())
 }
 
-pub async fn pull_objects_with_limit_and_send(
+/// Pull the objects and send them with their events in the order given.
+/// Returns the objects deleted or not found, which are not sent,
+/// and an error if any other object failed to pull.
+pub async fn pull_objects_with_events_and_send(
+    ctx: Ctx,
+    objects: Vec<(ObjectID, Event)>,
+    watch_tx: &UnboundedSender<Message>,
+) -> anyhow::Result<Vec<(ObjectID, Event)>> {
+    let total = objects.len();
+    let (ids, events): (Vec<ObjectID>, Vec<Event>) = objects.into_iter().unzip();
+    let mut not_found = vec![];
+    let mut failed = 0;
+    for ((id, rs), event) in multi_get_objects(ctx, ids).await.into_iter().zip(events) {
+        match rs {
+            Ok(mut msg) => {
+                msg.event = event;
+                if let Err(e) = watch_tx.send(msg) {
+                    error!("send message error: {:?}", e);
+                }
+            }
+            Err(e) if is_not_found(&e) => {
+                warn!("object {} is not found: {}", id, e);
+                not_found.push((id, event));
+            }
+            Err(e) => {
+                failed += 1;
+                error!("pull object {} error: {:?}", id, e);
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(ClientError::GetObjectError(format!(
+            "{} of {} objects failed to pull",
+            failed, total
+        ))
+        .into());
+    }
+    Ok(not_found)
+}
+
+fn is_not_found(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref::<ClientError>(),
+        Some(ClientError::ObjectNotFound(_))
+    )
+}
+
+/// Pull the objects, the results are in the order of the ids.
+pub async fn multi_get_objects(
     ctx: Ctx,
     ids: Vec<ObjectID>,
-    event: Event,
-    watch_tx: UnboundedSender<Message>,
-) -> anyhow::Result<()> {
-    if ids.len() > OBJECT_MAX_REQUEST_LIMIT || ids.len() == 0 {
-        return Ok(());
+) -> Vec<(ObjectID, anyhow::Result<Message>)> {
+    let responses = get_in_chunks(ids, |chunk| {
+        let ctx = ctx.clone();
+        async move {
+            let rs = ctx
+                .client
+                .read_api()
+                .multi_get_object_with_options(
+                    chunk,
+                    SuiObjectDataOptions {
+                        show_type: false,
+                        show_owner: false,
+                        show_previous_transaction: false,
+                        show_display: false,
+                        show_content: false,
+                        show_bcs: true,
+                        show_storage_rebate: false,
+                    },
+                )
+                .await?;
+            debug!("got objects: {:?}", rs);
+            Ok(rs)
+        }
+    })
+    .await;
+    let mut objects = Vec::with_capacity(responses.len());
+    for (id, rs) in responses {
+        let rs = match rs {
+            Ok(r) => prase_object_response(r).await,
+            Err(e) => Err(e),
+        };
+        objects.push((id, rs));
     }
-    let rs = ctx
-        .client
-        .read_api()
-        .multi_get_object_with_options(
-            ids,
-            SuiObjectDataOptions {
-                show_type: false,
-                show_owner: false,
-                show_previous_transaction: false,
-                show_display: false,
-                show_content: false,
-                show_bcs: true,
-                show_storage_rebate: false,
-            },
-        )
-        .await?;
-    debug!("got objects: {:?}", rs);
-    for r in rs {
-        let mut ev = prase_object_response(r).await?;
-        ev.event = event.clone();
-        if let Err(e) = watch_tx.send(ev) {
-            error!("send message error: {:?}", e);
+    objects
+}
+
+// Get the ids in requests of at most OBJECT_MAX_REQUEST_LIMIT ids, with at most
+// OBJECT_MAX_CONCURRENT_REQUESTS requests in flight. The results are in the order
+// of the ids, a failed request fails each of its ids.
+async fn get_in_chunks<T, F, Fut>(ids: Vec<ObjectID>, get: F) -> Vec<(ObjectID, anyhow::Result<T>)>
+where
+    F: Fn(Vec<ObjectID>) -> Fut,
+    Fut: Future<Output = anyhow::Result<Vec<T>>>,
+{
+    let chunks: Vec<Vec<ObjectID>> = ids
+        .chunks(OBJECT_MAX_REQUEST_LIMIT)
+        .map(|c| c.to_vec())
+        .collect();
+    // buffered keeps the order of the requests
+    let responses: Vec<_> = stream::iter(chunks)
+        .map(|chunk| {
+            let rs = get(chunk.clone());
+            async move { (chunk, rs.await) }
+        })
+        .buffered(OBJECT_MAX_CONCURRENT_REQUESTS)
+        .collect()
+        .await;
+    let mut objects = Vec::with_capacity(ids.len());
+    for (chunk, rs) in responses {
+        let e = match rs {
+            Ok(rs) if rs.len() == chunk.len() => {
+                objects.extend(chunk.into_iter().zip(rs.into_iter().map(Ok)));
+                continue;
+            }
+            Ok(rs) => format!("got {} objects of {} ids", rs.len(), chunk.len()),
+            Err(e) => e.to_string(),
+        };
+        for id in chunk {
+            objects.push((id, Err(ClientError::GetObjectError(e.clone()).into())));
         }
     }
-    Ok(())
+    objects
 }
 
 pub async fn get_all_dynamic_field_object(
@@ -266,7 +401,13 @@ pub async fn get_object_args(ctx: Ctx, ids: Vec<ObjectID>) -> anyhow::Result<Obj
 pub async fn prase_object_response(rs: SuiObjectResponse) -> anyhow::Result<Message> {
     if let Some(e) = rs.error {
         error!("get object error: {:?}", e);
-        return Err(ClientError::GetObjectError(e.to_string()).into());
+        return Err(match &e {
+            SuiObjectResponseError::NotExists { .. } | SuiObjectResponseError::Deleted { .. } => {
+                ClientError::ObjectNotFound(e.to_string())
+            }
+            _ => ClientError::GetObjectError(e.to_string()),
+        }
+        .into());
     }
     debug!("get object: {:?}", rs);
     if let Some(data) = rs.data {
@@ -648,6 +789,7 @@ impl From<SuiPosition> for Position {
             close_spread: p.info.close_spread,
             close_real_price: p.info.close_real_price,
             profit: p.info.profit.into(),
+            auto_open_price: p.info.auto_open_price,
             stop_surplus_price: p.info.stop_surplus_price,
             stop_loss_price: p.info.stop_loss_price,
             create_time: p.info.create_time,
@@ -662,3 +804,74 @@ impl From<SuiPosition> for Position {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::{self, Duration};
+
+    #[test]
+    fn test_merge_event() {
+        let merge = |mut current: Event, event: Event| {
+            merge_event(&mut current, event);
+            current
+        };
+        assert_eq!(merge(Event::Created, Event::Updated), Event::Created);
+        assert_eq!(merge(Event::Created, Event::Deleted), Event::Deleted);
+        assert_eq!(merge(Event::Updated, Event::Deleted), Event::Deleted);
+        assert_eq!(merge(Event::None, Event::Updated), Event::Updated);
+        assert_eq!(merge(Event::Updated, Event::None), Event::None);
+    }
+
+    #[test]
+    fn test_object_batch() {
+        let (a, b, c) = (ObjectID::random(), ObjectID::random(), ObjectID::random());
+        let mut batch = ObjectBatch::new();
+        batch.push(a, Event::Created);
+        batch.push(b, Event::Updated);
+        batch.push(a, Event::Updated);
+        batch.push(c, Event::None);
+        batch.push(b, Event::Deleted);
+        assert_eq!(batch.len(), 3);
+        assert_eq!(
+            batch.take(),
+            vec![(a, Event::Created), (b, Event::Deleted), (c, Event::None)]
+        );
+        assert!(batch.is_empty());
+        // the index is cleared with the objects
+        batch.push(b, Event::Updated);
+        assert_eq!(batch.take(), vec![(b, Event::Updated)]);
+    }
+
+    #[tokio::test]
+    async fn test_get_in_chunks() {
+        let ids: Vec<ObjectID> = (0..OBJECT_MAX_REQUEST_LIMIT * 4 + 7)
+            .map(|_| ObjectID::random())
+            .collect();
+        let first = ids[0];
+        let failed = ids[OBJECT_MAX_REQUEST_LIMIT];
+        let short = ids[OBJECT_MAX_REQUEST_LIMIT * 2];
+        let rs = get_in_chunks(ids.clone(), move |chunk| async move {
+            // the first request is answered last
+            if chunk[0] == first {
+                time::sleep(Duration::from_millis(50)).await;
+            }
+            if chunk[0] == failed {
+                return Err(anyhow::anyhow!("rpc error"));
+            }
+            if chunk[0] == short {
+                return Ok(chunk[1..].to_vec());
+            }
+            Ok(chunk)
+        })
+        .await;
+        assert_eq!(rs.len(), ids.len());
+        for (i, (id, r)) in rs.into_iter().enumerate() {
+            assert_eq!(id, ids[i]);
+            match i / OBJECT_MAX_REQUEST_LIMIT {
+                1 | 2 => assert!(r.is_err()),
+                _ => assert_eq!(r.unwrap(), id),
+            }
+        }
+    }
+}
@@ -0,0 +1,28 @@
+use crate::bot::state::Address;
+use crate::com::ClientError;
+use shared_crypto::intent::{Intent, IntentMessage, PersonalMessage};
+use sui_types::{
+    base_types::SuiAddress,
+    crypto::{EncodeDecodeBase64, PublicKey, Signature, SuiSignature},
+};
+
+/// Verify a base64 encoded Sui signature (flag || signature || public key) of a personal message,
+/// as signed by the wallets, and return the address of the signer.
+pub fn verify_personal_message(message: &[u8], signature: &str) -> anyhow::Result<Address> {
+    let signature = Signature::decode_base64(signature)
+        .map_err(|e| ClientError::InvalidSignature(e.to_string()))?;
+    let scheme = signature.scheme();
+    let pk = PublicKey::try_from_bytes(scheme, signature.public_key_bytes())
+        .map_err(|e| ClientError::InvalidSignature(e.to_string()))?;
+    let signer = SuiAddress::from(&pk);
+    let msg = IntentMessage::new(
+        Intent::personal_message(),
+        PersonalMessage {
+            message: message.to_vec(),
+        },
+    );
+    signature
+        .verify_secure(&msg, signer, scheme)
+        .map_err(|e| ClientError::InvalidSignature(e.to_string()))?;
+    Ok(Address::new(signer.to_vec()))
+}
@@ -0,0 +1,522 @@
+// Sign the transactions with the local keystore, or with a remote signer holding the keeper keys.
+// The remote signer only signs the move calls allowed by its policy.
+use crate::com::ClientError;
+use crate::sui::config::{Config, Ctx, KeystoreFile};
+use async_trait::async_trait;
+use axum::{
+    extract::Extension,
+    http::{header, HeaderMap, StatusCode},
+    response::IntoResponse,
+    routing::post,
+    Json, Router,
+};
+use base64::{engine::general_purpose, Engine as _};
+use fastcrypto::traits::ToFromBytes;
+use log::*;
+use reqwest::Client as HttpClient;
+use serde::{Deserialize, Serialize};
+use shared_crypto::intent::Intent;
+use std::collections::HashSet;
+use std::net::TcpListener;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::{fs, path::PathBuf};
+use sui_keys::keystore::{AccountKeystore, FileBasedKeystore, Keystore};
+use sui_types::{
+    base_types::{ObjectID, SuiAddress},
+    crypto::Signature,
+    transaction::{
+        Argument, CallArg, Command, TransactionData, TransactionDataAPI, TransactionKind,
+    },
+    SUI_FRAMEWORK_PACKAGE_ID,
+};
+
+// The functions of the sui framework signed, the other framework calls are refused.
+const FRAMEWORK_FUNCTIONS: [&str; 1] = ["coin::split"];
+
+#[async_trait]
+pub trait Signer: Send + Sync {
+    async fn sign(
+        &self,
+        address: SuiAddress,
+        data: &TransactionData,
+        intent: Intent,
+    ) -> anyhow::Result<Signature>;
+}
+
+pub type SharedSigner = Arc<dyn Signer>;
+
+/// Signs with the keystore of the sui cli config.
+pub struct LocalSigner {
+    ctx: Ctx,
+}
+
+impl LocalSigner {
+    pub fn new(ctx: Ctx) -> Self {
+        Self { ctx }
+    }
+}
+
+#[async_trait]
+impl Signer for LocalSigner {
+    async fn sign(
+        &self,
+        address: SuiAddress,
+        data: &TransactionData,
+        intent: Intent,
+    ) -> anyhow::Result<Signature> {
+        Ok(self
+            .ctx
+            .wallet
+            .config
+            .keystore
+            .sign_secure(&address, data, intent)?)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SignRequest {
+    pub address: SuiAddress,
+    /// The base64 bcs bytes of the TransactionData
+    pub tx_bytes: String,
+    pub intent: Intent,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SignResponse {
+    /// The base64 bytes of the signature
+    pub signature: String,
+}
+
+/// Sends the transactions to the remote signer of the url, with the bearer token if any.
+pub struct RemoteSigner {
+    http_client: HttpClient,
+    url: String,
+    auth_token: Option<String>,
+}
+
+impl RemoteSigner {
+    pub fn new(http_client: HttpClient, url: String, auth_token: Option<String>) -> Self {
+        Self {
+            http_client,
+            url,
+            auth_token,
+        }
+    }
+}
+
+#[async_trait]
+impl Signer for RemoteSigner {
+    async fn sign(
+        &self,
+        address: SuiAddress,
+        data: &TransactionData,
+        intent: Intent,
+    ) -> anyhow::Result<Signature> {
+        let req = SignRequest {
+            address,
+            tx_bytes: general_purpose::STANDARD.encode(bcs::to_bytes(data)?),
+            intent,
+        };
+        let mut rb = self
+            .http_client
+            .post(format!("{}/sign", self.url.trim_end_matches('/')))
+            .json(&req);
+        if let Some(token) = &self.auth_token {
+            rb = rb.bearer_auth(token);
+        }
+        let rs = rb.send().await?;
+        if !rs.status().is_success() {
+            let status = rs.status();
+            let msg = rs.text().await.unwrap_or_default();
+            return Err(ClientError::SignerError(format!("{}: {}", status, msg)).into());
+        }
+        let rs: SignResponse = rs.json().await?;
+        let bytes = general_purpose::STANDARD.decode(rs.signature)?;
+        Signature::from_bytes(&bytes)
+            .map_err(|e| ClientError::InvalidSignature(e.to_string()).into())
+    }
+}
+
+/// The move calls the remote signer signs: every call must be of an allowed package,
+/// and the functions of the guarded modules must be allowed one by one.
+#[derive(Debug, Clone, Default)]
+pub struct SignPolicy {
+    packages: HashSet<ObjectID>,
+    // (package, module), all the modules of the package if the module is None
+    guarded: HashSet<(ObjectID, Option<String>)>,
+    // (package, module, function)
+    functions: HashSet<(ObjectID, String, String)>,
+}
+
+impl SignPolicy {
+    /// The allowed functions are module::function of the guarded modules.
+    pub fn new(
+        packages: Vec<ObjectID>,
+        guarded: Vec<(ObjectID, Option<String>)>,
+        functions: Vec<String>,
+    ) -> anyhow::Result<Self> {
+        let mut policy = Self {
+            packages: packages.into_iter().collect(),
+            guarded: guarded.into_iter().collect(),
+            functions: HashSet::new(),
+        };
+        for f in functions {
+            let (module, function) = f.split_once("::").ok_or_else(|| {
+                ClientError::ConfigError(format!("allowed function {} is not module::function", f))
+            })?;
+            let packages: Vec<ObjectID> = policy
+                .guarded
+                .iter()
+                .filter(|(_, m)| m.as_deref().map_or(true, |m| m == module))
+                .map(|(p, _)| *p)
+                .collect();
+            if packages.is_empty() {
+                return Err(ClientError::ConfigError(format!(
+                    "allowed function {} is not of a guarded module",
+                    f
+                ))
+                .into());
+            }
+            for package in packages {
+                policy
+                    .functions
+                    .insert((package, module.to_string(), function.to_string()));
+            }
+        }
+        Ok(policy)
+    }
+
+    /// The policy of the config, the scale, pyth and wormhole packages are allowed,
+    /// the enter and pyth_network modules of scale and the sui framework are guarded.
+    pub fn from_config(config: &Config) -> anyhow::Result<Self> {
+        let signers = &config.signer_config;
+        let mut packages = signers.allowed_packages.clone();
+        packages.extend([
+            config.scale_package_id,
+            config.scale_oracle_package_id,
+            ObjectID::from_str(config.price_config.pyth_package.as_str())?,
+            ObjectID::from_str(config.price_config.worm_package.as_str())?,
+        ]);
+        let guarded = vec![
+            (config.scale_package_id, Some("enter".to_string())),
+            (config.scale_package_id, Some("pyth_network".to_string())),
+            (
+                config.scale_oracle_package_id,
+                Some("pyth_network".to_string()),
+            ),
+            (SUI_FRAMEWORK_PACKAGE_ID, None),
+        ];
+        let mut functions = signers.allowed_functions.clone();
+        functions.extend(FRAMEWORK_FUNCTIONS.iter().map(|f| f.to_string()));
+        Self::new(packages, guarded, functions)
+    }
+
+    pub fn check(&self, data: &TransactionData) -> Result<(), String> {
+        let pt = match data.kind() {
+            TransactionKind::ProgrammableTransaction(pt) => pt,
+            _ => return Err("only programmable transactions are signed".to_string()),
+        };
+        for command in pt.commands.iter() {
+            match command {
+                Command::MoveCall(call) => {
+                    let (module, function) = (call.module.as_str(), call.function.as_str());
+                    let key = (call.package, module.to_string(), function.to_string());
+                    if self.functions.contains(&key) {
+                        continue;
+                    }
+                    if self.guarded.contains(&(call.package, None))
+                        || self
+                            .guarded
+                            .contains(&(call.package, Some(module.to_string())))
+                    {
+                        return Err(format!(
+                            "function {}::{}::{} is not allowed",
+                            call.package, module, function
+                        ));
+                    }
+                    if !self.packages.contains(&call.package) {
+                        return Err(format!(
+                            "package {} of {}::{} is not allowed",
+                            call.package, module, function
+                        ));
+                    }
+                }
+                Command::SplitCoins(..) | Command::MergeCoins(..) | Command::MakeMoveVec(..) => {}
+                // the coins split to the sender, as in the gas rebalance
+                Command::TransferObjects(_, recipient)
+                    if is_sender(&pt.inputs, recipient, data.sender()) => {}
+                Command::TransferObjects(..) => {
+                    return Err("transfer objects to another address is not allowed".to_string())
+                }
+                _ => return Err("publish and upgrade are not allowed".to_string()),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_sender(inputs: &[CallArg], arg: &Argument, sender: SuiAddress) -> bool {
+    match arg {
+        Argument::Input(i) => match inputs.get(*i as usize) {
+            Some(CallArg::Pure(bytes)) => bcs::from_bytes::<SuiAddress>(bytes).ok() == Some(sender),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+struct SignerState {
+    keystore: Keystore,
+    policy: SignPolicy,
+    auth_token: Option<String>,
+}
+
+impl SignerState {
+    fn sign(
+        &self,
+        headers: &HeaderMap,
+        req: SignRequest,
+    ) -> Result<SignResponse, (StatusCode, String)> {
+        if let Some(token) = &self.auth_token {
+            let auth = headers
+                .get(header::AUTHORIZATION)
+                .and_then(|v| v.to_str().ok());
+            if auth != Some(format!("Bearer {}", token).as_str()) {
+                return Err((StatusCode::UNAUTHORIZED, "invalid auth token".to_string()));
+            }
+        }
+        let bad_request = |e: String| (StatusCode::BAD_REQUEST, e);
+        // only the transactions are signed, never a personal message or another intent
+        if req.intent != Intent::sui_transaction() {
+            return Err(bad_request(
+                "only the sui transaction intent is signed".to_string(),
+            ));
+        }
+        let bytes = general_purpose::STANDARD
+            .decode(req.tx_bytes)
+            .map_err(|e| bad_request(e.to_string()))?;
+        let data: TransactionData =
+            bcs::from_bytes(&bytes).map_err(|e| bad_request(e.to_string()))?;
+        if data.sender() != req.address {
+            return Err(bad_request(format!(
+                "sender {} is not the signer {}",
+                data.sender(),
+                req.address
+            )));
+        }
+        self.policy
+            .check(&data)
+            .map_err(|e| (StatusCode::FORBIDDEN, e))?;
+        let signature = self
+            .keystore
+            .sign_secure(&req.address, &data, Intent::sui_transaction())
+            .map_err(|e| (StatusCode::FORBIDDEN, e.to_string()))?;
+        Ok(SignResponse {
+            signature: general_purpose::STANDARD.encode(signature.as_ref()),
+        })
+    }
+}
+
+async fn sign(
+    Extension(state): Extension<Arc<SignerState>>,
+    headers: HeaderMap,
+    Json(req): Json<SignRequest>,
+) -> impl IntoResponse {
+    let address = req.address;
+    match state.sign(&headers, req) {
+        Ok(rs) => {
+            debug!("signed transaction of {}", address);
+            (StatusCode::OK, Json(rs)).into_response()
+        }
+        Err((status, e)) => {
+            warn!("refuse to sign transaction of {}: {}", address, e);
+            (status, e).into_response()
+        }
+    }
+}
+
+/// Load the file keystore of the sui cli config.
+pub fn load_keystore(sui_cli_config_file: &PathBuf) -> anyhow::Result<Keystore> {
+    #[derive(Deserialize)]
+    struct SuiCliConfig {
+        keystore: KeystoreFile,
+    }
+    let config: SuiCliConfig = serde_yaml::from_str(&fs::read_to_string(sui_cli_config_file)?)?;
+    Ok(Keystore::from(FileBasedKeystore::new(
+        &config.keystore.file,
+    )?))
+}
+
+/// Serve the remote signer, signing the allowed transactions with the keystore.
+/// Without an auth token, it is only served on the loopback address.
+pub async fn serve(
+    listener: TcpListener,
+    keystore: Keystore,
+    policy: SignPolicy,
+    auth_token: Option<String>,
+) -> anyhow::Result<()> {
+    let addr = listener.local_addr()?;
+    if auth_token.is_none() && !addr.ip().is_loopback() {
+        return Err(ClientError::ConfigError(format!(
+            "remote signer on {} needs an auth token",
+            addr
+        ))
+        .into());
+    }
+    let state = Arc::new(SignerState {
+        keystore,
+        policy,
+        auth_token,
+    });
+    let router = Router::new()
+        .route("/sign", post(sign))
+        .layer(Extension(state));
+    info!("start remote signer on {}", addr);
+    axum::Server::from_tcp(listener)?
+        .serve(router.into_make_service())
+        .await
+        .map_err(|e| ClientError::HttpServerError(e.to_string()).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sui_keys::keystore::InMemKeystore;
+    use sui_types::{
+        base_types::random_object_ref,
+        programmable_transaction_builder::ProgrammableTransactionBuilder as PTB, Identifier,
+    };
+
+    const TOKEN: &str = "secret";
+
+    fn transaction(
+        sender: SuiAddress,
+        package: ObjectID,
+        module: &str,
+        function: &str,
+    ) -> TransactionData {
+        let mut tx = PTB::new();
+        tx.programmable_move_call(
+            package,
+            Identifier::from_str(module).unwrap(),
+            Identifier::from_str(function).unwrap(),
+            vec![],
+            vec![],
+        );
+        TransactionData::new_programmable(sender, vec![random_object_ref()], tx.finish(), 1000, 1)
+    }
+
+    fn pay_sui(sender: SuiAddress, recipient: SuiAddress) -> TransactionData {
+        let mut tx = PTB::new();
+        tx.pay_sui(vec![recipient], vec![1000]).unwrap();
+        TransactionData::new_programmable(sender, vec![random_object_ref()], tx.finish(), 1000, 1)
+    }
+
+    fn policy(package: ObjectID) -> SignPolicy {
+        let mut functions = vec!["enter::force_liquidation".to_string()];
+        functions.extend(FRAMEWORK_FUNCTIONS.iter().map(|f| f.to_string()));
+        SignPolicy::new(
+            vec![package],
+            vec![
+                (package, Some("enter".to_string())),
+                (SUI_FRAMEWORK_PACKAGE_ID, None),
+            ],
+            functions,
+        )
+        .unwrap()
+    }
+
+    async fn start_signer(keystore: Keystore, policy: SignPolicy) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(serve(listener, keystore, policy, Some(TOKEN.to_string())));
+        url
+    }
+
+    #[test]
+    fn test_sign_policy() {
+        let sender = SuiAddress::random_for_testing_only();
+        let package = ObjectID::random();
+        let policy = policy(package);
+        let check = |data: TransactionData| policy.check(&data);
+
+        assert!(check(transaction(sender, package, "enter", "force_liquidation")).is_ok());
+        // not in the allow-list
+        assert!(check(transaction(sender, package, "enter", "withdrawal")).is_err());
+        // not an allowed package
+        let other = ObjectID::random();
+        assert!(check(transaction(sender, other, "enter", "force_liquidation")).is_err());
+        // the other modules of an allowed package need no allowance
+        assert!(check(transaction(sender, package, "market", "get_price")).is_ok());
+        // the guarded module is of the package, not of the name
+        assert!(check(transaction(sender, package, "coin", "split")).is_ok());
+        // only the allowed framework functions
+        let framework = SUI_FRAMEWORK_PACKAGE_ID;
+        assert!(check(transaction(sender, framework, "coin", "split")).is_ok());
+        assert!(check(transaction(
+            sender,
+            framework,
+            "transfer",
+            "public_transfer"
+        ))
+        .is_err());
+        assert!(check(transaction(sender, framework, "pay", "split_and_transfer")).is_err());
+        // the coins are only transferred to the sender
+        assert!(check(pay_sui(sender, sender)).is_ok());
+        let attacker = SuiAddress::random_for_testing_only();
+        assert!(check(pay_sui(sender, attacker)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_remote_signer() {
+        let keystore = Keystore::from(InMemKeystore::new_insecure_for_tests(1));
+        let address = keystore.addresses()[0];
+        let package = ObjectID::random();
+        let intent = Intent::sui_transaction();
+        let allowed = transaction(address, package, "enter", "force_liquidation");
+        let expected = keystore
+            .sign_secure(&address, &allowed, intent.clone())
+            .unwrap();
+        let url = start_signer(keystore, policy(package)).await;
+        let signer = RemoteSigner::new(HttpClient::new(), url.clone(), Some(TOKEN.to_string()));
+
+        let signature = signer
+            .sign(address, &allowed, intent.clone())
+            .await
+            .unwrap();
+        assert_eq!(signature, expected);
+
+        // refused by the policy
+        let tx = transaction(
+            address,
+            SUI_FRAMEWORK_PACKAGE_ID,
+            "transfer",
+            "public_transfer",
+        );
+        assert!(signer.sign(address, &tx, intent.clone()).await.is_err());
+        // the key of another address
+        let other = SuiAddress::random_for_testing_only();
+        let tx = transaction(other, package, "enter", "force_liquidation");
+        assert!(signer.sign(other, &tx, intent.clone()).await.is_err());
+        // another intent
+        let personal = Intent::personal_message();
+        assert!(signer.sign(address, &allowed, personal).await.is_err());
+        // without the token
+        let anonymous = RemoteSigner::new(HttpClient::new(), url.clone(), None);
+        assert!(anonymous
+            .sign(address, &allowed, intent.clone())
+            .await
+            .is_err());
+        let wrong = RemoteSigner::new(HttpClient::new(), url, Some("wrong".to_string()));
+        assert!(wrong.sign(address, &allowed, intent).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_remote_signer_needs_token_off_loopback() {
+        let keystore = Keystore::from(InMemKeystore::new_insecure_for_tests(1));
+        let listener = TcpListener::bind("0.0.0.0:0").unwrap();
+        let rs = serve(listener, keystore, SignPolicy::default(), None).await;
+        assert!(rs.is_err());
+    }
+}
@@ -1,19 +1,31 @@
+use std::path::Path;
 use std::str::FromStr;
 
 use crate::bot::state::{Event, EventSyncRx, MessageSender};
-use crate::com::{Task, TaskStopRx};
+use crate::com::{ClientError, Task, TaskStopRx};
 use crate::sui::config::Ctx;
 use crate::sui::object;
-use crate::sui::object::ObjectType;
+use crate::sui::object::{ObjectBatch, ObjectType};
 use log::*;
 use move_core_types::{identifier::Identifier, language_storage::TypeTag};
 use sui_sdk::rpc_types::{EventFilter, SuiEvent};
 use sui_sdk::types::base_types::ObjectID;
+use sui_sdk::SuiClient;
 use sui_types::event::EventID;
+use tokio::time::{self, Duration, Instant};
 // use tokio_stream::StreamExt;
 use futures::StreamExt;
 use serde_json::Value;
 
+const RECONNECT_MIN_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+// a subscription closed before this uptime and without any event is a failed connection
+const RECONNECT_MIN_UPTIME: Duration = Duration::from_secs(30);
+const BACKFILL_PAGE_SIZE: usize = 50;
+const EVENT_CURSOR_KEY: &str = "last_event";
+// the events of a burst are pulled in one multi get after the first one waits this window
+const EVENT_BATCH_WINDOW: Duration = Duration::from_millis(100);
+
 pub struct EventSubscriber {
     task: Task,
 }
@@ -35,46 +47,93 @@ impl EventSubscriber {
         watch_tx: MessageSender,
         mut sync_rx: EventSyncRx,
     ) -> anyhow::Result<()> {
+        let cursor_store = CursorStore::open(&ctx.config.scale_store_path)?;
+        let mut cursor = cursor_store.load()?;
+        let mut delay = RECONNECT_MIN_DELAY;
         'connection: loop {
             info!("event sub connecting ...");
-            // let filter = EventFilter::All(vec![
-            //     EventFilter::Package(ctx.config.scale_package_id),
-            //     // SuiEventFilter::Module("enter".to_string()),
-            // ]);
             let filter = EventFilter::Package(ctx.config.scale_package_id);
-            // todo: If the server is not running for the first time, it will continuously retry.
-            let client = ctx.wallet.get_client().await?;
-            let mut sub = client.event_api().subscribe_event(filter).await?;
+            let connected = match ctx.wallet.get_client().await {
+                Ok(client) => match client.event_api().subscribe_event(filter).await {
+                    Ok(sub) => Ok((client, sub)),
+                    Err(e) => Err(e.into()),
+                },
+                Err(e) => Err(e),
+            };
+            let (client, mut sub) = match connected {
+                Ok(c) => c,
+                Err(e) => {
+                    error!("event sub connect error: {}, retry in {:?}", e, delay);
+                    tokio::select! {
+                        _ = &mut close_rx => break 'connection,
+                        _ = time::sleep(delay) => {}
+                    }
+                    delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+                    continue;
+                }
+            };
             debug!("event sub created ...");
-            // let mut timer = time::interval(Duration::from_secs(5));
+            // the live events are buffered by the subscription while the gap is filled,
+            // an event may be handled twice, which only pulls its object again.
+            // Without a cursor the startup sync has the objects, the gap of a later
+            // reconnection is filled from the latest event before this subscription.
+            let caught_up = match cursor.clone() {
+                Some(from) => backfill(&ctx, &client, from, &watch_tx, &cursor_store)
+                    .await
+                    .map(Some),
+                None => seed_cursor(&ctx, &client, &cursor_store).await,
+            };
+            match caught_up {
+                Ok(last) => {
+                    if last.is_some() {
+                        cursor = last;
+                    }
+                }
+                Err(e) => {
+                    error!("event backfill error: {}, retry in {:?}", e, delay);
+                    drop(sub);
+                    tokio::select! {
+                        _ = &mut close_rx => break 'connection,
+                        _ = time::sleep(delay) => {}
+                    }
+                    delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+                    continue;
+                }
+            }
+            let connected_at = Instant::now();
+            let mut got_event = false;
+            // the objects of the events in the window, pulled together
+            let mut batch = ObjectBatch::new();
+            // the last event of the batch, the cursor once the batch is handled
+            let mut received: Option<EventID> = None;
+            let mut flush_at: Option<Instant> = None;
             'sub: loop {
                 tokio::select! {
                     r = &mut close_rx => {
                         debug!("event sub got close signal: {:?}", r);
                         break 'connection;
                     }
+                    _ = time::sleep_until(flush_at.unwrap_or_else(Instant::now)), if flush_at.is_some() => {
+                        flush_at = None;
+                        let last = received.take();
+                        if let Err(e) = flush(&ctx, &mut batch, &watch_tx, &last, &cursor_store).await {
+                            // the events of the batch are backfilled from the cursor
+                            error!("event sub pull objects error: {}, reconnect", e);
+                            break 'sub;
+                        }
+                        if last.is_some() {
+                            cursor = last;
+                        }
+                    }
                     rs = sub.next() =>{
                         debug!("event sub got next: {:?}", rs);
                         match rs {
                             Some(Ok(event)) => {
                                 debug!("event sub got event: {:?}", event);
-                                if let Some(event_rs) = get_change_object(event) {
-                                    // debug!("event sub got event_rs: {:?}", event_rs);
-                                    if event_rs.object_type != ObjectType::None {
-                                        match object::pull_object(ctx.clone(), event_rs.object_id).await{
-                                            Ok(mut msg) => {
-                                                debug!("pull object success: {:?}", msg);
-                                                msg.event = event_rs.event;
-                                                if let Err(e) = watch_tx.send(msg) {
-                                                    error!("watch_tx send error: {:?}", e);
-                                                }
-                                            }
-                                            Err(e) => {
-                                                error!("pull object error: {:?}", e);
-                                            }
-                                        }
-                                    }
-                                }
+                                got_event = true;
+                                received = Some(event.id.clone());
+                                push_event(&mut batch, event);
+                                flush_at.get_or_insert_with(|| Instant::now() + EVENT_BATCH_WINDOW);
                             }
                             Some(Err(e)) => {
                                 error!("event sub got error: {:?}", e);
@@ -90,9 +149,8 @@ impl EventSubscriber {
                         if let Some(id) = id {
                             match ObjectID::from_str(id.to_string().as_str()){
                                 Ok(id) => {
-                                    if let Err(e) = object::pull_object(ctx.clone(), id).await{
-                                        error!("pull object error: {:?}", e);
-                                    }
+                                    batch.push(id, Event::None);
+                                    flush_at.get_or_insert_with(|| Instant::now() + EVENT_BATCH_WINDOW);
                                 }
                                 Err(e) => {
                                     error!("parse object id error: {:?}", e);
@@ -103,14 +161,160 @@ impl EventSubscriber {
                 }
             }
             drop(sub);
-            info!("sui event sub reconnecting ...");
+            // the events received before the subscription closed
+            let last = received.take();
+            match flush(&ctx, &mut batch, &watch_tx, &last, &cursor_store).await {
+                Ok(()) if last.is_some() => cursor = last,
+                Ok(()) => {}
+                Err(e) => error!("event sub pull objects error: {}", e),
+            }
+            // a node closing the subscription right away is retried with the backoff
+            if got_event || connected_at.elapsed() >= RECONNECT_MIN_UPTIME {
+                delay = RECONNECT_MIN_DELAY;
+                info!("sui event sub reconnecting ...");
+            } else {
+                warn!("sui event sub closed early, reconnect in {:?}", delay);
+                tokio::select! {
+                    _ = &mut close_rx => break 'connection,
+                    _ = time::sleep(delay) => {}
+                }
+                delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+            }
         }
         Ok(())
     }
     pub async fn shutdown(self) {
         self.task.shutdown().await;
     }
+    pub fn into_tasks(self) -> Vec<Task> {
+        vec![self.task]
+    }
+}
+// Push the object changed by the event to the batch.
+fn push_event(batch: &mut ObjectBatch, event: SuiEvent) {
+    if let Some(event_rs) = get_change_object(event) {
+        if event_rs.object_type != ObjectType::None {
+            batch.push(event_rs.object_id, event_rs.event);
+        }
+    }
+}
+
+// Pull the objects of the batch and send them to the watch task,
+// then save the cursor of the last event pushed, which is not saved if any pull failed.
+async fn flush(
+    ctx: &Ctx,
+    batch: &mut ObjectBatch,
+    watch_tx: &MessageSender,
+    cursor: &Option<EventID>,
+    cursor_store: &CursorStore,
+) -> anyhow::Result<()> {
+    if !batch.is_empty() {
+        debug!("event sub pull {} objects", batch.len());
+        object::pull_objects_with_events_and_send(ctx.clone(), batch.take(), watch_tx).await?;
+    }
+    if let Some(id) = cursor {
+        if let Err(e) = cursor_store.save(id) {
+            error!("save event cursor error: {}", e);
+        }
+    }
+    Ok(())
+}
+
+// Handle the events after the cursor, missed while disconnected.
+// Returns the last handled event, the cursor itself if there is none.
+async fn backfill(
+    ctx: &Ctx,
+    client: &SuiClient,
+    from: EventID,
+    watch_tx: &MessageSender,
+    cursor_store: &CursorStore,
+) -> anyhow::Result<EventID> {
+    let mut cursor = from;
+    let mut count = 0;
+    loop {
+        // the same module events as the startup sync
+        let page = client
+            .event_api()
+            .query_events(
+                EventFilter::MoveModule {
+                    package: ctx.config.scale_package_id,
+                    module: Identifier::from_str("enter")?,
+                },
+                Some(cursor.clone()),
+                Some(BACKFILL_PAGE_SIZE),
+                false,
+            )
+            .await?;
+        let has_next_page = page.has_next_page && !page.data.is_empty();
+        let mut batch = ObjectBatch::new();
+        for event in page.data {
+            cursor = event.id.clone();
+            push_event(&mut batch, event);
+            count += 1;
+        }
+        object::pull_objects_with_events_and_send(ctx.clone(), batch.take(), watch_tx).await?;
+        cursor_store.save(&cursor)?;
+        if !has_next_page {
+            break;
+        }
+    }
+    if count > 0 {
+        info!("event sub backfilled {} missed events", count);
+    }
+    Ok(cursor)
 }
+
+// Save the latest event as the cursor, none if the package has no event yet.
+async fn seed_cursor(
+    ctx: &Ctx,
+    client: &SuiClient,
+    cursor_store: &CursorStore,
+) -> anyhow::Result<Option<EventID>> {
+    let page = client
+        .event_api()
+        .query_events(
+            EventFilter::MoveModule {
+                package: ctx.config.scale_package_id,
+                module: Identifier::from_str("enter")?,
+            },
+            None,
+            Some(1),
+            true,
+        )
+        .await?;
+    let latest = page.data.into_iter().next().map(|e| e.id);
+    if let Some(id) = &latest {
+        cursor_store.save(id)?;
+        debug!("event sub cursor seeded at {:?}", id);
+    }
+    Ok(latest)
+}
+
+// The id of the last handled event, kept across restarts.
+struct CursorStore {
+    db: sled::Db,
+}
+
+impl CursorStore {
+    fn open(store_path: &Path) -> anyhow::Result<Self> {
+        let db = sled::open(store_path.join("event_cursor"))
+            .map_err(|e| ClientError::DBError(e.to_string()))?;
+        Ok(Self { db })
+    }
+
+    fn load(&self) -> anyhow::Result<Option<EventID>> {
+        match self.db.get(EVENT_CURSOR_KEY)? {
+            Some(v) => Ok(Some(serde_json::from_slice(&v)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn save(&self, id: &EventID) -> anyhow::Result<()> {
+        self.db.insert(EVENT_CURSOR_KEY, serde_json::to_vec(id)?)?;
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct EventResult {
     pub object_type: ObjectType,
@@ -118,7 +322,7 @@ pub struct EventResult {
     pub event: Event,
 }
 
-fn get_change_object(event: SuiEvent) -> Option<EventResult> {
+pub fn get_change_object(event: SuiEvent) -> Option<EventResult> {
     if event.type_.type_params.len() > 0 {
         if let TypeTag::Struct(v) = &event.type_.type_params[0] {
             if let Value::Object(obj) = &event.parsed_json {
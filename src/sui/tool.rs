@@ -1,11 +1,14 @@
 use crate::{
     bot::state::DENOMINATOR,
-    bot::state::{Address, MoveCall, PositionParams},
+    bot::state::{Address, MoveCall, PositionParams, TxOutcome},
     com,
     com::ClientError,
     sui::{
-        config::{Config, Context, Ctx},
+        config::{Config, Context, Ctx, Role},
+        executor::{Executor, TxData},
+        gas::{GasLease, GasPool, SharedGasPool},
         object::{self, ObjectParams},
+        signer::{LocalSigner, RemoteSigner, SharedSigner},
     },
     utils,
 };
@@ -20,26 +23,21 @@ use move_core_types::{
 };
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value as JsonValue};
-use shared_crypto::intent::Intent;
 use std::collections::HashMap;
 use std::fmt;
 use std::str::FromStr;
+use std::sync::Arc;
 use sui_json_rpc_types::{
-    MoveCallParams, RPCTransactionRequestParams, SuiExecutionStatus, SuiMoveStruct, SuiObjectData,
-    SuiObjectDataFilter, SuiObjectDataOptions, SuiObjectResponse, SuiObjectResponseQuery,
-    SuiTransactionBlockEffects, SuiTypeTag,
+    MoveCallParams, RPCTransactionRequestParams, SuiMoveStruct, SuiObjectData, SuiObjectDataFilter,
+    SuiObjectDataOptions, SuiObjectResponse, SuiObjectResponseQuery, SuiTypeTag,
 };
-use sui_keys::keystore::AccountKeystore;
 use sui_sdk::{
     json::SuiJsonValue,
-    rpc_types::SuiTransactionBlockResponseOptions,
-    types::{base_types::ObjectID, transaction::Transaction},
+    types::base_types::{ObjectID, SuiAddress},
 };
 use sui_types::{
     coin::{self, Coin},
-    crypto::Signature,
     programmable_transaction_builder::ProgrammableTransactionBuilder as PTB,
-    quorum_driver_types::ExecuteTransactionRequestType,
     transaction::{Argument, CallArg, Command, ObjectArg, TransactionData, TransactionKind},
     Identifier, SUI_CLOCK_OBJECT_ID, SUI_FRAMEWORK_PACKAGE_ID,
 };
@@ -59,6 +57,9 @@ const SCALE_ENTER_MODULE: &IdentStr = ident_str!("pyth_network");
 pub struct Tool {
     ctx: Ctx,
     gas_budget: u64,
+    // the gas pools of the signers, shared by the roles of the same signer
+    gas_pools: HashMap<Role, SharedGasPool>,
+    executor: Executor,
     pyth_price_info_mp: HashMap<String, PriceInfo>,
     is_init_pyth_price_info: bool,
 }
@@ -71,7 +72,7 @@ struct PTBCtx {
     objects: ObjectParams,
     tx: PTB,
     is_init: bool,
-    gas_coins: Vec<(u64, SuiObjectData)>,
+    gas: Option<GasLease>,
     ctx: Ctx,
     gas_budget: u64,
 }
@@ -82,7 +83,7 @@ impl PTBCtx {
             objects: ObjectParams::new(),
             tx: PTB::new(),
             is_init: false,
-            gas_coins: Vec::new(),
+            gas: None,
             ctx,
             gas_budget,
         }
@@ -94,12 +95,12 @@ impl PTBCtx {
         self.object_ids.extend(object_ids);
     }
 
-    pub async fn init(&mut self, gas: Vec<(u64, SuiObjectData)>) -> anyhow::Result<()> {
+    pub async fn init(&mut self, gas: GasLease) -> anyhow::Result<()> {
         if self.is_init {
             return Ok(());
         }
         self.objects = object::get_object_args(self.ctx.clone(), self.object_ids.clone()).await?;
-        self.gas_coins = gas;
+        self.gas = Some(gas);
         self.is_init = true;
         Ok(())
     }
@@ -117,46 +118,22 @@ impl PTBCtx {
         ))
     }
 
-    pub fn split_one_gas(&mut self, budget: u64) -> anyhow::Result<SuiObjectData> {
-        if !self.is_init {
-            return Err(ClientError::PTBCtxNotInit.into());
-        }
-        if self.gas_coins.len() != 1 {
-            return self.select_one_gas(budget);
-        }
-        // TODO if account has only one gas coin, split it
-        let (_balance, gas_coin_data) = self.gas_coins.pop().ok_or(ClientError::NoGasCoin)?;
-        Ok(gas_coin_data)
-    }
-    pub fn select_one_gas(&mut self, budget: u64) -> anyhow::Result<SuiObjectData> {
-        if !self.is_init {
-            return Err(ClientError::PTBCtxNotInit.into());
-        }
-        for i in 0..self.gas_coins.len() {
-            let (balance, gas_coin_data) = self.gas_coins[i].clone();
-            if balance >= budget {
-                self.gas_coins.remove(i);
-                return Ok(gas_coin_data);
-            }
-        }
-        Err(ClientError::NoGasCoin.into())
-    }
-
-    pub async fn get_transaction_data(mut px: PTBCtx) -> anyhow::Result<TransactionData> {
+    pub async fn get_transaction_data(px: PTBCtx) -> anyhow::Result<TxData> {
         if !px.is_init {
             return Err(ClientError::PTBCtxNotInit.into());
         }
-        let gas = px.select_one_gas(px.gas_budget)?;
-        debug!("transaction gas: {:?}", gas.object_id);
         let PTBCtx {
             tx,
+            gas,
             gas_budget,
             ctx,
             ..
         } = px;
+        let gas = gas.ok_or(ClientError::NoGasCoin)?;
+        debug!("transaction gas: {:?}", gas.object_id());
         let pt = tx.finish();
 
-        let sender = ctx.get_active_address()?;
+        let sender = gas.owner();
         let gas_price = ctx.client.read_api().get_reference_gas_price().await?;
         let tx_data = TransactionData::new(
             TransactionKind::ProgrammableTransaction(pt.to_owned()),
@@ -165,7 +142,10 @@ impl PTBCtx {
             gas_budget,
             gas_price.to_owned(),
         );
-        Ok(tx_data)
+        Ok(TxData {
+            data: tx_data,
+            gas: Some(gas),
+        })
     }
 }
 #[derive(Debug, Deserialize, Serialize)]
@@ -198,9 +178,30 @@ impl fmt::LowerHex for PriceIdentifier {
 impl Tool {
     pub async fn new(conf: Config, gas_budget: u64) -> anyhow::Result<Self> {
         let ctx = Context::new(conf).await?;
+        let mut signer_pools: HashMap<SuiAddress, SharedGasPool> = HashMap::new();
+        let mut gas_pools = HashMap::new();
+        for role in Role::ALL {
+            let signer = ctx.get_signer(role)?;
+            debug!("signer of {:?}: {}", role, signer);
+            let pool = signer_pools.entry(signer).or_insert_with(|| {
+                GasPool::new(ctx.clone(), ctx.config.gas_pool_config.clone(), signer)
+            });
+            gas_pools.insert(role, pool.clone());
+        }
+        let signer: SharedSigner = match &ctx.config.signer_config.remote_url {
+            Some(url) => Arc::new(RemoteSigner::new(
+                ctx.http_client.clone(),
+                url.clone(),
+                ctx.config.signer_config.auth_token.clone(),
+            )),
+            None => Arc::new(LocalSigner::new(ctx.clone())),
+        };
+        let executor = Executor::new(ctx.clone(), ctx.config.executor_config.clone(), signer);
         let mut tool = Self {
             ctx,
             gas_budget,
+            gas_pools,
+            executor,
             pyth_price_info_mp: HashMap::new(),
             is_init_pyth_price_info: false,
         };
@@ -260,14 +261,16 @@ impl Tool {
         }
         Err(ClientError::PythPriceInfoNotFound(price_feed_id.to_string()).into())
     }
-    // package the infos of the update price transaction
+    // package the infos of the update price transaction, `update_fee` is paid for each feed
     async fn init_price_update_transaction(
         &self,
-        total_pyth_fee: u64,
+        role: Role,
+        update_fee: u64,
         vaa_data: Vec<String>,
         price_info_object_ids: Vec<ObjectID>,
         other_objects: Vec<ObjectID>,
     ) -> anyhow::Result<PTBCtx> {
+        let total_pyth_fee = price_info_object_ids.len() as u64 * update_fee;
         if total_pyth_fee == 0 {
             return Err(ClientError::InvalidCliParams("budget is zero".to_string()).into());
         }
@@ -290,7 +293,11 @@ impl Tool {
             SUI_CLOCK_OBJECT_ID,
         ]);
         px.add_object_ids(price_info_object_ids.clone());
-        let gas = self.get_all_gas().await?;
+        // the pyth fees are split from the gas coin
+        let gas = self
+            .gas_pool(role)
+            .acquire(self.gas_budget + total_pyth_fee)
+            .await?;
         px.init(gas).await?;
         // init object input
         let worm_state_input = px.get_object_input(worm_state_id, false)?;
@@ -334,12 +341,8 @@ impl Tool {
             vec![],
             vec![pyth_state_input, vaas, clock_object_input],
         );
-        let amount = px.tx.pure(PYTH_PRICE_UPDATE_FEE)?;
-        let coin_token = px.split_one_gas(total_pyth_fee)?;
-        debug!("transaction python pay gas: {:?}", coin_token.object_id);
-        let coin_input = px
-            .tx
-            .obj(ObjectArg::ImmOrOwnedObject(coin_token.object_ref()))?;
+        let amount = px.tx.pure(update_fee)?;
+        let coin_input = Argument::GasCoin;
         for info in price_info_object_ids {
             let c = px.tx.programmable_move_call(
                 SUI_FRAMEWORK_PACKAGE_ID,
@@ -408,7 +411,7 @@ impl Tool {
         Ok(TypeTag::from_str(t.as_str())?)
     }
     pub async fn get_gas(&self, budget: u64) -> anyhow::Result<Vec<(u64, SuiObjectData)>> {
-        let active_address = self.ctx.get_active_address()?;
+        let active_address = self.ctx.get_signer(Role::Default)?;
         let gas_objects = self.ctx.wallet.gas_objects(active_address).await?;
         let mut sui_objects = Vec::new();
         let mut amout = 0u64;
@@ -429,18 +432,8 @@ impl Tool {
         Ok(sui_objects)
     }
 
-    pub async fn get_all_gas(&self) -> anyhow::Result<Vec<(u64, SuiObjectData)>> {
-        let active_address = self.ctx.get_active_address()?;
-        let gas_objects = self.ctx.wallet.gas_objects(active_address).await?;
-        let mut sui_objects = Vec::new();
-        for gas_object in gas_objects {
-            sui_objects.push(gas_object);
-        }
-        Ok(sui_objects)
-    }
-
     pub async fn get_coin_object_whith_t(&self, budget: u64) -> anyhow::Result<Vec<ObjectID>> {
-        let active_address = self.ctx.get_active_address()?;
+        let active_address = self.ctx.get_signer(Role::Default)?;
         let mut coin_objects: Vec<SuiObjectResponse> = Vec::new();
         let mut cursor = None;
         loop {
@@ -489,53 +482,26 @@ impl Tool {
         Ok(token_objects)
     }
 
-    fn get_transaction_signature(&self, pm: &TransactionData) -> anyhow::Result<Signature> {
-        let address = self.ctx.wallet.config.active_address.ok_or_else(|| {
-            ClientError::NoActiveAccount(
-                "no active account, please use sui client command create it .".to_string(),
-            )
-        })?;
-        let signature =
-            self.ctx
-                .wallet
-                .config
-                .keystore
-                .sign_secure(&address, pm, Intent::sui_transaction())?;
-        Ok(signature)
-    }
-
-    async fn exec(&self, pm: TransactionData) -> anyhow::Result<()> {
-        let signature = self.get_transaction_signature(&pm)?;
-        let opt = SuiTransactionBlockResponseOptions::default();
-        let tx = self
-            .ctx
-            .client
-            .quorum_driver_api()
-            .execute_transaction_block(
-                Transaction::from_data(pm.clone(), Intent::sui_transaction(), vec![signature]),
-                opt,
-                Some(ExecuteTransactionRequestType::WaitForLocalExecution),
-            )
-            .await?;
-
-        if tx.errors.is_empty() {
-            println!("exec: {:?} , success", tx.digest.to_string());
-        } else {
-            println!("exec: {:?} , error: {:?}", tx.digest.to_string(), tx.errors);
-        }
-        if let Some(e) = tx.effects {
-            let SuiTransactionBlockEffects::V1(v) = e;
-            match v.status {
-                SuiExecutionStatus::Success => {
-                    return Ok(());
-                }
-                SuiExecutionStatus::Failure { error } => {
-                    println!(" error: {:?}", error);
-                    return Err(ClientError::TransactionExecutionFailure(error).into());
-                }
+    /// Submit the transaction through the executor.
+    pub async fn send(&self, tx: TxData) -> anyhow::Result<TxOutcome> {
+        match self.executor.execute(tx).await {
+            Ok(outcome) => {
+                debug!("exec: {}", outcome);
+                Ok(outcome)
+            }
+            Err(e) => {
+                debug!("exec error: {}", e);
+                Err(e)
             }
         }
-        Ok(())
+    }
+
+    async fn exec(&self, tx: TxData) -> anyhow::Result<()> {
+        self.send(tx).await.map(print_outcome)
+    }
+
+    fn gas_pool(&self, role: Role) -> &SharedGasPool {
+        &self.gas_pools[&role]
     }
 
     async fn get_transaction_data(
@@ -545,25 +511,48 @@ impl Tool {
         function: &str,
         call_args: Vec<SuiJsonValue>,
         type_args: Vec<SuiTypeTag>,
-    ) -> anyhow::Result<TransactionData> {
-        self.ctx
+    ) -> anyhow::Result<TxData> {
+        self.get_role_transaction_data(
+            Role::Default,
+            package,
+            module,
+            function,
+            call_args,
+            type_args,
+        )
+        .await
+    }
+
+    // the transaction is sent and signed by the signer of the role
+    async fn get_role_transaction_data(
+        &self,
+        role: Role,
+        package: ObjectID,
+        module: &str,
+        function: &str,
+        call_args: Vec<SuiJsonValue>,
+        type_args: Vec<SuiTypeTag>,
+    ) -> anyhow::Result<TxData> {
+        let gas = self.gas_pool(role).acquire(self.gas_budget).await?;
+        let data = self
+            .ctx
             .client
             .transaction_builder()
             .move_call(
-                self.ctx.wallet.config.active_address.ok_or_else(|| {
-                    ClientError::NoActiveAccount(
-                        "no active account, please use sui client command create it .".to_string(),
-                    )
-                })?,
+                gas.owner(),
                 package,
                 module,
                 function,
                 type_args,
                 call_args,
-                None,
+                Some(gas.object_id()),
                 self.gas_budget,
             )
-            .await
+            .await?;
+        Ok(TxData {
+            data,
+            gas: Some(gas),
+        })
     }
 
     pub async fn coin_set(&self, args: &clap::ArgMatches) -> anyhow::Result<()> {
@@ -586,6 +575,22 @@ impl Tool {
         self.exec(transaction_data).await
     }
 
+    /// Merge the dust gas coins and split the SUI into the gas pool size, for each signer.
+    pub async fn rebalance_gas(&self) -> anyhow::Result<()> {
+        let mut signers = Vec::new();
+        for pool in self.gas_pools.values() {
+            if signers.contains(&pool.owner()) {
+                continue;
+            }
+            signers.push(pool.owner());
+            if let Some(tx) = pool.rebalance_transaction(self.gas_budget).await? {
+                self.exec(tx.into()).await?;
+            }
+            pool.refresh().await?;
+        }
+        Ok(())
+    }
+
     pub async fn coin_burn(&self, args: &clap::ArgMatches) -> anyhow::Result<()> {
         let coins = args
             .get_many::<String>("coins")
@@ -719,13 +724,21 @@ impl Tool {
             });
             p.push(ts);
         }
+        let gas = self
+            .gas_pool(Role::Default)
+            .acquire(self.gas_budget)
+            .await?;
         let ts_data = self
             .ctx
             .client
             .transaction_builder()
-            .batch_transaction(self.ctx.get_active_address()?, p, None, self.gas_budget)
+            .batch_transaction(gas.owner(), p, Some(gas.object_id()), self.gas_budget)
             .await?;
-        self.exec(ts_data).await
+        self.exec(TxData {
+            data: ts_data,
+            gas: Some(gas),
+        })
+        .await
     }
 
     pub async fn update_pyth_price_auto(&self) -> anyhow::Result<()> {
@@ -743,17 +756,19 @@ impl Tool {
     }
     pub async fn update_all_pyth_price(&self) -> anyhow::Result<()> {
         let feed_ids = self.ctx.config.price_config.get_feed_ids(None);
-        self.update_pyth_price_by_ids(feed_ids, PYTH_PRICE_UPDATE_FEE)
+        self.update_pyth_price_by_ids(Role::Default, feed_ids, PYTH_PRICE_UPDATE_FEE)
             .await
+            .map(print_outcome)
     }
 
     async fn update_pyth_price_by_ids(
         &self,
+        role: Role,
         feed_ids: Vec<String>,
         update_fee: u64,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<TxOutcome> {
         let px = self
-            .wrapper_ptb_with_pyth(feed_ids, update_fee, vec![])
+            .wrapper_ptb_with_pyth(role, feed_ids, update_fee, vec![])
             .await?;
         let tx_data = PTBCtx::get_transaction_data(px).await?;
         // let response = self
@@ -764,11 +779,12 @@ impl Tool {
         //     .await?;
         // println!("dry_run_transaction_block: {:?}", response.effects);
         // Ok(())
-        self.exec(tx_data).await
+        self.send(tx_data).await
     }
 
     async fn wrapper_ptb_with_pyth(
         &self,
+        role: Role,
         feed_ids: Vec<String>,
         update_fee: u64,
         other_objects: Vec<ObjectID>,
@@ -784,11 +800,11 @@ impl Tool {
         for id in feed_ids {
             price_info_object_ids.push(self.get_pyth_price_info_id(id.as_str()).await?);
         }
-        let total_pyth_fee = price_info_object_ids.len() as u64 * update_fee;
         debug!("update pyth price bat");
         let px = self
             .init_price_update_transaction(
-                total_pyth_fee,
+                role,
+                update_fee,
                 vaa_data,
                 price_info_object_ids,
                 other_objects,
@@ -803,12 +819,15 @@ impl Tool {
         let update_fee = args
             .get_one::<u64>("update_fee")
             .ok_or_else(|| ClientError::InvalidCliParams("update_fee".to_string()))?;
-        return self.update_pyth_price_by_ids(ids, *update_fee).await;
+        return self
+            .update_pyth_price_by_ids(Role::Default, ids, *update_fee)
+            .await
+            .map(print_outcome);
     }
 
     async fn _get_coin_value(&self) -> anyhow::Result<()> {
         let mut pt_builder = PTB::new();
-        let sender = self.ctx.get_active_address()?;
+        let sender = self.ctx.get_signer(Role::Default)?;
 
         let gas_price = self.ctx.client.read_api().get_reference_gas_price().await?;
         let mut gas_coins = self.get_gas(1000000000).await?;
@@ -845,9 +864,10 @@ impl Tool {
         Ok(())
     }
 
-    async fn get_price_inner(&self, symbol: &str) -> anyhow::Result<()> {
+    async fn get_price_inner(&self, role: Role, symbol: &str) -> anyhow::Result<TxOutcome> {
         let transaction_data = self
-            .get_transaction_data(
+            .get_role_transaction_data(
+                role,
                 self.ctx.config.scale_oracle_package_id,
                 ORACLE_MODULE_NAME,
                 "get_price",
@@ -860,14 +880,16 @@ impl Tool {
                 vec![],
             )
             .await?;
-        self.exec(transaction_data).await
+        self.send(transaction_data).await
     }
 
     pub async fn get_price(&self, args: &clap::ArgMatches) -> anyhow::Result<()> {
         let symbol = args
             .get_one::<String>("symbol")
             .ok_or_else(|| ClientError::InvalidCliParams("symbol".to_string()))?;
-        self.get_price_inner(symbol.as_str()).await
+        self.get_price_inner(Role::Default, symbol.as_str())
+            .await
+            .map(print_outcome)
     }
 
     pub async fn create_account(&self, _args: &clap::ArgMatches) -> anyhow::Result<()> {
@@ -1535,9 +1557,10 @@ impl Tool {
             .await?;
         self.exec(transaction_data).await
     }
-    pub async fn receive_award_inner(&self, nft: String) -> anyhow::Result<()> {
+    pub async fn receive_award_inner(&self, role: Role, nft: String) -> anyhow::Result<TxOutcome> {
         let transaction_data = self
-            .get_transaction_data(
+            .get_role_transaction_data(
+                role,
                 self.ctx.config.scale_package_id,
                 SCALE_MODULE_NAME,
                 "receive_award",
@@ -1550,17 +1573,20 @@ impl Tool {
                 vec![],
             )
             .await?;
-        self.exec(transaction_data).await
+        self.send(transaction_data).await
     }
     pub async fn receive_award(&self, args: &clap::ArgMatches) -> anyhow::Result<()> {
         let nft = args
             .get_one::<u64>("nft")
             .ok_or_else(|| ClientError::InvalidCliParams("nft".to_string()))?;
-        self.receive_award_inner(nft.to_string()).await
+        self.receive_award_inner(Role::Default, nft.to_string())
+            .await
+            .map(print_outcome)
     }
-    pub async fn receive_reward_inner(&self) -> anyhow::Result<()> {
+    pub async fn receive_reward_inner(&self, role: Role) -> anyhow::Result<TxOutcome> {
         let transaction_data = self
-            .get_transaction_data(
+            .get_role_transaction_data(
+                role,
                 self.ctx.config.scale_package_id,
                 SCALE_MODULE_NAME,
                 "receive_reward",
@@ -1571,11 +1597,13 @@ impl Tool {
                 vec![],
             )
             .await?;
-        self.exec(transaction_data).await
+        self.send(transaction_data).await
     }
 
     pub async fn receive_reward(&self, _args: &clap::ArgMatches) -> anyhow::Result<()> {
-        self.receive_reward_inner().await
+        self.receive_reward_inner(Role::Default)
+            .await
+            .map(print_outcome)
     }
 
     pub async fn investment(&self, args: &clap::ArgMatches) -> anyhow::Result<()> {
@@ -1635,14 +1663,19 @@ impl Tool {
             .await?;
         self.exec(transaction_data).await
     }
-    pub async fn trigger_update_opening_price_inner(&self, symbol: String) -> anyhow::Result<()> {
+    pub async fn trigger_update_opening_price_inner(
+        &self,
+        role: Role,
+        market_id: String,
+    ) -> anyhow::Result<TxOutcome> {
         let transaction_data = self
-            .get_transaction_data(
+            .get_role_transaction_data(
+                role,
                 self.ctx.config.scale_package_id,
                 SCALE_MODULE_NAME,
                 "trigger_update_opening_price",
                 vec![
-                    SuiJsonValue::from_object_id(ObjectID::from_str(symbol.as_str())?),
+                    SuiJsonValue::from_object_id(ObjectID::from_str(market_id.as_str())?),
                     SuiJsonValue::from_object_id(self.ctx.config.scale_market_list_id),
                     SuiJsonValue::from_object_id(self.ctx.config.scale_bot_id),
                     SuiJsonValue::from_object_id(self.ctx.config.scale_oracle_state_id),
@@ -1651,7 +1684,7 @@ impl Tool {
                 vec![self.get_t()],
             )
             .await?;
-        self.exec(transaction_data).await
+        self.send(transaction_data).await
     }
     pub async fn trigger_update_opening_price(
         &self,
@@ -1660,8 +1693,9 @@ impl Tool {
         let symbol = args
             .get_one::<String>("symbol")
             .ok_or_else(|| ClientError::InvalidCliParams("symbol".to_string()))?;
-        self.trigger_update_opening_price_inner(symbol.to_string())
+        self.trigger_update_opening_price_inner(Role::Default, symbol.to_string())
             .await
+            .map(print_outcome)
     }
 
     pub async fn generate_upgrade_move_token(&self, args: &clap::ArgMatches) -> anyhow::Result<()> {
@@ -1864,11 +1898,12 @@ impl Tool {
     }
     pub async fn auto_close_position_inner(
         &self,
+        role: Role,
         account: String,
         position: String,
         position_type: u8,
         mut symbol: Option<String>,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<TxOutcome> {
         if position_type != 2 {
             symbol = None;
         }
@@ -1883,7 +1918,7 @@ impl Tool {
         ];
         input_objects.reverse();
         let mut px = self
-            .wrapper_ptb_with_pyth(feed_ids, PYTH_PRICE_UPDATE_FEE, input_objects.clone())
+            .wrapper_ptb_with_pyth(role, feed_ids, PYTH_PRICE_UPDATE_FEE, input_objects.clone())
             .await?;
         let args = vec![
             px.get_object_input(input_objects.pop().unwrap(), false)?,
@@ -1901,7 +1936,7 @@ impl Tool {
             args,
         );
         let tx_data = PTBCtx::get_transaction_data(px).await?;
-        self.exec(tx_data).await
+        self.send(tx_data).await
     }
     pub async fn auto_close_position(&self, args: &clap::ArgMatches) -> anyhow::Result<()> {
         let account = args
@@ -1910,17 +1945,25 @@ impl Tool {
         let position = args
             .get_one::<String>("position")
             .ok_or_else(|| ClientError::InvalidCliParams("position".to_string()))?;
-        self.auto_close_position_inner(account.to_string(), position.to_string(), 0, None)
-            .await
+        self.auto_close_position_inner(
+            Role::Default,
+            account.to_string(),
+            position.to_string(),
+            0,
+            None,
+        )
+        .await
+        .map(print_outcome)
     }
 
     async fn force_liquidation_inner(
         &self,
+        role: Role,
         account: String,
         position: String,
         position_type: u8,
         mut symbol: Option<String>,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<TxOutcome> {
         if position_type != 2 {
             symbol = None;
         }
@@ -1935,7 +1978,7 @@ impl Tool {
         ];
         input_objects.reverse();
         let mut px = self
-            .wrapper_ptb_with_pyth(feed_ids, PYTH_PRICE_UPDATE_FEE, input_objects.clone())
+            .wrapper_ptb_with_pyth(role, feed_ids, PYTH_PRICE_UPDATE_FEE, input_objects.clone())
             .await?;
         let args = vec![
             px.get_object_input(input_objects.pop().unwrap(), false)?,
@@ -1953,7 +1996,7 @@ impl Tool {
             args,
         );
         let tx_data = PTBCtx::get_transaction_data(px).await?;
-        self.exec(tx_data).await
+        self.send(tx_data).await
     }
 
     pub async fn force_liquidation(&self, args: &clap::ArgMatches) -> anyhow::Result<()> {
@@ -1963,13 +2006,25 @@ impl Tool {
         let position = args
             .get_one::<String>("position")
             .ok_or_else(|| ClientError::InvalidCliParams("position".to_string()))?;
-        self.force_liquidation_inner(account.to_string(), position.to_string(), 0, None)
-            .await
+        self.force_liquidation_inner(
+            Role::Default,
+            account.to_string(),
+            position.to_string(),
+            0,
+            None,
+        )
+        .await
+        .map(print_outcome)
     }
 
-    async fn process_fund_fee_inner(&self, account: String) -> anyhow::Result<()> {
+    async fn process_fund_fee_inner(
+        &self,
+        role: Role,
+        account: String,
+    ) -> anyhow::Result<TxOutcome> {
         let transaction_data = self
-            .get_transaction_data(
+            .get_role_transaction_data(
+                role,
                 self.ctx.config.scale_package_id,
                 SCALE_MODULE_NAME,
                 "process_fund_fee",
@@ -1982,13 +2037,15 @@ impl Tool {
                 vec![self.get_t()],
             )
             .await?;
-        self.exec(transaction_data).await
+        self.send(transaction_data).await
     }
     pub async fn process_fund_fee(&self, args: &clap::ArgMatches) -> anyhow::Result<()> {
         let account = args
             .get_one::<String>("account")
             .ok_or_else(|| ClientError::InvalidCliParams("account".to_string()))?;
-        self.process_fund_fee_inner(account.clone()).await
+        self.process_fund_fee_inner(Role::Default, account.clone())
+            .await
+            .map(print_outcome)
     }
     pub async fn update_cross_limit_position(&self, args: &clap::ArgMatches) -> anyhow::Result<()> {
         let position = args
@@ -2073,11 +2130,12 @@ impl Tool {
     }
     async fn open_limit_position_inner(
         &self,
+        role: Role,
         account: String,
         position: String,
         position_type: u8,
         mut symbol: Option<String>,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<TxOutcome> {
         if position_type != 2 {
             symbol = None;
         }
@@ -2092,7 +2150,7 @@ impl Tool {
         ];
         input_objects.reverse();
         let mut px = self
-            .wrapper_ptb_with_pyth(feed_ids, PYTH_PRICE_UPDATE_FEE, input_objects.clone())
+            .wrapper_ptb_with_pyth(role, feed_ids, PYTH_PRICE_UPDATE_FEE, input_objects.clone())
             .await?;
         let args = vec![
             px.get_object_input(input_objects.pop().unwrap(), false)?,
@@ -2110,7 +2168,7 @@ impl Tool {
             args,
         );
         let tx_data = PTBCtx::get_transaction_data(px).await?;
-        self.exec(tx_data).await
+        self.send(tx_data).await
     }
 
     pub async fn open_limit_position(&self, args: &clap::ArgMatches) -> anyhow::Result<()> {
@@ -2120,8 +2178,15 @@ impl Tool {
         let position = args
             .get_one::<String>("position")
             .ok_or_else(|| ClientError::InvalidCliParams("position".to_string()))?;
-        self.open_limit_position_inner(account.to_string(), position.to_string(), 0, None)
-            .await
+        self.open_limit_position_inner(
+            Role::Default,
+            account.to_string(),
+            position.to_string(),
+            0,
+            None,
+        )
+        .await
+        .map(print_outcome)
     }
     pub async fn update_automatic_price(&self, args: &clap::ArgMatches) -> anyhow::Result<()> {
         let position = args
@@ -2190,16 +2255,18 @@ impl Tool {
 
 #[async_trait]
 impl MoveCall for Tool {
-    async fn trigger_update_opening_price(&self, symbol: String) -> anyhow::Result<()> {
-        self.trigger_update_opening_price_inner(symbol).await
+    async fn trigger_update_opening_price(&self, market_id: String) -> anyhow::Result<TxOutcome> {
+        self.trigger_update_opening_price_inner(Role::FundFee, market_id)
+            .await
     }
 
     async fn force_liquidation(
         &self,
         account_id: Address,
         position: PositionParams,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<TxOutcome> {
         self.force_liquidation_inner(
+            Role::Liquidator,
             account_id.to_string(),
             position.id.to_string(),
             position.position_type,
@@ -2208,16 +2275,18 @@ impl MoveCall for Tool {
         .await
     }
 
-    async fn process_fund_fee(&self, account_id: Address) -> anyhow::Result<()> {
-        self.process_fund_fee_inner(account_id.to_string()).await
+    async fn process_fund_fee(&self, account_id: Address) -> anyhow::Result<TxOutcome> {
+        self.process_fund_fee_inner(Role::FundFee, account_id.to_string())
+            .await
     }
 
     async fn auto_close_position(
         &self,
         account_id: Address,
         position: PositionParams,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<TxOutcome> {
         self.auto_close_position_inner(
+            Role::Liquidator,
             account_id.to_string(),
             position.id.to_string(),
             position.position_type,
@@ -2230,8 +2299,9 @@ impl MoveCall for Tool {
         &self,
         account_id: Address,
         position: PositionParams,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<TxOutcome> {
         self.open_limit_position_inner(
+            Role::Liquidator,
             account_id.to_string(),
             position.id.to_string(),
             position.position_type,
@@ -2239,13 +2309,51 @@ impl MoveCall for Tool {
         )
         .await
     }
-    async fn receive_award(&self, nft: String) -> anyhow::Result<()> {
-        self.receive_award_inner(nft).await
+    async fn receive_award(&self, nft: String) -> anyhow::Result<TxOutcome> {
+        self.receive_award_inner(Role::Default, nft).await
+    }
+    async fn receive_reward(&self) -> anyhow::Result<TxOutcome> {
+        self.receive_reward_inner(Role::Default).await
+    }
+    async fn get_price(&self, symbol: &str) -> anyhow::Result<TxOutcome> {
+        self.get_price_inner(Role::Oracle, symbol).await
+    }
+    async fn update_pyth_price(
+        &self,
+        feed_ids: Vec<String>,
+        update_fee: u64,
+    ) -> anyhow::Result<TxOutcome> {
+        self.update_pyth_price_by_ids(Role::Oracle, feed_ids, update_fee)
+            .await
     }
-    async fn receive_reward(&self) -> anyhow::Result<()> {
-        self.receive_reward_inner().await
+}
+
+fn print_outcome(outcome: TxOutcome) {
+    println!("exec: {}", outcome);
+    if !outcome.simulated {
+        return;
+    }
+    let objects = [
+        ("created", &outcome.created),
+        ("mutated", &outcome.mutated),
+        ("deleted", &outcome.deleted),
+    ];
+    for (change, ids) in objects {
+        for id in ids {
+            println!("  {} object: {}", change, id);
+        }
     }
-    async fn get_price(&self, symbol: &str) -> anyhow::Result<()> {
-        self.get_price_inner(symbol).await
+    for c in outcome.balance_changes.iter() {
+        println!(
+            "  balance change: {} {} of {}",
+            c.owner, c.amount, c.coin_type
+        );
     }
+    println!(
+        "  gas: computation {}, storage {}, rebate {}, net {}",
+        outcome.computation_cost,
+        outcome.storage_cost,
+        outcome.storage_rebate,
+        outcome.net_gas_cost()
+    );
 }